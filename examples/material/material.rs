@@ -0,0 +1,79 @@
+//! Shows the sunset project on a rotating cube as an emissive screen and on the
+//! ground as a lit surface.
+
+use bevy::prelude::*;
+
+use bevy_shadertoy_wgsl::*;
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(ClearColor(Color::BLACK))
+        .insert_resource(ShadertoyCanvas {
+            width: 512,
+            height: 512,
+            borders: 0.0,
+            position: Vec3::new(0.0, 0.0, 0.0),
+            active: true,
+        })
+        // the canvas is only shown on the meshes
+        .insert_resource(ShadertoyOutput::None)
+        .add_plugins(DefaultPlugins)
+        .add_plugin(ShadertoyPlugin)
+        .add_plugin(ShadertoyMaterialPlugin)
+        // MainImage is created by the startup system of ShadertoyPlugin
+        .add_startup_system_to_stage(StartupStage::PostStartup, setup)
+        .add_system(rotate)
+        .run();
+}
+
+#[derive(Component)]
+struct Rotating;
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    main_image: Res<MainImage>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ShadertoyMaterial>>,
+) {
+    commands.insert_resource(make_and_load_shaders2("sunset", &asset_server, false));
+
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+            material: materials.add(ShadertoyMaterial::new(main_image.0.clone())),
+            transform: Transform::from_xyz(0.0, 1.0, 0.0),
+            ..default()
+        },
+        Rotating,
+    ));
+
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Plane { size: 8.0 })),
+        material: materials.add(ShadertoyMaterial::lit(main_image.0.clone())),
+        ..default()
+    });
+
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 3000.0,
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(3.0, 4.0, 3.0),
+        ..default()
+    });
+
+    commands.spawn(Camera3dBundle {
+        transform: Transform::from_xyz(-3.0, 3.0, 5.0)
+            .looking_at(Vec3::new(0.0, 0.5, 0.0), Vec3::Y),
+        ..default()
+    });
+}
+
+fn rotate(time: Res<Time>, mut query: Query<&mut Transform, With<Rotating>>) {
+    for mut transform in query.iter_mut() {
+        transform.rotate_y(0.5 * time.delta_seconds());
+    }
+}
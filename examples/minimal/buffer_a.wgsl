@@ -1,6 +1,3 @@
-@compute @workgroup_size(8, 8, 1)
-fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
-    let color = vec4<f32>(0.5);
-    textureStore(buffer_a, location, color);
-}
\ No newline at end of file
+fn mainImage(fragCoord: vec2<f32>) -> vec4<f32> {
+    return vec4<f32>(0.5);
+}
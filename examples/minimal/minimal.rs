@@ -29,7 +29,9 @@ fn main() {
 			..default()
 		}))
         .add_plugin(ShadertoyPlugin)
+        .add_plugin(ShadertoyRecorderPlugin)
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(ShadertoyRebuildDiagnosticsPlugin)
         .add_plugin(LogDiagnosticsPlugin::default())
         .add_startup_system(setup)
         .run();
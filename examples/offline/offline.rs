@@ -0,0 +1,111 @@
+//! Renders a project without a window and writes every frame of the main image to disk.
+//!
+//! cargo run --release --example offline -- paint 1920 1080 300 png renders/paint
+//!
+//! The arguments are the project, the resolution, the number of frames, the format
+//! (png, png16 or exr) and the output directory. On machines without a GPU, a software
+//! adapter such as lavapipe can be used, WGPU_BACKEND=vulkan selects the backend.
+
+use bevy::{app::ScheduleRunnerPlugin, prelude::*, winit::WinitPlugin};
+
+use bevy_shadertoy_wgsl::*;
+
+use std::f32::consts::TAU;
+use std::path::PathBuf;
+
+struct Arguments {
+    project: String,
+    width: u32,
+    height: u32,
+    frame_count: u32,
+    format: OfflineImageFormat,
+    output_dir: PathBuf,
+}
+
+fn parse_arguments() -> Arguments {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let arg = |index: usize, default: &str| args.get(index).cloned().unwrap_or(default.to_string());
+
+    Arguments {
+        project: arg(0, "paint"),
+        width: arg(1, "960").parse().expect("width must be a number"),
+        height: arg(2, "600").parse().expect("height must be a number"),
+        frame_count: arg(3, "120").parse().expect("frame count must be a number"),
+        format: match arg(4, "png").as_str() {
+            "png16" => OfflineImageFormat::Png16,
+            "exr" => OfflineImageFormat::Exr,
+            _ => OfflineImageFormat::Png8,
+        },
+        output_dir: PathBuf::from(arg(5, "renders")),
+    }
+}
+
+// the mouse is held down and circles around the centre of the canvas
+fn circling_mouse(width: u32, height: u32, frame_count: u32) -> Vec<ScriptedMouse> {
+    let centre = Vec2::new(width as f32, height as f32) / 2.0;
+    let radius = centre.min_element() / 2.0;
+
+    (0..frame_count)
+        .map(|frame| {
+            let angle = TAU * frame as f32 / frame_count as f32;
+            ScriptedMouse {
+                frame,
+                position: centre + radius * Vec2::new(angle.cos(), angle.sin()),
+                pressed: true,
+            }
+        })
+        .collect()
+}
+
+#[derive(Resource)]
+struct Project(String);
+
+fn main() {
+    let arguments = parse_arguments();
+
+    App::new()
+        .insert_resource(ShadertoyCanvas {
+            width: arguments.width,
+            height: arguments.height,
+            borders: 0.0,
+            position: Vec3::new(0.0, 0.0, 0.0),
+            active: true,
+        })
+        .insert_resource(ShadertoyDeterministicMode {
+            dt: 1.0 / 60.0,
+            date: Vec4::new(2022.0, 11.0, 1.0, 0.0),
+            mouse_script: circling_mouse(arguments.width, arguments.height, arguments.frame_count),
+        })
+        .insert_resource(ShadertoyOfflineRender {
+            output_dir: arguments.output_dir,
+            format: arguments.format,
+            frame_count: arguments.frame_count,
+        })
+        .insert_resource(Project(arguments.project))
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    add_primary_window: false,
+                    exit_on_all_closed: false,
+                    ..default()
+                })
+                .disable::<WinitPlugin>(),
+        )
+        .add_plugin(ScheduleRunnerPlugin)
+        .add_plugin(ShadertoyPlugin)
+        .add_plugin(ShadertoyOfflineRenderPlugin)
+        .add_startup_system(setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    project: Res<Project>,
+    st_res: Res<ShadertoyResources>,
+) {
+    let all_shader_handles: ShaderHandles =
+        make_and_load_shaders2(&project.0, &asset_server, st_res.include_debugger);
+
+    commands.insert_resource(all_shader_handles);
+}
@@ -0,0 +1,39 @@
+// moves the particles, this pass only writes the storage buffer and is therefore
+// dispatched once per particle
+
+@compute @workgroup_size(64, 1, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let index = invocation_id.x;
+    let R: vec2<f32> = uni.iResolution.xy;
+
+    #ifdef INIT
+        let angle = hash(index * 2u) * 6.28318;
+        particles[index].position = R * vec2<f32>(hash(index * 2u + 1u), hash(index * 3u + 7u));
+        particles[index].velocity = vec2<f32>(cos(angle), sin(angle)) * 100.0;
+        particles[index].color = vec4<f32>(
+            0.5 + 0.5 * cos(6.28318 * (f32(index) / 4096.0 + vec3<f32>(0.0, 0.33, 0.67))),
+            1.0,
+        );
+    #else
+        var particle = particles[index];
+
+        // pulled towards the mouse while a button is held
+        if (uni.iMouse.z > 0.0) {
+            let to_mouse = uni.iMouse.xy - particle.position;
+            particle.velocity += normalize(to_mouse) * 400.0 * uni.iTimeDelta;
+        }
+
+        particle.position += particle.velocity * uni.iTimeDelta;
+
+        // bounce off the borders of the canvas
+        if (particle.position.x < 0.0 || particle.position.x > R.x) {
+            particle.velocity.x = -particle.velocity.x;
+        }
+        if (particle.position.y < 0.0 || particle.position.y > R.y) {
+            particle.velocity.y = -particle.velocity.y;
+        }
+        particle.position = clamp(particle.position, vec2<f32>(0.0), R);
+
+        particles[index] = particle;
+    #endif
+}
@@ -0,0 +1,16 @@
+// draws every particle as a glowing quad, one instance per element of the storage buffer
+
+fn drawVertex(instance: u32, corner: vec2<f32>) -> DrawVertex {
+    let particle = particles[instance];
+
+    var particle_vertex: DrawVertex;
+    particle_vertex.position = pixelToClip(particle.position + corner * 8.0);
+    particle_vertex.color = particle.color;
+    particle_vertex.corner = corner;
+    return particle_vertex;
+}
+
+fn drawFragment(draw_vertex: DrawVertex) -> vec4<f32> {
+    let glow = max(1.0 - length(draw_vertex.corner), 0.0);
+    return vec4<f32>(draw_vertex.color.rgb, glow * glow);
+}
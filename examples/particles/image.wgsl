@@ -0,0 +1,7 @@
+// the background, draw.wgsl draws the particles over it
+
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    textureStore(texture, location, vec4<f32>(vec3<f32>(0.02), 1.0));
+}
@@ -0,0 +1,7 @@
+struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+    color: vec4<f32>,
+};
+
+var<storage, read_write> particles: array<Particle, 4096>;
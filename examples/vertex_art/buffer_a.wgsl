@@ -0,0 +1,13 @@
+// the vertex art pass draws into this buffer, which fades into trails
+
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+
+    #ifdef INIT
+        textureStore(buffer_a, location, vec4<f32>(0.0, 0.0, 0.0, 1.0));
+    #else
+        let previous = textureLoad(buffer_a, location);
+        textureStore(buffer_a, location, vec4<f32>(previous.rgb * 0.94, 1.0));
+    #endif
+}
@@ -0,0 +1,3 @@
+fn palette(t: f32) -> vec3<f32> {
+    return 0.5 + 0.5 * cos(6.28318 * (t + vec3<f32>(0.0, 0.33, 0.67)));
+}
@@ -0,0 +1,8 @@
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    let color = textureLoad(buffer_a, location).rgb;
+
+    // tone maps the lines that add up
+    textureStore(texture, location, vec4<f32>(color / (1.0 + color), 1.0));
+}
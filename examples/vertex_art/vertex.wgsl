@@ -0,0 +1,19 @@
+// a spiral of lines around a breathing flower, as on vertexshaderart.com
+
+fn mainVertex(vertexId: f32, vertexCount: f32) -> DrawVertex {
+    let R: vec2<f32> = uni.iResolution.xy;
+
+    // each pair of vertices is a line from the previous point of the curve to the next one
+    let point_id = floor(vertexId / 2.0) + vertexId % 2.0;
+    let t = point_id / (vertexCount * 0.5);
+
+    let angle = t * 6.28318 * 40.0 + uni.iTime * 0.3;
+    let petals = 0.6 + 0.4 * sin(angle * 0.125 * 7.0 + uni.iTime);
+    let radius = t * petals * min(R.x, R.y) * 0.45;
+    let position = R * 0.5 + vec2<f32>(cos(angle), sin(angle)) * radius;
+
+    var line_vertex: DrawVertex;
+    line_vertex.position = pixelToClip(position);
+    line_vertex.color = vec4<f32>(palette(t + uni.iTime * 0.05), 0.5);
+    return line_vertex;
+}
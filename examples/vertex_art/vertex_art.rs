@@ -0,0 +1,57 @@
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+};
+
+use bevy_shadertoy_wgsl::*;
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(ClearColor(Color::GRAY))
+        .insert_resource(ShadertoyCanvas {
+            width: 960. as u32,
+            height: 600.0 as u32,
+            borders: 0.0,
+            position: Vec3::new(0.0, 0.0, 0.0),
+			active: true
+        })
+        // lines drawn into buffer A, see vertex.wgsl
+        .insert_resource(ShadertoyVertexArt {
+            vertex_count: 20000,
+            primitive: VertexArtPrimitive::Lines,
+            target: CaptureTarget::BufferA,
+            blend: DrawBlend::Additive,
+        })
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+			window: WindowDescriptor {
+				width: 960.,
+				height: 600.,
+				cursor_visible: true,
+				monitor: MonitorSelection::Primary,
+				position: WindowPosition::Centered,
+				// present_mode: PresentMode::Immediate, // uncomment for unthrottled FPS
+				..default()
+			},
+			..default()
+		}))
+        .add_plugin(ShadertoyPlugin)
+        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(LogDiagnosticsPlugin::default())
+        .add_startup_system(setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut st_res: ResMut<ShadertoyResources>,
+) {
+    let example = "vertex_art";
+    st_res.include_debugger = false;
+
+    let all_shader_handles: ShaderHandles =
+        make_and_load_shaders2(example, &asset_server, st_res.include_debugger);
+
+    commands.insert_resource(all_shader_handles);
+}
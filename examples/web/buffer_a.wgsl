@@ -0,0 +1,13 @@
+// paints with the mouse, the strokes fade out over time
+
+fn mainImage(fragCoord: vec2<f32>) -> vec4<f32> {
+    var color = load_buffer_a(vec2<i32>(fragCoord)) * 0.98;
+
+    if (uni.iMouse.z > 0.0) {
+        let d = length(fragCoord - uni.iMouse.xy);
+        let brush = smoothstep(20.0, 10.0, d);
+        color = mix(color, vec4<f32>(palette(uni.iTime * 0.1), 1.0), brush);
+    }
+
+    return color;
+}
@@ -0,0 +1,7 @@
+fn mainImage(fragCoord: vec2<f32>) -> vec4<f32> {
+    let uv = fragCoord / uni.iResolution.xy;
+    let background = palette(uv.x * 0.3 + uni.iTime * 0.05) * 0.2;
+    let paint = load_buffer_a(vec2<i32>(fragCoord));
+
+    return vec4<f32>(mix(background, paint.rgb, paint.a), 1.0);
+}
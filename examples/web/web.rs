@@ -0,0 +1,55 @@
+// Runs a project in the browser, on the fragment backend. Build it with
+//
+//     RUSTFLAGS=--cfg=web_sys_unstable_apis cargo build --release --example web \
+//         --target wasm32-unknown-unknown --no-default-features --features wasm
+//     wasm-bindgen --out-name web --out-dir examples/web/wasm --target web \
+//         target/wasm32-unknown-unknown/release/examples/web.wasm
+//
+// and serve the crate root, e.g. with `python3 -m http.server`, then open
+// /examples/web/index.html in a browser with WebGPU enabled.
+
+use bevy::prelude::*;
+
+use bevy_shadertoy_wgsl::*;
+
+fn main() {
+    let mut app = App::new();
+
+    app.insert_resource(ClearColor(Color::GRAY))
+        .insert_resource(ShadertoyCanvas {
+            width: 960. as u32,
+            height: 600.0 as u32,
+            borders: 0.0,
+            position: Vec3::new(0.0, 0.0, 0.0),
+            active: true,
+        })
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    window: WindowDescriptor {
+                        width: 960.,
+                        height: 600.,
+                        canvas: Some("#shadertoy".to_string()),
+                        ..default()
+                    },
+                    ..default()
+                })
+                // fetched relative to index.html, for the textures of the image pass
+                .set(AssetPlugin {
+                    asset_folder: "../../assets".to_string(),
+                    ..default()
+                }),
+        )
+        .add_plugin(ShadertoyPlugin)
+        .add_startup_system(setup)
+        .run();
+}
+
+fn setup(mut commands: Commands, mut projects: ResMut<Assets<ShadertoyProject>>) {
+    commands.spawn(Camera2dBundle::default());
+
+    // the project is compiled into the wasm file. It can be fetched instead with
+    // asset_server.load("shadertoy/<name>/project.shadertoy").
+    let project = projects.add(include_shadertoy_project!("."));
+    commands.insert_resource(ShadertoyActiveProject::new(project));
+}
@@ -0,0 +1,150 @@
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+    render::render_resource::*,
+};
+
+use std::borrow::Cow;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+// What the init and update pipelines of a pass are built from. The pipeline ids are
+// only queued again when one of these changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineKey {
+    pub shader: Handle<Shader>,
+    pub layout: BindGroupLayoutId,
+    pub storage_layout: Option<BindGroupLayoutId>,
+}
+
+impl PipelineKey {
+    pub fn new(shader: &Handle<Shader>, layout: &BindGroupLayout) -> Self {
+        Self {
+            shader: shader.clone_weak(),
+            layout: layout.id(),
+            storage_layout: None,
+        }
+    }
+
+    // for the passes that also bind the storage buffers of the project
+    pub fn with_storage(mut self, storage_layout: &BindGroupLayout) -> Self {
+        self.storage_layout = Some(storage_layout.id());
+        self
+    }
+}
+
+// What the bind group of a pass is built from. A resized or reloaded image gets a new
+// texture, so comparing the texture ids catches both.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BindGroupKey {
+    pub layout: BindGroupLayoutId,
+    pub textures: Vec<TextureId>,
+}
+
+impl BindGroupKey {
+    pub fn new(layout: &BindGroupLayout, textures: &[&Texture]) -> Self {
+        Self {
+            layout: layout.id(),
+            textures: textures.iter().map(|texture| texture.id()).collect(),
+        }
+    }
+}
+
+// Counts the rebuilds done in the render world. The counters are shared with the main
+// world, where ShadertoyRebuildDiagnosticsPlugin reads them.
+#[derive(Clone, Default, Resource)]
+pub struct ShadertoyRebuildCounters {
+    pub pipelines: Arc<AtomicU64>,
+    pub bind_groups: Arc<AtomicU64>,
+}
+
+impl ShadertoyRebuildCounters {
+    pub fn count_pipeline_rebuild(&self) {
+        self.pipelines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_bind_group_rebuild(&self) {
+        self.bind_groups.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// queues the INIT and regular variants of the "update" entry point of a pass, the storage
+// buffers are bound at group 1
+pub fn queue_pass_pipelines(
+    pipeline_cache: &mut PipelineCache,
+    layout: &BindGroupLayout,
+    storage_layout: Option<&BindGroupLayout>,
+    shader: &Handle<Shader>,
+) -> (CachedComputePipelineId, CachedComputePipelineId) {
+    let layout: Vec<BindGroupLayout> = [Some(layout), storage_layout]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+
+    let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: Some(layout.clone()),
+        shader: shader.clone(),
+        shader_defs: vec!["INIT".to_string()],
+        entry_point: Cow::from("update"),
+    });
+
+    let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        shader: shader.clone(),
+        shader_defs: vec![],
+        entry_point: Cow::from("update"),
+    });
+
+    (init_pipeline, update_pipeline)
+}
+
+// Adds the number of pipeline and bind group rebuilds per frame to the diagnostics.
+// Use with LogDiagnosticsPlugin to print them.
+#[derive(Default)]
+pub struct ShadertoyRebuildDiagnosticsPlugin;
+
+impl ShadertoyRebuildDiagnosticsPlugin {
+    pub const PIPELINE_REBUILDS: DiagnosticId =
+        DiagnosticId::from_u128(0x3e1c_6a0d_52f4_4b8e_9d27_c410_8fa3_1b65);
+    pub const BIND_GROUP_REBUILDS: DiagnosticId =
+        DiagnosticId::from_u128(0x9b74_0e2f_c83a_4d16_a5e1_27bd_60c9_f842);
+
+    fn setup_system(mut diagnostics: ResMut<Diagnostics>) {
+        diagnostics.add(Diagnostic::new(
+            Self::PIPELINE_REBUILDS,
+            "shadertoy_pipeline_rebuilds",
+            20,
+        ));
+        diagnostics.add(Diagnostic::new(
+            Self::BIND_GROUP_REBUILDS,
+            "shadertoy_bind_group_rebuilds",
+            20,
+        ));
+    }
+
+    fn diagnostic_system(
+        mut diagnostics: ResMut<Diagnostics>,
+        counters: Res<ShadertoyRebuildCounters>,
+    ) {
+        diagnostics.add_measurement(
+            Self::PIPELINE_REBUILDS,
+            counters.pipelines.swap(0, Ordering::Relaxed) as f64,
+        );
+        diagnostics.add_measurement(
+            Self::BIND_GROUP_REBUILDS,
+            counters.bind_groups.swap(0, Ordering::Relaxed) as f64,
+        );
+    }
+}
+
+impl Plugin for ShadertoyRebuildDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(Self::setup_system)
+            .add_system(Self::diagnostic_system);
+    }
+}
@@ -0,0 +1,445 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        render_asset::RenderAssets,
+        render_graph,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        texture::GpuImage,
+        Extract,
+    },
+};
+
+use std::borrow::Cow;
+
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::{
+    pass_name, ActivePasses, CaptureTarget, MainImage, PassUsages, ShaderHandles, ShadertoyCanvas,
+    WorkgroupSize,
+};
+
+pub const CAMERA_INPUT_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x41c8_9e0d_b7a3_2f65);
+
+// Feeds what a camera renders into one of the buffers, so that the passes can work on a
+// game scene, e.g. for CRT, bloom or paint effects. Render the camera into an image made
+// by camera_input_image with RenderTarget::Image, then add the input to
+// ShadertoyCameraInputs. The buffer is written after the resample and upload nodes and
+// before any pass runs. Inputs into the main image, or into a buffer that an active pass
+// writes, are rejected with an error, as the passes would overwrite them.
+// The cameras render after the passes, so the passes see the scene of the previous frame.
+#[derive(Clone, Debug)]
+pub struct ShadertoyCameraInput {
+    pub image: Handle<Image>,
+    pub buffer: CaptureTarget,
+    // resizes the image with the canvas, so that the camera renders at the resolution of
+    // the buffer. Otherwise the image is stretched to the size of the buffer.
+    pub follow_canvas_size: bool,
+}
+
+impl ShadertoyCameraInput {
+    pub fn new(image: Handle<Image>, buffer: CaptureTarget) -> Self {
+        Self {
+            image,
+            buffer,
+            follow_canvas_size: true,
+        }
+    }
+}
+
+// The camera inputs, one per camera. Each input takes the place of one of the four buffers
+// rather than being bound to the passes as a texture of its own, which leaves the layouts
+// of the passes and the shaders as they are on Shadertoy, but leaves fewer buffers to the
+// project. Two inputs into the same buffer overwrite each other.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct ShadertoyCameraInputs(pub Vec<ShadertoyCameraInput>);
+
+// why the input cannot go into the buffer, if a pass that runs writes it
+fn camera_input_conflict(
+    buffer: CaptureTarget,
+    passes: Option<(&ActivePasses, &PassUsages)>,
+) -> Option<String> {
+    let index = match CaptureTarget::BUFFERS
+        .iter()
+        .position(|target| *target == buffer)
+    {
+        Some(index) => index,
+        None => return Some("the image pass writes the main image".to_string()),
+    };
+
+    let (active_passes, pass_usages) = passes?;
+    let active = active_passes.as_array();
+    (0..5)
+        .find(|&pass| (pass == 4 || active[pass]) && pass_usages.get(pass).writes[index])
+        .map(|pass| format!("the {} pass writes it", pass_name(pass)))
+}
+
+// drops the inputs that the passes would overwrite, checked again for every new project
+pub fn reject_camera_inputs(
+    mut inputs: ResMut<ShadertoyCameraInputs>,
+    shader_handles: Option<Res<ShaderHandles>>,
+) {
+    let project_changed = shader_handles
+        .as_ref()
+        .map_or(false, |handles| handles.is_changed());
+    if !inputs.is_changed() && !project_changed {
+        return;
+    }
+
+    let passes = shader_handles
+        .as_ref()
+        .map(|handles| (&handles.active_passes, &handles.pass_usages));
+    let conflicts = inputs
+        .0
+        .iter()
+        .map(|input| camera_input_conflict(input.buffer, passes))
+        .collect::<Vec<_>>();
+    if conflicts.iter().all(Option::is_none) {
+        return;
+    }
+
+    let mut conflicts = conflicts.into_iter();
+    inputs.0.retain(|input| match conflicts.next().flatten() {
+        Some(reason) => {
+            error!(
+                "camera input into {} rejected: {}",
+                input.buffer.name(),
+                reason
+            );
+            false
+        }
+        None => true,
+    });
+}
+
+// An image a camera can render into and a pass can read, in the format of the window
+pub fn camera_input_image(width: u32, height: u32) -> Image {
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("camera_input"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..default()
+    };
+    // fills the image with zeros
+    image.resize(size);
+
+    image
+}
+
+pub fn resize_camera_input(
+    inputs: Res<ShadertoyCameraInputs>,
+    canvas: Res<ShadertoyCanvas>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let canvas_size = Vec2::new(canvas.width as f32, canvas.height as f32);
+
+    for input in inputs.0.iter().filter(|input| input.follow_canvas_size) {
+        // only borrowed mutably on a change, as that makes the image be prepared again
+        if images.get(&input.image).map(|image| image.size()) == Some(canvas_size) {
+            continue;
+        }
+
+        if let Some(image) = images.get_mut(&input.image) {
+            image.resize(Extent3d {
+                width: canvas.width,
+                height: canvas.height,
+                depth_or_array_layers: 1,
+            });
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct CameraInputPipeline {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for CameraInputPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("camera_input_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba32Float,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("camera_input_sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        let pipeline = world
+            .resource_mut::<PipelineCache>()
+            .queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from("camera_input_pipeline")),
+                layout: Some(vec![layout.clone()]),
+                shader: CAMERA_INPUT_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: Cow::from("copy_camera"),
+            });
+
+        CameraInputPipeline {
+            layout,
+            sampler,
+            pipeline,
+        }
+    }
+}
+
+impl CameraInputPipeline {
+    // copies any sampled texture into a buffer, used by the post-process node for the view
+    pub(crate) fn copy(
+        &self,
+        render_context: &mut RenderContext,
+        pipeline_cache: &PipelineCache,
+        source: &TextureView,
+        buffer: &GpuImage,
+    ) {
+        let pipeline = match pipeline_cache.get_compute_pipeline(self.pipeline) {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+
+        let bind_group = render_context
+            .render_device
+            .create_bind_group(&BindGroupDescriptor {
+                label: Some("camera_input_bind_group"),
+                layout: &self.layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(source),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&self.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(&buffer.texture_view),
+                    },
+                ],
+            });
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor {
+                label: Some("camera_input_compute_pass"),
+            });
+
+        let (workgroups_x, workgroups_y) = WorkgroupSize::SIZE_8X8
+            .dispatch_count(buffer.size.x as u32, buffer.size.y as u32);
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+    }
+}
+
+// the camera targets and the buffers they are copied into
+#[derive(Resource, Default)]
+pub struct ExtractedCameraInput(Vec<(Handle<Image>, Handle<Image>)>);
+
+pub fn extract_camera_input(
+    mut commands: Commands,
+    inputs: Extract<Res<ShadertoyCameraInputs>>,
+    main_image: Extract<Res<MainImage>>,
+    texture_a: Extract<Res<TextureA>>,
+    texture_b: Extract<Res<TextureB>>,
+    texture_c: Extract<Res<TextureC>>,
+    texture_d: Extract<Res<TextureD>>,
+) {
+    let buffers = [&texture_a.0, &texture_b.0, &texture_c.0, &texture_d.0];
+    let extracted = inputs
+        .0
+        .iter()
+        .map(|input| {
+            (
+                input.image.clone(),
+                input.buffer.image(&main_image.0, buffers).clone(),
+            )
+        })
+        .collect();
+
+    commands.insert_resource(ExtractedCameraInput(extracted));
+}
+
+pub struct CameraInputJob {
+    bind_group: BindGroup,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Resource, Default)]
+pub struct CameraInputJobs(Vec<CameraInputJob>);
+
+// the bind group is made every frame, as both textures change when they are resized
+pub fn queue_camera_input(
+    input: Res<ExtractedCameraInput>,
+    pipeline: Res<CameraInputPipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    mut jobs: ResMut<CameraInputJobs>,
+) {
+    jobs.0 = input
+        .0
+        .iter()
+        .filter_map(|(camera, buffer)| {
+            let camera = gpu_images.get(camera)?;
+            let buffer = gpu_images.get(buffer)?;
+
+            let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+                label: Some("camera_input_bind_group"),
+                layout: &pipeline.layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&camera.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&pipeline.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(&buffer.texture_view),
+                    },
+                ],
+            });
+
+            Some(CameraInputJob {
+                bind_group,
+                width: buffer.size.x as u32,
+                height: buffer.size.y as u32,
+            })
+        })
+        .collect();
+}
+
+// copies the camera targets into their buffers before any pass runs
+pub struct CameraInputNode;
+
+impl render_graph::Node for CameraInputNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let jobs = &world.resource::<CameraInputJobs>().0;
+        if jobs.is_empty() {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<CameraInputPipeline>().pipeline;
+
+        if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipeline) {
+            let mut pass = render_context
+                .command_encoder
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("camera_input_compute_pass"),
+                });
+            pass.set_pipeline(pipeline);
+
+            for job in jobs.iter() {
+                let (workgroups_x, workgroups_y) =
+                    WorkgroupSize::SIZE_8X8.dispatch_count(job.width, job.height);
+
+                pass.set_bind_group(0, &job.bind_group, &[]);
+                pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::BufferUsage;
+
+    fn writing(buffers: [bool; 4]) -> BufferUsage {
+        BufferUsage {
+            writes: buffers,
+            ..BufferUsage::default()
+        }
+    }
+
+    #[test]
+    fn rejects_the_main_image() {
+        assert!(camera_input_conflict(CaptureTarget::MainImage, None).is_some());
+        assert!(camera_input_conflict(CaptureTarget::BufferA, None).is_none());
+    }
+
+    #[test]
+    fn rejects_buffers_written_by_passes_that_run() {
+        let usages = PassUsages {
+            image: writing([false, false, false, true]),
+            buffers: [
+                writing([true, false, false, false]),
+                writing([false, true, false, false]),
+                BufferUsage::default(),
+                BufferUsage::default(),
+            ],
+        };
+        let active = ActivePasses::from_array([true, false, false, false]);
+        let conflict = |buffer| camera_input_conflict(buffer, Some((&active, &usages)));
+
+        assert!(conflict(CaptureTarget::BufferA).is_some());
+        // the pass of buffer b is inactive
+        assert!(conflict(CaptureTarget::BufferB).is_none());
+        assert!(conflict(CaptureTarget::BufferC).is_none());
+        assert!(conflict(CaptureTarget::BufferD).is_some());
+    }
+}
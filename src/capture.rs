@@ -0,0 +1,527 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        Extract,
+    },
+    utils::HashMap,
+};
+
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::{MainImage, ShadertoyClock};
+
+// rows of a buffer a texture is copied into must be aligned to this, as wgpu requires
+const COPY_ROW_ALIGNMENT: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CaptureTarget {
+    MainImage,
+    BufferA,
+    BufferB,
+    BufferC,
+    BufferD,
+}
+
+impl CaptureTarget {
+    pub const BUFFERS: [CaptureTarget; 4] = [
+        CaptureTarget::BufferA,
+        CaptureTarget::BufferB,
+        CaptureTarget::BufferC,
+        CaptureTarget::BufferD,
+    ];
+
+    // as in the file names of the shaders
+    pub fn name(&self) -> &'static str {
+        match self {
+            CaptureTarget::MainImage => "image",
+            CaptureTarget::BufferA => "buffer_a",
+            CaptureTarget::BufferB => "buffer_b",
+            CaptureTarget::BufferC => "buffer_c",
+            CaptureTarget::BufferD => "buffer_d",
+        }
+    }
+
+    // with the buffers ordered as in BUFFERS
+    pub(crate) fn image<'a>(
+        &self,
+        main_image: &'a Handle<Image>,
+        buffers: [&'a Handle<Image>; 4],
+    ) -> &'a Handle<Image> {
+        match self {
+            CaptureTarget::MainImage => main_image,
+            CaptureTarget::BufferA => buffers[0],
+            CaptureTarget::BufferB => buffers[1],
+            CaptureTarget::BufferC => buffers[2],
+            CaptureTarget::BufferD => buffers[3],
+        }
+    }
+}
+
+// in texels, with the origin at the top left corner of the canvas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureRegion {
+    pub origin: UVec2,
+    pub size: UVec2,
+}
+
+impl TextureRegion {
+    pub fn texel(position: UVec2) -> Self {
+        Self {
+            origin: position,
+            size: UVec2::ONE,
+        }
+    }
+
+    pub fn contains(&self, other: &TextureRegion) -> bool {
+        other.origin.cmpge(self.origin).all()
+            && (other.origin + other.size).cmple(self.origin + self.size).all()
+    }
+
+    // the part of the region inside a texture of the given size
+    pub(crate) fn clamp(&self, texture_size: UVec2) -> Self {
+        let origin = self.origin.min(texture_size);
+        let end = (self.origin + self.size).min(texture_size);
+        Self {
+            origin,
+            size: end - origin,
+        }
+    }
+}
+
+// The buffers are rgba32float, and so is the main image except on the web, where it is
+// rgba16float, see MAIN_IMAGE_FORMAT
+fn bytes_per_texel(format: TextureFormat) -> u32 {
+    match format {
+        TextureFormat::Rgba16Float => 8,
+        _ => 16,
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = match bits & 0x8000 {
+        0 => 1.0,
+        _ => -1.0,
+    };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// size of the rows of a buffer that a texture of the given width is copied into
+pub(crate) fn padded_bytes_per_row(width: u32, format: TextureFormat) -> u32 {
+    let unpadded_bytes_per_row = width * bytes_per_texel(format);
+    (unpadded_bytes_per_row + COPY_ROW_ALIGNMENT - 1) / COPY_ROW_ALIGNMENT * COPY_ROW_ALIGNMENT
+}
+
+// the texels of a mapped buffer as f32, without the padding at the end of the rows
+pub(crate) fn unpad_rows(
+    data: &[u8],
+    width: u32,
+    padded_bytes_per_row: u32,
+    format: TextureFormat,
+) -> Vec<f32> {
+    let row_bytes = (width * bytes_per_texel(format)) as usize;
+    let rows = data
+        .chunks(padded_bytes_per_row as usize)
+        .map(|row| &row[..row_bytes]);
+
+    match format {
+        TextureFormat::Rgba16Float => rows
+            .flat_map(|row| row.chunks_exact(2))
+            .map(|value| f16_to_f32(u16::from_le_bytes([value[0], value[1]])))
+            .collect(),
+        _ => rows
+            .flat_map(|row| row.chunks_exact(4))
+            .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+            .collect(),
+    }
+}
+
+// The content of a texture in one frame, as linear rgba floats with the first row at
+// the top of the canvas
+pub struct CapturedFrame {
+    pub target: CaptureTarget,
+    // iFrame and iTime of the frame the texture was captured in
+    pub frame: u32,
+    pub time: f32,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<f32>,
+}
+
+impl CapturedFrame {
+    // 8 bit sRGB, as shown in the window
+    pub fn to_srgb8(&self) -> Vec<u8> {
+        encode_srgb(&self.pixels, u8::MAX as f32)
+            .map(|value| value as u8)
+            .collect()
+    }
+
+    pub fn to_srgb16(&self) -> Vec<u16> {
+        encode_srgb(&self.pixels, u16::MAX as f32)
+            .map(|value| value as u16)
+            .collect()
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// the color channels are encoded to sRGB, alpha stays linear
+fn encode_srgb(pixels: &[f32], max: f32) -> impl Iterator<Item = f32> + '_ {
+    pixels.iter().enumerate().map(move |(index, value)| {
+        let encoded = match index % 4 {
+            3 => value.clamp(0.0, 1.0),
+            _ => linear_to_srgb(*value),
+        };
+        (encoded * max).round()
+    })
+}
+
+// Textures to read back after the frame has been rendered. It is cleared at the start of
+// every frame, so consumers request the targets every frame they need them.
+#[derive(Clone, Resource, Default, Debug)]
+pub struct CaptureRequests(Vec<CaptureTarget>);
+
+impl CaptureRequests {
+    pub fn request(&mut self, target: CaptureTarget) {
+        if !self.0.contains(&target) {
+            self.0.push(target);
+        }
+    }
+
+    pub fn is_requested(&self, target: CaptureTarget) -> bool {
+        self.0.contains(&target)
+    }
+}
+
+// The frames read back from the render world. They arrive once the gpu has copied them,
+// usually one or two frames after they were requested, with the targets requested in the
+// same frame together, and stay here until the next frame.
+#[derive(Resource, Default)]
+pub struct CapturedFrames(pub Vec<Arc<CapturedFrame>>);
+
+impl CapturedFrames {
+    pub fn of(&self, target: CaptureTarget) -> impl Iterator<Item = &Arc<CapturedFrame>> {
+        self.0.iter().filter(move |frame| frame.target == target)
+    }
+}
+
+// Shared by the main and the render world
+#[derive(Clone, Resource, Default)]
+pub struct CaptureQueue(Arc<Mutex<Vec<CapturedFrame>>>);
+
+pub fn receive_captured_frames(
+    queue: Res<CaptureQueue>,
+    mut captured: ResMut<CapturedFrames>,
+    mut requests: ResMut<CaptureRequests>,
+) {
+    requests.0.clear();
+    captured.0 = queue.0.lock().unwrap().drain(..).map(Arc::new).collect();
+}
+
+// the requested targets with their images, in the render world
+#[derive(Resource, Default)]
+pub struct ExtractedCaptureRequests(Vec<(CaptureTarget, Handle<Image>)>);
+
+pub fn extract_capture_requests(
+    mut commands: Commands,
+    requests: Extract<Res<CaptureRequests>>,
+    main_image: Extract<Res<MainImage>>,
+    texture_a: Extract<Res<TextureA>>,
+    texture_b: Extract<Res<TextureB>>,
+    texture_c: Extract<Res<TextureC>>,
+    texture_d: Extract<Res<TextureD>>,
+) {
+    let buffers = [&texture_a.0, &texture_b.0, &texture_c.0, &texture_d.0];
+    let requests = requests
+        .0
+        .iter()
+        .map(|target| (*target, target.image(&main_image.0, buffers).clone()))
+        .collect();
+
+    commands.insert_resource(ExtractedCaptureRequests(requests));
+}
+
+// Maps a buffer for reading once the copies into it are done. The callback runs in a later
+// poll of the device, so the frame does not wait for the gpu.
+pub(crate) struct MapRequest(Arc<Mutex<Option<bool>>>);
+
+impl MapRequest {
+    // the buffer must not be used by the frames that follow until it is unmapped
+    pub(crate) fn start(buffer: &Buffer) -> Self {
+        let result = Arc::new(Mutex::new(None));
+        let callback_result = result.clone();
+        buffer.slice(..).map_async(MapMode::Read, move |mapped| {
+            *callback_result.lock().unwrap() = Some(mapped.is_ok());
+        });
+        Self(result)
+    }
+
+    // None while the gpu is still copying, then whether the buffer could be mapped
+    pub(crate) fn result(&self) -> Option<bool> {
+        *self.0.lock().unwrap()
+    }
+}
+
+struct CaptureBuffer {
+    buffer: Buffer,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    padded_bytes_per_row: u32,
+}
+
+// the captures of one frame, handed to the main world together once all are mapped
+struct PendingCaptures {
+    frame: u32,
+    time: f32,
+    buffers: Vec<(CaptureTarget, CaptureBuffer, MapRequest)>,
+}
+
+// The buffers of the current frame, those still being mapped, and the mapped ones that
+// were read and can be reused for targets of the same size
+#[derive(Resource, Default)]
+pub struct CaptureBuffers {
+    current: HashMap<CaptureTarget, CaptureBuffer>,
+    pending: Vec<PendingCaptures>,
+    free: HashMap<CaptureTarget, Vec<CaptureBuffer>>,
+}
+
+pub fn prepare_capture_buffers(
+    requests: Res<ExtractedCaptureRequests>,
+    mut capture_buffers: ResMut<CaptureBuffers>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+) {
+    let capture_buffers = &mut *capture_buffers;
+
+    for (target, image) in requests.0.iter() {
+        let gpu_image = match gpu_images.get(image) {
+            Some(gpu_image) => gpu_image,
+            None => continue,
+        };
+        let (width, height) = (gpu_image.size.x as u32, gpu_image.size.y as u32);
+        let format = gpu_image.texture_format;
+
+        // free buffers of another size are left over from before a resize
+        let free = capture_buffers.free.entry(*target).or_default();
+        while let Some(capture_buffer) = free.pop() {
+            if capture_buffer.width == width
+                && capture_buffer.height == height
+                && capture_buffer.format == format
+            {
+                capture_buffers.current.insert(*target, capture_buffer);
+                break;
+            }
+        }
+        if capture_buffers.current.contains_key(target) {
+            continue;
+        }
+
+        let padded_bytes_per_row = padded_bytes_per_row(width, format);
+
+        let buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("capture_buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        capture_buffers.current.insert(
+            *target,
+            CaptureBuffer {
+                buffer,
+                width,
+                height,
+                format,
+                padded_bytes_per_row,
+            },
+        );
+    }
+}
+
+// copies the requested textures into the capture buffers once every pass has run
+pub struct CaptureNode;
+
+impl render_graph::Node for CaptureNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+        let capture_buffers = world.resource::<CaptureBuffers>();
+
+        for (target, image) in world.resource::<ExtractedCaptureRequests>().0.iter() {
+            if let (Some(gpu_image), Some(capture_buffer)) =
+                (gpu_images.get(image), capture_buffers.current.get(target))
+            {
+                // skips the frame where the texture was resized but not the buffer yet
+                let buffer_size =
+                    Vec2::new(capture_buffer.width as f32, capture_buffer.height as f32);
+                if gpu_image.size != buffer_size {
+                    continue;
+                }
+
+                render_context.command_encoder.copy_texture_to_buffer(
+                    gpu_image.texture.as_image_copy(),
+                    ImageCopyBuffer {
+                        buffer: &capture_buffer.buffer,
+                        layout: ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: NonZeroU32::new(capture_buffer.padded_bytes_per_row),
+                            rows_per_image: None,
+                        },
+                    },
+                    Extent3d {
+                        width: capture_buffer.width,
+                        height: capture_buffer.height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Runs after the frame has been submitted. It starts mapping the buffers of the frame and
+// hands over the captures of earlier frames whose buffers are mapped by now, so that
+// capturing does not stall the frames.
+pub fn read_captured_frames(
+    mut capture_buffers: ResMut<CaptureBuffers>,
+    clock: Res<ShadertoyClock>,
+    queue: Res<CaptureQueue>,
+    render_device: Res<RenderDevice>,
+) {
+    let capture_buffers = &mut *capture_buffers;
+
+    if !capture_buffers.current.is_empty() {
+        let buffers = capture_buffers
+            .current
+            .drain()
+            .map(|(target, capture_buffer)| {
+                let map_request = MapRequest::start(&capture_buffer.buffer);
+                (target, capture_buffer, map_request)
+            })
+            .collect();
+
+        capture_buffers.pending.push(PendingCaptures {
+            frame: clock.frame(),
+            time: clock.time(),
+            buffers,
+        });
+    }
+
+    if capture_buffers.pending.is_empty() {
+        return;
+    }
+
+    render_device.poll(wgpu::Maintain::Poll);
+
+    // the frames arrive in the order they were captured in
+    let mut queue = queue.0.lock().unwrap();
+
+    while let Some(pending) = capture_buffers.pending.first() {
+        let mapped = pending
+            .buffers
+            .iter()
+            .all(|(_, _, map_request)| map_request.result().is_some());
+        if !mapped {
+            break;
+        }
+
+        let pending = capture_buffers.pending.remove(0);
+        for (target, capture_buffer, map_request) in pending.buffers {
+            if map_request.result() != Some(true) {
+                error!("could not map the capture buffer of {}", target.name());
+                continue;
+            }
+
+            let pixels = unpad_rows(
+                &capture_buffer.buffer.slice(..).get_mapped_range(),
+                capture_buffer.width,
+                capture_buffer.padded_bytes_per_row,
+                capture_buffer.format,
+            );
+            capture_buffer.buffer.unmap();
+
+            queue.push(CapturedFrame {
+                target,
+                frame: pending.frame,
+                time: pending.time,
+                width: capture_buffer.width,
+                height: capture_buffer.height,
+                pixels,
+            });
+
+            capture_buffers
+                .free
+                .entry(target)
+                .or_default()
+                .push(capture_buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_the_padding_of_the_rows() {
+        let padded_bytes_per_row = padded_bytes_per_row(2, TextureFormat::Rgba32Float);
+        assert_eq!(padded_bytes_per_row, 256);
+
+        let mut data = vec![0u8; 2 * padded_bytes_per_row as usize];
+        for row in 0..2 {
+            for value in 0..8 {
+                let start = row * padded_bytes_per_row as usize + value * 4;
+                let texel = (row * 8 + value) as f32;
+                data[start..start + 4].copy_from_slice(&texel.to_le_bytes());
+            }
+        }
+
+        let texels = unpad_rows(&data, 2, padded_bytes_per_row, TextureFormat::Rgba32Float);
+        assert_eq!(
+            texels,
+            (0..16).map(|value| value as f32).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn decodes_half_floats() {
+        let padded_bytes_per_row = padded_bytes_per_row(1, TextureFormat::Rgba16Float);
+        let mut data = vec![0u8; padded_bytes_per_row as usize];
+        for (index, bits) in [0x3c00u16, 0xc000, 0x3800, 0x0001].iter().enumerate() {
+            data[index * 2..index * 2 + 2].copy_from_slice(&bits.to_le_bytes());
+        }
+
+        let texels = unpad_rows(&data, 1, padded_bytes_per_row, TextureFormat::Rgba16Float);
+        assert_eq!(texels, vec![1.0, -2.0, 0.5, 2f32.powi(-24)]);
+    }
+}
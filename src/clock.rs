@@ -0,0 +1,299 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+use crate::{ShadertoyCommand, ShadertoyDeterministicMode, ShadertoyPassesReady};
+
+// Drives iTime, iTimeDelta and iFrame. While paused, the buffer passes are not
+// dispatched, so that simulations freeze, and the image pass keeps showing them.
+// It stays at frame 0 until every active pass has loaded and run INIT, and starts over
+// whenever a pass has to load again.
+#[derive(Clone, Resource, ExtractResource, Debug)]
+pub struct ShadertoyClock {
+    pub paused: bool,
+    // multiplies the frame time, 1.0 is normal speed
+    pub speed: f32,
+    // time delta of a single step while paused and of the steps simulated after a seek
+    pub fixed_step: f32,
+    // when set, every frame advances by this many seconds instead of the frame time, e.g.
+    // while recording. ShadertoyDeterministicMode takes precedence.
+    pub fixed_dt: Option<f32>,
+    // How many steps are simulated per rendered frame when fast-forwarding after a seek.
+    // Every step runs the whole graph with its own iTime and iFrame, see FastForwardNode.
+    pub fast_forward_steps_per_frame: u32,
+    time: f32,
+    delta: f32,
+    frame: u32,
+    steps: u32,
+    pending_steps: u32,
+    seek_target: Option<f32>,
+    fast_forward_target: Option<f32>,
+    // held at frame 0 while the passes load
+    held: bool,
+}
+
+impl Default for ShadertoyClock {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            speed: 1.0,
+            fixed_step: 1.0 / 60.0,
+            fixed_dt: None,
+            fast_forward_steps_per_frame: 16,
+            time: 0.0,
+            delta: 0.0,
+            frame: 0,
+            steps: 0,
+            pending_steps: 0,
+            seek_target: None,
+            fast_forward_target: None,
+            held: true,
+        }
+    }
+}
+
+impl ShadertoyClock {
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // advances exactly one frame of fixed_step seconds, only has an effect while paused
+    pub fn step(&mut self) {
+        if self.paused {
+            self.pending_steps += 1;
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.max(0.0);
+    }
+
+    // Restarts the shader with INIT and simulates the buffers up to the given time, in
+    // steps of fixed_step seconds
+    pub fn seek(&mut self, time: f32) {
+        self.seek_target = Some(time.max(0.0));
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    // Number of times the buffer passes are dispatched in the current frame. It is 0 while
+    // paused and can be more than 1 while fast-forwarding.
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    pub fn is_fast_forwarding(&self) -> bool {
+        self.fast_forward_target.is_some()
+    }
+
+    // whether the clock waits for the passes to load
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    // back to the first frame, on ShadertoyCommand::Reset and while the passes load
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+        self.delta = 0.0;
+        self.frame = 0;
+        self.steps = 1;
+        self.pending_steps = 0;
+    }
+
+    // continues from a saved time and frame, keeping the settings of the clock
+    pub(crate) fn resume_at(&mut self, time: f32, delta: f32, frame: u32) {
+        self.time = time;
+        self.delta = delta;
+        self.frame = frame;
+        self.pending_steps = 0;
+        self.seek_target = None;
+        self.fast_forward_target = None;
+    }
+
+    fn advance(&mut self, delta: f32, steps: u32) {
+        self.delta = delta;
+        self.time += delta * steps as f32;
+        self.frame += steps;
+        self.steps = steps;
+    }
+
+    // a frame in which the passes are not dispatched
+    fn stand_still(&mut self) {
+        self.delta = 0.0;
+        self.steps = 0;
+    }
+
+    // Advances the clock by a rendered frame of frame_time seconds, before the speed is
+    // applied. Returns true when a seek needs the passes to go through INIT again.
+    fn tick(&mut self, frame_time: f32) -> bool {
+        // the reset sends the passes through INIT before the fast-forward starts
+        if let Some(target) = self.seek_target.take() {
+            self.fast_forward_target = Some(target);
+            return true;
+        }
+
+        if let Some(target) = self.fast_forward_target {
+            let step = self.fixed_step.max(f32::EPSILON);
+            let remaining_steps = ((target - self.time) / step).ceil().max(0.0) as u32;
+            let steps = remaining_steps.min(self.fast_forward_steps_per_frame.max(1));
+
+            if steps < remaining_steps {
+                self.advance(step, steps);
+                return false;
+            }
+
+            // the last steps are shortened so that they land on the target
+            self.fast_forward_target = None;
+            match steps {
+                0 => self.stand_still(),
+                _ => self.advance((target - self.time) / steps as f32, steps),
+            }
+            self.time = target;
+            return false;
+        }
+
+        if self.paused {
+            if self.pending_steps > 0 {
+                self.pending_steps -= 1;
+                self.advance(self.fixed_step, 1);
+            } else {
+                self.stand_still();
+            }
+            return false;
+        }
+
+        self.advance(frame_time * self.speed, 1);
+        false
+    }
+}
+
+pub fn tick_clock(
+    time: Res<Time>,
+    deterministic: Option<Res<ShadertoyDeterministicMode>>,
+    passes_ready: Res<ShadertoyPassesReady>,
+    mut clock: ResMut<ShadertoyClock>,
+    mut commands: EventWriter<ShadertoyCommand>,
+) {
+    // the first update of the passes sees frame 0, as on the frame after a reset
+    if !passes_ready.get() {
+        clock.restart();
+        clock.held = true;
+        return;
+    }
+    if clock.held {
+        clock.held = false;
+        return;
+    }
+
+    let frame_time = match (deterministic, clock.fixed_dt) {
+        (Some(deterministic), _) => deterministic.dt,
+        (None, Some(fixed_dt)) => fixed_dt,
+        (None, None) => time.delta_seconds(),
+    };
+    if clock.tick(frame_time) {
+        commands.send(ShadertoyCommand::Reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running_clock() -> ShadertoyClock {
+        let mut clock = ShadertoyClock::default();
+        clock.restart();
+        clock.held = false;
+        clock
+    }
+
+    #[test]
+    fn stands_still_while_paused() {
+        let mut clock = running_clock();
+        clock.tick(0.1);
+        clock.pause();
+
+        clock.tick(0.1);
+        assert_eq!((clock.time(), clock.delta()), (0.1, 0.0));
+        assert_eq!((clock.frame(), clock.steps()), (1, 0));
+    }
+
+    #[test]
+    fn steps_a_single_frame_while_paused() {
+        let mut clock = running_clock();
+        clock.pause();
+        clock.step();
+
+        clock.tick(0.1);
+        assert_eq!(
+            (clock.time(), clock.delta()),
+            (clock.fixed_step, clock.fixed_step)
+        );
+        assert_eq!((clock.frame(), clock.steps()), (1, 1));
+
+        clock.tick(0.1);
+        assert_eq!((clock.frame(), clock.steps()), (1, 0));
+    }
+
+    #[test]
+    fn scales_the_frame_time_by_the_speed() {
+        let mut clock = running_clock();
+        clock.set_speed(2.0);
+
+        clock.tick(0.25);
+        assert_eq!((clock.time(), clock.delta()), (0.5, 0.5));
+        assert_eq!(clock.steps(), 1);
+    }
+
+    #[test]
+    fn fast_forwards_to_the_target_of_a_seek() {
+        let mut clock = running_clock();
+        clock.fixed_step = 0.25;
+        clock.fast_forward_steps_per_frame = 3;
+
+        clock.seek(0.9);
+        assert!(clock.tick(0.1));
+        clock.restart();
+
+        assert!(!clock.tick(0.1));
+        assert_eq!((clock.time(), clock.steps()), (0.75, 3));
+        assert!(clock.is_fast_forwarding());
+
+        // the last step is shortened, its delta still adds up to the target
+        clock.tick(0.1);
+        assert_eq!((clock.frame(), clock.steps()), (4, 1));
+        assert!((clock.delta() - 0.15).abs() < 1e-6);
+        assert_eq!(clock.time(), 0.9);
+        assert!(!clock.is_fast_forwarding());
+    }
+
+    #[test]
+    fn seeking_to_the_start_runs_no_step() {
+        let mut clock = running_clock();
+        clock.tick(0.1);
+
+        clock.seek(0.0);
+        assert!(clock.tick(0.1));
+        clock.restart();
+
+        clock.tick(0.1);
+        assert_eq!((clock.time(), clock.delta()), (0.0, 0.0));
+        assert_eq!((clock.frame(), clock.steps()), (0, 0));
+        assert!(!clock.is_fast_forwarding());
+    }
+}
@@ -0,0 +1,97 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::ShadertoyClock;
+
+// Send as an event to control the running shader
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadertoyCommand {
+    // clears the buffers, sets i_frame and i_time back to zero and runs INIT again,
+    // like the rewind button on Shadertoy
+    Reset,
+    // starts or stops the ShadertoyRecorder
+    ToggleRecording,
+    // saves the main image as PNG with the ShadertoyScreenshots settings
+    Screenshot,
+    // saves the raw float content of the buffers A to D
+    DumpBuffers,
+}
+
+#[derive(Clone, Resource, Debug)]
+pub struct ShadertoyKeyBindings {
+    pub reset: Option<KeyCode>,
+    pub record: Option<KeyCode>,
+    pub screenshot: Option<KeyCode>,
+    pub dump_buffers: Option<KeyCode>,
+}
+
+impl Default for ShadertoyKeyBindings {
+    fn default() -> Self {
+        Self {
+            reset: Some(KeyCode::Back),
+            record: Some(KeyCode::F9),
+            screenshot: Some(KeyCode::F12),
+            dump_buffers: Some(KeyCode::F10),
+        }
+    }
+}
+
+// set during the frames where a reset was requested, so that the render
+// graph nodes go back through INIT
+#[derive(Clone, Resource, ExtractResource, Default)]
+pub struct ShadertoyReset(pub bool);
+
+pub fn send_key_commands(
+    keys: Res<Input<KeyCode>>,
+    key_bindings: Res<ShadertoyKeyBindings>,
+    mut commands: EventWriter<ShadertoyCommand>,
+) {
+    let bindings = [
+        (key_bindings.reset, ShadertoyCommand::Reset),
+        (key_bindings.record, ShadertoyCommand::ToggleRecording),
+        (key_bindings.screenshot, ShadertoyCommand::Screenshot),
+        (key_bindings.dump_buffers, ShadertoyCommand::DumpBuffers),
+    ];
+
+    for (key, command) in bindings {
+        if let Some(key) = key {
+            if keys.just_pressed(key) {
+                commands.send(command);
+            }
+        }
+    }
+}
+
+pub fn apply_commands(
+    mut commands: EventReader<ShadertoyCommand>,
+    mut clock: ResMut<ShadertoyClock>,
+    mut reset: ResMut<ShadertoyReset>,
+    mut images: ResMut<Assets<Image>>,
+    texture_a: Res<TextureA>,
+    texture_b: Res<TextureB>,
+    texture_c: Res<TextureC>,
+    texture_d: Res<TextureD>,
+) {
+    reset.0 = false;
+
+    for command in commands.iter() {
+        match command {
+            ShadertoyCommand::Reset => {
+                reset.0 = true;
+                clock.restart();
+
+                // touching the images uploads their initial content again as new textures
+                for handle in [&texture_a.0, &texture_b.0, &texture_c.0, &texture_d.0] {
+                    images.get_mut(handle);
+                }
+            }
+            // handled by the recorder and take_screenshots
+            ShadertoyCommand::ToggleRecording
+            | ShadertoyCommand::Screenshot
+            | ShadertoyCommand::DumpBuffers => {}
+        }
+    }
+}
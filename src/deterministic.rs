@@ -0,0 +1,41 @@
+use bevy::prelude::*;
+
+// Insert this resource to make two runs of the same shader produce identical buffers.
+// Every frame advances the ShadertoyClock by dt instead of the real frame time, iDate
+// and iSampleRate are pinned, and the mouse follows mouse_script instead of the window.
+#[derive(Clone, Resource, Debug)]
+pub struct ShadertoyDeterministicMode {
+    pub dt: f32,
+    // (year, month, day, seconds) as in Shadertoy's iDate
+    pub date: Vec4,
+    // must be sorted by frame
+    pub mouse_script: Vec<ScriptedMouse>,
+}
+
+impl Default for ShadertoyDeterministicMode {
+    fn default() -> Self {
+        Self {
+            dt: 1.0 / 60.0,
+            date: Vec4::ZERO,
+            mouse_script: Vec::new(),
+        }
+    }
+}
+
+impl ShadertoyDeterministicMode {
+    // the last scripted mouse state at or before the given frame
+    pub fn mouse_at(&self, frame: u32) -> Option<&ScriptedMouse> {
+        self.mouse_script
+            .iter()
+            .take_while(|mouse| mouse.frame <= frame)
+            .last()
+    }
+}
+
+// State of the mouse from the given frame on, with the position in canvas pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScriptedMouse {
+    pub frame: u32,
+    pub position: Vec2,
+    pub pressed: bool,
+}
@@ -0,0 +1,1062 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        extract_resource::ExtractResource,
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_phase::TrackedRenderPass,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        texture::GpuImage,
+    },
+};
+
+use std::borrow::Cow;
+
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::{
+    BindGroupKey, CaptureTarget, CommonUniformMeta, MainImage, PassRunState, PipelineKey,
+    ProjectStorage, ShaderHandles, ShadertoyCanvas, ShadertoyRebuildCounters, ShadertoyState,
+    ShadertoyStorageBuffers, WorkgroupSize,
+};
+
+pub const DRAW_COMPOSITE_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x2c85_e9a1_4f07_d36b);
+
+// runs the draw pass of the project once the image pass is done
+pub const DRAW_NODE: &str = "draw";
+
+// runs the vertex art pass of the project, before the image pass if it draws into a buffer
+// and after it otherwise, see update_render_graph
+pub const VERTEX_ART_NODE: &str = "vertex_art";
+
+// The primitives drawn over the main image are blended together in this format, as
+// rgba32float is not blendable. Those drawn into a buffer keep its precision in
+// rgba32float, where each primitive replaces what is behind it and only the composite
+// shader blends them into the buffer.
+const DRAW_TARGET_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+const DRAW_BUFFER_TARGET_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+// What the draw pass renders for each instance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawPrimitive {
+    // a single pixel at the position returned by drawVertex
+    Points,
+    // two triangles, drawVertex is called for each of their corners. Sprites are quads that
+    // drawFragment shapes from the corner.
+    Quads,
+}
+
+impl DrawPrimitive {
+    fn vertex_count(&self) -> u32 {
+        match self {
+            DrawPrimitive::Points => 1,
+            DrawPrimitive::Quads => 6,
+        }
+    }
+
+    fn topology(&self) -> PrimitiveTopology {
+        match self {
+            DrawPrimitive::Points => PrimitiveTopology::PointList,
+            DrawPrimitive::Quads => PrimitiveTopology::TriangleList,
+        }
+    }
+
+    fn shader_defs(&self) -> Vec<String> {
+        match self {
+            DrawPrimitive::Points => vec!["POINTS".to_string()],
+            DrawPrimitive::Quads => vec![],
+        }
+    }
+}
+
+// How the colors of the primitives are blended, with each other and then with the image
+// they are drawn into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawBlend {
+    // over what is behind, by the alpha of the color
+    Alpha,
+    // added to what is behind, scaled by the alpha of the color
+    Additive,
+}
+
+impl DrawBlend {
+    // the target starts out transparent, so it ends up holding premultiplied colors
+    fn blend_state(&self) -> BlendState {
+        match self {
+            DrawBlend::Alpha => BlendState::ALPHA_BLENDING,
+            DrawBlend::Additive => BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+            },
+        }
+    }
+}
+
+// How many instances the draw pass renders
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DrawInstances {
+    // one per element of the first storage buffer of the project
+    FirstStorage,
+    // one per element of the storage buffer with this name
+    Storage(String),
+    Count(u32),
+}
+
+impl DrawInstances {
+    pub fn count(&self, storage: &ProjectStorage) -> Option<u32> {
+        match self {
+            DrawInstances::FirstStorage => {
+                storage.buffers.first().map(|buffer| buffer.element_count)
+            }
+            DrawInstances::Storage(name) => storage
+                .index(name)
+                .map(|index| storage.buffers[index].element_count),
+            DrawInstances::Count(count) => Some(*count),
+        }
+    }
+}
+
+// Configures the draw pass of the project, written in its optional draw.wgsl:
+//
+//     fn drawVertex(instance: u32, corner: vec2<f32>) -> DrawVertex
+//     fn drawFragment(draw_vertex: DrawVertex) -> vec4<f32>
+//
+// drawVertex places the instance, usually from a storage buffer, with
+// pixelToClip(position). The instances are rendered once the image pass is done and
+// composited into the main image. Insert it to change the defaults, which draw a quad per
+// element of the first storage buffer with alpha blending.
+#[derive(Clone, Debug, PartialEq, Eq, Resource, ExtractResource)]
+pub struct ShadertoyDraw {
+    pub primitive: DrawPrimitive,
+    pub instances: DrawInstances,
+    pub blend: DrawBlend,
+}
+
+impl Default for ShadertoyDraw {
+    fn default() -> Self {
+        Self {
+            primitive: DrawPrimitive::Quads,
+            instances: DrawInstances::FirstStorage,
+            blend: DrawBlend::Alpha,
+        }
+    }
+}
+
+// How the vertices of the vertex art pass are assembled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexArtPrimitive {
+    // one pixel per vertex
+    Points,
+    // a line between each pair of vertices
+    Lines,
+    // a triangle between each three vertices
+    Triangles,
+}
+
+impl VertexArtPrimitive {
+    fn topology(&self) -> PrimitiveTopology {
+        match self {
+            VertexArtPrimitive::Points => PrimitiveTopology::PointList,
+            VertexArtPrimitive::Lines => PrimitiveTopology::LineList,
+            VertexArtPrimitive::Triangles => PrimitiveTopology::TriangleList,
+        }
+    }
+}
+
+// Configures the vertex art pass of the project, written in its optional vertex.wgsl as on
+// vertexshaderart.com:
+//
+//     fn mainVertex(vertexId: f32, vertexCount: f32) -> DrawVertex
+//
+// It is called for each vertex and places it with pixelToClip(position), the color of
+// the vertex is interpolated across the primitive. Drawn into a buffer, the primitives
+// are seen by the passes of the next frame and stay there until a pass clears them. The
+// defaults draw 10000 points over the main image with alpha blending.
+#[derive(Clone, Debug, PartialEq, Eq, Resource, ExtractResource)]
+pub struct ShadertoyVertexArt {
+    pub vertex_count: u32,
+    pub primitive: VertexArtPrimitive,
+    pub target: CaptureTarget,
+    pub blend: DrawBlend,
+}
+
+impl Default for ShadertoyVertexArt {
+    fn default() -> Self {
+        Self {
+            vertex_count: 10000,
+            primitive: VertexArtPrimitive::Points,
+            target: CaptureTarget::MainImage,
+            blend: DrawBlend::Alpha,
+        }
+    }
+}
+
+// The buffers are bound as sampled textures as on the fragment backend, and the storage
+// buffers are read only
+fn assemble_draw_template(
+    template: &str,
+    common: &str,
+    code_block: &str,
+    storage: &ProjectStorage,
+) -> Option<String> {
+    if code_block.trim().is_empty() {
+        return None;
+    }
+
+    let shader_content = template
+        .replace(
+            "{{DRAW_PRELUDE}}",
+            include_str!("./templates/draw_prelude.wgsl"),
+        )
+        .replace(
+            "{{FRAGMENT_PRELUDE}}",
+            include_str!("./templates/fragment_prelude.wgsl"),
+        )
+        .replace("{{STORAGE}}", &storage.draw_declarations)
+        .replace("{{COMMON}}", common)
+        .replace("{{CODE_BLOCK}}", code_block);
+
+    Some(shader_content)
+}
+
+// Assembles the draw shader of a project, None if it has no draw.wgsl
+pub fn assemble_draw_shader(
+    common: &str,
+    code_block: &str,
+    storage: &ProjectStorage,
+) -> Option<String> {
+    assemble_draw_template(
+        include_str!("./templates/draw_template.wgsl"),
+        common,
+        code_block,
+        storage,
+    )
+}
+
+// Assembles the vertex art shader of a project, None if it has no vertex.wgsl
+pub fn assemble_vertex_art_shader(
+    common: &str,
+    code_block: &str,
+    storage: &ProjectStorage,
+) -> Option<String> {
+    assemble_draw_template(
+        include_str!("./templates/vertex_art_template.wgsl"),
+        common,
+        code_block,
+        storage,
+    )
+}
+
+#[derive(Resource)]
+pub struct DrawPipelines {
+    // uniform at binding 0, the four buffers at bindings 1 to 4, then the counts of the
+    // draw call at binding 5
+    pub layout: BindGroupLayout,
+    // the image composited into at binding 0 and the draw target at binding 1
+    pub composite_layout: BindGroupLayout,
+    // indexed by the blend, then by whether the draw target is blended, see
+    // DrawSettings::blended
+    alpha_composite_pipelines: [CachedComputePipelineId; 2],
+    additive_composite_pipelines: [CachedComputePipelineId; 2],
+}
+
+impl FromWorld for DrawPipelines {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let mut entries = vec![BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::VERTEX_FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(std::mem::size_of::<f32>() as u64 * 25),
+            },
+            count: None,
+        }];
+        for binding in 1..5 {
+            entries.push(BindGroupLayoutEntry {
+                binding,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+        }
+        entries.push(BindGroupLayoutEntry {
+            binding: 5,
+            visibility: ShaderStages::VERTEX_FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(DRAW_COUNTS_SIZE),
+            },
+            count: None,
+        });
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("draw_layout"),
+            entries: &entries,
+        });
+
+        let composite_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("draw_composite_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::ReadWrite,
+                        format: TextureFormat::Rgba32Float,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue_pipeline = |shader_defs: Vec<String>| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from("draw_composite_pipeline")),
+                layout: Some(vec![composite_layout.clone()]),
+                shader: DRAW_COMPOSITE_SHADER_HANDLE.typed(),
+                shader_defs,
+                entry_point: Cow::from("composite"),
+            })
+        };
+
+        let alpha_composite_pipelines = [
+            queue_pipeline(vec!["STRAIGHT_ALPHA".to_string()]),
+            queue_pipeline(vec![]),
+        ];
+        let additive_composite_pipelines = [
+            queue_pipeline(vec!["ADDITIVE".to_string(), "STRAIGHT_ALPHA".to_string()]),
+            queue_pipeline(vec!["ADDITIVE".to_string()]),
+        ];
+
+        DrawPipelines {
+            layout,
+            composite_layout,
+            alpha_composite_pipelines,
+            additive_composite_pipelines,
+        }
+    }
+}
+
+impl DrawPipelines {
+    fn composite_pipeline(&self, settings: &DrawSettings) -> CachedComputePipelineId {
+        let pipelines = match settings.blend {
+            DrawBlend::Alpha => &self.alpha_composite_pipelines,
+            DrawBlend::Additive => &self.additive_composite_pipelines,
+        };
+        pipelines[settings.blended() as usize]
+    }
+}
+
+// the vertex and instance counts of the draw call, padded to the 16 bytes of a uniform
+const DRAW_COUNTS_SIZE: u64 = 16;
+
+// What a draw pass is rendered with, besides its shader
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct DrawSettings {
+    topology: PrimitiveTopology,
+    shader_defs: Vec<String>,
+    blend: DrawBlend,
+    vertex_count: u32,
+    instance_count: u32,
+    target: CaptureTarget,
+}
+
+impl DrawSettings {
+    fn instances(draw: &ShadertoyDraw, instance_count: u32) -> Self {
+        Self {
+            topology: draw.primitive.topology(),
+            shader_defs: draw.primitive.shader_defs(),
+            blend: draw.blend,
+            vertex_count: draw.primitive.vertex_count(),
+            instance_count,
+            target: CaptureTarget::MainImage,
+        }
+    }
+
+    fn vertex_art(vertex_art: &ShadertoyVertexArt) -> Self {
+        Self {
+            topology: vertex_art.primitive.topology(),
+            shader_defs: vec![],
+            blend: vertex_art.blend,
+            vertex_count: vertex_art.vertex_count,
+            instance_count: 1,
+            target: vertex_art.target,
+        }
+    }
+
+    fn target_format(&self) -> TextureFormat {
+        match self.target {
+            CaptureTarget::MainImage => DRAW_TARGET_FORMAT,
+            _ => DRAW_BUFFER_TARGET_FORMAT,
+        }
+    }
+
+    // whether the primitives are blended with each other in the draw target, which then
+    // holds premultiplied colors
+    fn blended(&self) -> bool {
+        self.target_format() == DRAW_TARGET_FORMAT
+    }
+
+    // the pipeline does not depend on the counts, and on the target only through its format
+    fn same_pipeline(&self, other: &DrawSettings) -> bool {
+        self.topology == other.topology
+            && self.shader_defs == other.shader_defs
+            && self.blend == other.blend
+            && self.target_format() == other.target_format()
+    }
+
+    fn same_counts(&self, other: &DrawSettings) -> bool {
+        self.vertex_count == other.vertex_count && self.instance_count == other.instance_count
+    }
+}
+
+fn draw_pipeline_descriptor(
+    layout: &BindGroupLayout,
+    storage_layout: &BindGroupLayout,
+    shader: &Handle<Shader>,
+    settings: &DrawSettings,
+) -> RenderPipelineDescriptor {
+    RenderPipelineDescriptor {
+        label: Some(Cow::from("draw_pipeline")),
+        layout: Some(vec![layout.clone(), storage_layout.clone()]),
+        vertex: VertexState {
+            shader: shader.clone(),
+            shader_defs: settings.shader_defs.clone(),
+            entry_point: Cow::from("vertex"),
+            buffers: vec![],
+        },
+        fragment: Some(FragmentState {
+            shader: shader.clone(),
+            shader_defs: settings.shader_defs.clone(),
+            entry_point: Cow::from("fragment"),
+            targets: vec![Some(ColorTargetState {
+                format: settings.target_format(),
+                blend: settings.blended().then(|| settings.blend.blend_state()),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: settings.topology,
+            ..default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+    }
+}
+
+fn create_draw_target(
+    render_device: &RenderDevice,
+    size: Vec2,
+    format: TextureFormat,
+) -> (Texture, TextureView) {
+    let target = render_device.create_texture(&TextureDescriptor {
+        label: Some("draw_target"),
+        size: Extent3d {
+            width: size.x as u32,
+            height: size.y as u32,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+    });
+    let target_view = target.create_view(&TextureViewDescriptor::default());
+
+    (target, target_view)
+}
+
+pub struct DrawPass {
+    pipeline: CachedRenderPipelineId,
+    pipeline_key: PipelineKey,
+    settings: DrawSettings,
+    bind_group: BindGroup,
+    bind_group_key: BindGroupKey,
+    // The primitives are rendered into this, which has the size of the image they are
+    // drawn into, and it is then composited into that image
+    target: Texture,
+    target_view: TextureView,
+    size: Vec2,
+    composite_bind_group: BindGroup,
+    composite_bind_group_key: BindGroupKey,
+}
+
+// None while the project has no such pass
+#[derive(Resource, Default)]
+pub struct PreparedDraws {
+    pub instances: Option<DrawPass>,
+    pub vertex_art: Option<DrawPass>,
+}
+
+// What the draw passes share while they are prepared
+struct DrawContext<'a> {
+    pipelines: &'a DrawPipelines,
+    storage_layout: &'a BindGroupLayout,
+    common_uniform_buffer: &'a Buffer,
+    // indexed as in PassUsages::get
+    images: [&'a GpuImage; 5],
+    render_device: &'a RenderDevice,
+    rebuild_counters: &'a ShadertoyRebuildCounters,
+}
+
+impl<'a> DrawContext<'a> {
+    // Queues the pipeline of a draw pass and keeps its bind groups and its target in sync
+    // with the images
+    fn prepare(
+        &self,
+        cached: Option<DrawPass>,
+        shader: &Handle<Shader>,
+        settings: DrawSettings,
+        pipeline_cache: &mut PipelineCache,
+    ) -> DrawPass {
+        let layout = &self.pipelines.layout;
+        let pipeline_key = PipelineKey::new(shader, layout).with_storage(self.storage_layout);
+        let pipeline = match &cached {
+            Some(cached)
+                if cached.pipeline_key == pipeline_key
+                    && cached.settings.same_pipeline(&settings) =>
+            {
+                cached.pipeline
+            }
+            _ => {
+                self.rebuild_counters.count_pipeline_rebuild();
+                pipeline_cache.queue_render_pipeline(draw_pipeline_descriptor(
+                    layout,
+                    self.storage_layout,
+                    shader,
+                    &settings,
+                ))
+            }
+        };
+
+        // the counts are in the bind group
+        let buffers: Vec<&Texture> = self.images[..4].iter().map(|image| &image.texture).collect();
+        let bind_group_key = BindGroupKey::new(layout, &buffers);
+        let bind_group = match &cached {
+            Some(cached)
+                if cached.bind_group_key == bind_group_key
+                    && cached.settings.same_counts(&settings) =>
+            {
+                cached.bind_group.clone()
+            }
+            _ => {
+                self.rebuild_counters.count_bind_group_rebuild();
+                self.create_bind_group(&settings)
+            }
+        };
+
+        let image = match settings.target {
+            CaptureTarget::MainImage => self.images[4],
+            buffer => self.images[CaptureTarget::BUFFERS
+                .iter()
+                .position(|target| *target == buffer)
+                .unwrap_or_default()],
+        };
+
+        let size = image.size;
+        let format = settings.target_format();
+        let (target, target_view) = match &cached {
+            Some(cached) if cached.size == size && cached.settings.target_format() == format => {
+                (cached.target.clone(), cached.target_view.clone())
+            }
+            _ => create_draw_target(self.render_device, size, format),
+        };
+
+        let composite_layout = &self.pipelines.composite_layout;
+        let composite_bind_group_key =
+            BindGroupKey::new(composite_layout, &[&image.texture, &target]);
+        let composite_bind_group = match cached {
+            Some(cached) if cached.composite_bind_group_key == composite_bind_group_key => {
+                cached.composite_bind_group
+            }
+            _ => {
+                self.rebuild_counters.count_bind_group_rebuild();
+
+                self.render_device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("draw_composite_bind_group"),
+                    layout: composite_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(&image.texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(&target_view),
+                        },
+                    ],
+                })
+            }
+        };
+
+        DrawPass {
+            pipeline,
+            pipeline_key,
+            settings,
+            bind_group,
+            bind_group_key,
+            target,
+            target_view,
+            size,
+            composite_bind_group,
+            composite_bind_group_key,
+        }
+    }
+
+    fn create_bind_group(&self, settings: &DrawSettings) -> BindGroup {
+        let counts = [settings.vertex_count, settings.instance_count, 0, 0];
+        let counts_buffer = self
+            .render_device
+            .create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("draw_counts_buffer"),
+                contents: bytemuck::cast_slice(&counts),
+                usage: BufferUsages::UNIFORM,
+            });
+
+        let mut entries = vec![BindGroupEntry {
+            binding: 0,
+            resource: self.common_uniform_buffer.as_entire_binding(),
+        }];
+        for (buffer, image) in self.images[..4].iter().enumerate() {
+            entries.push(BindGroupEntry {
+                binding: buffer as u32 + 1,
+                resource: BindingResource::TextureView(&image.texture_view),
+            });
+        }
+        entries.push(BindGroupEntry {
+            binding: 5,
+            resource: counts_buffer.as_entire_binding(),
+        });
+
+        self.render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("draw_bind_group"),
+            layout: &self.pipelines.layout,
+            entries: &entries,
+        })
+    }
+}
+
+// Prepares the draw and vertex art passes of the project, if it has them
+pub fn queue_draw_passes(
+    mut prepared: ResMut<PreparedDraws>,
+    pipelines: Res<DrawPipelines>,
+    draw: Res<ShadertoyDraw>,
+    vertex_art: Res<ShadertoyVertexArt>,
+    all_shader_handles: Res<ShaderHandles>,
+    storage_buffers: Res<ShadertoyStorageBuffers>,
+    common_uniform_meta: Res<CommonUniformMeta>,
+    gpu_images: Res<RenderAssets<Image>>,
+    main_image: Res<MainImage>,
+    texture_a_image: Res<TextureA>,
+    texture_b_image: Res<TextureB>,
+    texture_c_image: Res<TextureC>,
+    texture_d_image: Res<TextureD>,
+    render_device: Res<RenderDevice>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    rebuild_counters: Res<ShadertoyRebuildCounters>,
+    render_graph: Res<RenderGraph>,
+    mut reported: Local<bool>,
+) {
+    let (draw_shader, vertex_art_shader) = (
+        &all_shader_handles.draw_shader,
+        &all_shader_handles.vertex_art_shader,
+    );
+    if draw_shader.is_none() && vertex_art_shader.is_none() {
+        *prepared = PreparedDraws::default();
+        *reported = false;
+        return;
+    }
+
+    let instance_count = draw.instances.count(&all_shader_handles.storage);
+    let problem = match instance_count {
+        _ if render_graph.get_node_id(DRAW_NODE).is_err() => Some(
+            "the draw and vertex art passes are not supported in post-process mode, they are \
+             ignored",
+        ),
+        None if draw_shader.is_some() => {
+            Some("the storage buffer to draw the instances of is not declared")
+        }
+        _ => None,
+    };
+    if let Some(problem) = problem {
+        if !*reported {
+            error!("{}", problem);
+            *reported = true;
+        }
+        *prepared = PreparedDraws::default();
+        return;
+    }
+    *reported = false;
+
+    let outputs = [
+        &texture_a_image.0,
+        &texture_b_image.0,
+        &texture_c_image.0,
+        &texture_d_image.0,
+        &main_image.0,
+    ];
+    let images: [&GpuImage; 5] = match outputs.map(|handle| gpu_images.get(handle)) {
+        [Some(a), Some(b), Some(c), Some(d), Some(main_image)] => [a, b, c, d, main_image],
+        _ => return,
+    };
+
+    let context = DrawContext {
+        pipelines: &pipelines,
+        storage_layout: &storage_buffers.draw_layout,
+        common_uniform_buffer: &common_uniform_meta.buffer,
+        images,
+        render_device: &render_device,
+        rebuild_counters: &rebuild_counters,
+    };
+
+    prepared.instances = draw_shader.as_ref().map(|shader| {
+        context.prepare(
+            prepared.instances.take(),
+            shader,
+            DrawSettings::instances(&draw, instance_count.unwrap_or_default()),
+            &mut pipeline_cache,
+        )
+    });
+    prepared.vertex_art = vertex_art_shader.as_ref().map(|shader| {
+        context.prepare(
+            prepared.vertex_art.take(),
+            shader,
+            DrawSettings::vertex_art(&vertex_art),
+            &mut pipeline_cache,
+        )
+    });
+}
+
+// The passes of PreparedDraws
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawKind {
+    Instances,
+    VertexArt,
+}
+
+// The fragment backend runs neither the draw nor the vertex art pass, see queue_draw_passes
+pub fn reject_draw_passes(all_shader_handles: Res<ShaderHandles>, mut reported: Local<bool>) {
+    let has_draw_passes =
+        all_shader_handles.draw_shader.is_some() || all_shader_handles.vertex_art_shader.is_some();
+    if has_draw_passes && !*reported {
+        error!("the draw and vertex art passes need the compute backend, they are ignored");
+    }
+    *reported = has_draw_passes;
+}
+
+// Renders the primitives of a draw pass and composites them into their image. It follows
+// the schedule of the pass of that image, so that they are drawn once per update of the
+// image.
+pub struct DrawNode {
+    kind: DrawKind,
+    state: ShadertoyState,
+    run_state: PassRunState,
+}
+
+impl DrawNode {
+    pub fn new(kind: DrawKind) -> Self {
+        Self {
+            kind,
+            state: ShadertoyState::Loading,
+            run_state: PassRunState::default(),
+        }
+    }
+
+    fn draw_pass<'a>(&self, world: &'a World) -> Option<&'a DrawPass> {
+        let prepared = world.resource::<PreparedDraws>();
+        match self.kind {
+            DrawKind::Instances => prepared.instances.as_ref(),
+            DrawKind::VertexArt => prepared.vertex_art.as_ref(),
+        }
+    }
+}
+
+impl render_graph::Node for DrawNode {
+    fn update(&mut self, world: &mut World) {
+        let settings = self
+            .draw_pass(world)
+            .map(|draw_pass| (draw_pass.pipeline, draw_pass.settings.clone()));
+
+        let (pipeline, settings) = match settings {
+            Some(settings) => settings,
+            // a project without the pass is not waited for
+            None => {
+                self.state = ShadertoyState::Loading;
+                self.run_state.dispatch = false;
+                return;
+            }
+        };
+
+        // draw passes have no INIT variant
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let composite_pipeline = world
+            .resource::<DrawPipelines>()
+            .composite_pipeline(&settings);
+        self.state = match (
+            pipeline_cache.get_render_pipeline_state(pipeline),
+            pipeline_cache.get_compute_pipeline_state(composite_pipeline),
+        ) {
+            (CachedPipelineState::Ok(_), CachedPipelineState::Ok(_)) => ShadertoyState::Update,
+            _ => ShadertoyState::Loading,
+        };
+
+        self.run_state
+            .update(settings.target.name(), &self.state, world);
+        if self.kind == DrawKind::VertexArt {
+            self.run_state.plan_fast_forward(VERTEX_ART_NODE, world);
+        }
+    }
+
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        if !world.resource::<ShadertoyCanvas>().active || !self.run_state.dispatch {
+            return Ok(());
+        }
+
+        if let Some(draw_pass) = self.draw_pass(world) {
+            run_draw_pass(draw_pass, world, &mut render_context.command_encoder);
+        }
+
+        Ok(())
+    }
+}
+
+// Draws the primitives of a draw pass once and composites them into their image. Run by
+// the DrawNode, and by the FastForwardNode for the steps before the last one.
+pub(crate) fn run_draw_pass(
+    draw_pass: &DrawPass,
+    world: &World,
+    command_encoder: &mut CommandEncoder,
+) {
+    let settings = &draw_pass.settings;
+
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let composite_pipeline = world
+        .resource::<DrawPipelines>()
+        .composite_pipeline(settings);
+    let (pipeline, composite_pipeline) = match (
+        pipeline_cache.get_render_pipeline(draw_pass.pipeline),
+        pipeline_cache.get_compute_pipeline(composite_pipeline),
+    ) {
+        (Some(pipeline), Some(composite_pipeline)) => (pipeline, composite_pipeline),
+        _ => return,
+    };
+
+    // a resized image gets its draw target in the next frame
+    let image = settings.target.image(
+        &world.resource::<MainImage>().0,
+        [
+            &world.resource::<TextureA>().0,
+            &world.resource::<TextureB>().0,
+            &world.resource::<TextureC>().0,
+            &world.resource::<TextureD>().0,
+        ],
+    );
+    match world.resource::<RenderAssets<Image>>().get(image) {
+        Some(image) if image.size == draw_pass.size => {}
+        _ => return,
+    }
+
+    let (workgroups_x, workgroups_y) =
+        WorkgroupSize::SIZE_8X8.dispatch_count(draw_pass.size.x as u32, draw_pass.size.y as u32);
+
+    let pass_descriptor = RenderPassDescriptor {
+        label: Some("draw_pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: &draw_pass.target_view,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    };
+
+    {
+        let mut render_pass =
+            TrackedRenderPass::new(command_encoder.begin_render_pass(&pass_descriptor));
+
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &draw_pass.bind_group, &[]);
+        render_pass.set_bind_group(
+            1,
+            &world.resource::<ShadertoyStorageBuffers>().draw_bind_group,
+            &[],
+        );
+        render_pass.draw(0..settings.vertex_count, 0..settings.instance_count);
+    }
+
+    let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+        label: Some("draw_composite_pass"),
+    });
+    pass.set_pipeline(composite_pipeline);
+    pass.set_bind_group(0, &draw_pass.composite_bind_group, &[]);
+    pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::StorageBufferLayout;
+
+    fn storage() -> ProjectStorage {
+        ProjectStorage {
+            declarations: String::new(),
+            draw_declarations: "var<storage, read> particles: array<vec4<f32>, 100>;".to_string(),
+            buffers: vec![
+                StorageBufferLayout {
+                    name: "particles".to_string(),
+                    element_count: 100,
+                    element_size: 16,
+                },
+                StorageBufferLayout {
+                    name: "trails".to_string(),
+                    element_count: 400,
+                    element_size: 16,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn counts_the_instances_of_storage_buffers() {
+        let storage = storage();
+
+        assert_eq!(DrawInstances::FirstStorage.count(&storage), Some(100));
+        assert_eq!(
+            DrawInstances::Storage("trails".to_string()).count(&storage),
+            Some(400)
+        );
+        assert_eq!(
+            DrawInstances::Storage("missing".to_string()).count(&storage),
+            None
+        );
+        assert_eq!(DrawInstances::Count(7).count(&storage), Some(7));
+        assert_eq!(
+            DrawInstances::FirstStorage.count(&ProjectStorage::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_the_pipeline_when_only_the_counts_change() {
+        let vertex_art = ShadertoyVertexArt {
+            target: CaptureTarget::BufferA,
+            ..default()
+        };
+        let settings = DrawSettings::vertex_art(&vertex_art);
+
+        let more_vertices = DrawSettings::vertex_art(&ShadertoyVertexArt {
+            vertex_count: 20,
+            ..vertex_art.clone()
+        });
+        assert!(settings.same_pipeline(&more_vertices));
+        assert!(!settings.same_counts(&more_vertices));
+
+        // the buffers share the format of their draw target
+        let other_buffer = DrawSettings::vertex_art(&ShadertoyVertexArt {
+            target: CaptureTarget::BufferC,
+            ..vertex_art.clone()
+        });
+        assert!(settings.same_pipeline(&other_buffer));
+
+        for changed in [
+            ShadertoyVertexArt {
+                target: CaptureTarget::MainImage,
+                ..vertex_art.clone()
+            },
+            ShadertoyVertexArt {
+                primitive: VertexArtPrimitive::Lines,
+                ..vertex_art.clone()
+            },
+            ShadertoyVertexArt {
+                blend: DrawBlend::Additive,
+                ..vertex_art.clone()
+            },
+        ] {
+            assert!(!settings.same_pipeline(&DrawSettings::vertex_art(&changed)));
+        }
+
+        let points = DrawSettings::instances(
+            &ShadertoyDraw {
+                primitive: DrawPrimitive::Points,
+                ..default()
+            },
+            100,
+        );
+        assert!(!points.same_pipeline(&DrawSettings::instances(&ShadertoyDraw::default(), 100)));
+    }
+
+    #[test]
+    fn blends_only_the_targets_of_the_main_image() {
+        let instances = DrawSettings::instances(&ShadertoyDraw::default(), 100);
+        assert!(instances.blended());
+
+        let vertex_art = DrawSettings::vertex_art(&ShadertoyVertexArt {
+            target: CaptureTarget::BufferB,
+            ..default()
+        });
+        assert!(!vertex_art.blended());
+        assert_eq!(vertex_art.target_format(), TextureFormat::Rgba32Float);
+    }
+
+    #[test]
+    fn assembles_the_draw_shaders() {
+        let storage = storage();
+        let common = "fn common_helper() {}";
+        let code_block = "fn drawVertex(instance: u32, corner: vec2<f32>) -> DrawVertex {}";
+
+        assert_eq!(assemble_draw_shader(common, "  \n", &storage), None);
+        assert_eq!(assemble_vertex_art_shader(common, "", &storage), None);
+
+        for shader in [
+            assemble_draw_shader(common, code_block, &storage).unwrap(),
+            assemble_vertex_art_shader(common, code_block, &storage).unwrap(),
+        ] {
+            assert!(!shader.contains("{{"));
+            assert!(shader.contains(common));
+            assert!(shader.contains(code_block));
+            assert!(shader.contains(&storage.draw_declarations));
+            assert!(shader.contains("fn pixelToClip("));
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+    },
+    utils::HashMap,
+};
+
+use crevice::std140::AsStd140;
+
+use crate::texture_a::dispatch_texture_a_update;
+use crate::texture_b::dispatch_texture_b_update;
+use crate::texture_c::dispatch_texture_c_update;
+use crate::texture_d::dispatch_texture_d_update;
+use crate::{
+    run_draw_pass, CommonUniformMeta, ExtractedUniform, PreparedDraws, ShadertoyCanvas,
+    ShadertoyClock, COMMON_UNIFORM_SIZE, VERTEX_ART_NODE,
+};
+
+pub const FAST_FORWARD_NODE: &str = "fast_forward";
+
+// The uniforms of the steps of the current frame while the clock fast-forwards, one slot
+// per step. The last slot is the current frame, the others go back one fixed step each.
+#[derive(Resource, Default)]
+pub struct FastForwardUniforms {
+    buffer: Option<Buffer>,
+    capacity: u32,
+    // the steps before the last one, which the FastForwardNode runs
+    pub earlier_steps: u32,
+}
+
+impl FastForwardUniforms {
+    // copies the uniform of a step into the common uniform buffer, between two passes
+    pub fn copy_step(
+        &self,
+        step: u32,
+        common_uniform_meta: &CommonUniformMeta,
+        command_encoder: &mut CommandEncoder,
+    ) {
+        if let Some(buffer) = &self.buffer {
+            command_encoder.copy_buffer_to_buffer(
+                buffer,
+                step as u64 * COMMON_UNIFORM_SIZE,
+                &common_uniform_meta.buffer,
+                0,
+                COMMON_UNIFORM_SIZE,
+            );
+        }
+    }
+}
+
+pub fn prepare_fast_forward_uniforms(
+    clock: Res<ShadertoyClock>,
+    extracted_uniform: Res<ExtractedUniform>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut uniforms: ResMut<FastForwardUniforms>,
+) {
+    uniforms.earlier_steps = clock.steps().saturating_sub(1);
+    if uniforms.earlier_steps == 0 {
+        return;
+    }
+
+    let slots = uniforms.earlier_steps + 1;
+    if uniforms.capacity < slots {
+        uniforms.buffer = Some(render_device.create_buffer(&BufferDescriptor {
+            label: Some("fast_forward_uniforms"),
+            size: slots as u64 * COMMON_UNIFORM_SIZE,
+            usage: BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        uniforms.capacity = slots;
+    }
+
+    let mut bytes = Vec::with_capacity((slots as u64 * COMMON_UNIFORM_SIZE) as usize);
+    for step in 0..slots {
+        let steps_back = (slots - 1 - step) as f32;
+        let mut uniform = extracted_uniform.0;
+        uniform.i_frame -= steps_back;
+        uniform.i_time -= uniform.i_time_delta * steps_back;
+
+        let slot_start = bytes.len();
+        bytes.extend_from_slice(uniform.as_std140().as_bytes());
+        bytes.resize(slot_start + COMMON_UNIFORM_SIZE as usize, 0);
+    }
+
+    if let Some(buffer) = &uniforms.buffer {
+        render_queue.write_buffer(buffer, 0, &bytes);
+    }
+}
+
+// Whether each node is dispatched in the steps before the last one, from the earlier_steps
+// of its PassRunState. Cleared at the start of every frame in the render world.
+#[derive(Resource, Default)]
+pub struct FastForwardPlan(pub HashMap<&'static str, Vec<bool>>);
+
+pub fn clear_fast_forward_plan(mut plan: ResMut<FastForwardPlan>) {
+    plan.0.clear();
+}
+
+// The nodes that change the buffers, in the order of the render graph, set by
+// update_render_graph
+#[derive(Resource, Default)]
+pub struct FastForwardOrder(pub Vec<&'static str>);
+
+// While the clock fast-forwards, it runs the steps before the last one of the frame, the
+// whole graph at a time and each with its own iTime and iFrame, so that passes that feed
+// each other see what they would in a real run. The pass nodes then run the last step as
+// in any other frame. The image pass only runs in the last step, its output is replaced
+// every step anyway.
+pub struct FastForwardNode;
+
+impl render_graph::Node for FastForwardNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let uniforms = world.resource::<FastForwardUniforms>();
+        if uniforms.earlier_steps == 0 || !world.resource::<ShadertoyCanvas>().active {
+            return Ok(());
+        }
+
+        let plan = world.resource::<FastForwardPlan>();
+        let order = world.resource::<FastForwardOrder>();
+        let common_uniform_meta = world.resource::<CommonUniformMeta>();
+        let command_encoder = &mut render_context.command_encoder;
+
+        for step in 0..uniforms.earlier_steps {
+            uniforms.copy_step(step, common_uniform_meta, command_encoder);
+
+            for node in order.0.iter() {
+                let dispatched = plan
+                    .0
+                    .get(node)
+                    .and_then(|steps| steps.get(step as usize))
+                    .copied()
+                    .unwrap_or(false);
+                if !dispatched {
+                    continue;
+                }
+
+                match *node {
+                    "texture_a" => dispatch_texture_a_update(world, command_encoder),
+                    "texture_b" => dispatch_texture_b_update(world, command_encoder),
+                    "texture_c" => dispatch_texture_c_update(world, command_encoder),
+                    "texture_d" => dispatch_texture_d_update(world, command_encoder),
+                    VERTEX_ART_NODE => {
+                        if let Some(draw_pass) = &world.resource::<PreparedDraws>().vertex_art {
+                            run_draw_pass(draw_pass, world, command_encoder);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // the pass nodes run the last step with the uniform of the current frame
+        uniforms.copy_step(uniforms.earlier_steps, common_uniform_meta, command_encoder);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,812 @@
+use bevy::{
+    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph,
+        render_phase::TrackedRenderPass,
+        render_resource::*,
+        renderer::{RenderAdapter, RenderContext, RenderDevice},
+        settings::WgpuFeatures,
+        texture::GpuImage,
+    },
+};
+
+use std::borrow::Cow;
+
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::{
+    pass_name, pass_order, ActivePasses, BindGroupKey, ChangedWindowSize, CommonUniformMeta,
+    FastForwardUniforms, MainImage, PassRunState, ShaderHandles, ShadertoyCanvas, ShadertoyLoading,
+    ShadertoyRebuildCounters, ShadertoyReset, ShadertoyResizePolicies, ShadertoyState,
+    ShadertoyTextures, BUFFER_NAMES, MAIN_IMAGE_FORMAT,
+};
+
+// How the passes are run. Insert it before adding the ShadertoyPlugin to choose it,
+// otherwise it is picked from the capabilities of the adapter when the plugin is built.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Resource)]
+pub enum ShadertoyBackend {
+    // compute passes on read_write storage textures. The adapter must support them for
+    // rgba32float, which rules out WebGPU and most mobile adapters.
+    Compute,
+    // Every pass renders a full-screen triangle into a render target, which is then copied
+    // into its buffer. The buffers are bound as sampled textures, so a pass sees them as
+    // they were before it ran. Only projects written as mainImage functions can run on it,
+    // and the post-process mode is not supported.
+    Fragment,
+}
+
+impl ShadertoyBackend {
+    pub fn detect(adapter: &RenderAdapter, device: &RenderDevice) -> Self {
+        // WebGPU has no read_write storage textures, whatever the adapter reports
+        if cfg!(feature = "wasm") {
+            return ShadertoyBackend::Fragment;
+        }
+
+        // without this feature, the device only allows the guaranteed format features
+        let adapter_features = device
+            .features()
+            .contains(WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let read_write = adapter
+            .get_texture_format_features(TextureFormat::Rgba32Float)
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::STORAGE_READ_WRITE);
+
+        match adapter_features && read_write {
+            true => ShadertoyBackend::Compute,
+            false => ShadertoyBackend::Fragment,
+        }
+    }
+}
+
+// Passes can be written as a mainImage function instead of an "update" entry point, in
+// which case they run on both backends:
+//
+//     fn mainImage(fragCoord: vec2<f32>) -> vec4<f32>
+//
+// fragCoord is the center of the pixel and the returned color is written into the buffer
+// of the pass, or into the main image for the image pass. The buffers are read with
+// load_buffer_a(location: vec2<i32>) to load_buffer_d. On the compute backend, a buffer
+// pass may only read its own buffer at load_buffer_x(vec2<i32>(fragCoord)), as it would
+// see what the other pixels wrote in the same frame otherwise.
+pub fn uses_main_image(code_block: &str) -> bool {
+    code_block.contains("fn mainImage(") && !code_block.contains("fn update(")
+}
+
+// Whether a mainImage pass reads its own buffer at other pixels than the one it writes,
+// which races between the invocations of a compute pass
+fn reads_own_buffer_elsewhere(buffer_type: &str, source: &str) -> bool {
+    let call = format!("load_{}(", buffer_type);
+    source.match_indices(&call).any(|(start, _)| {
+        let arguments = &source[start + call.len()..];
+        let mut depth = 1;
+        let end = arguments.find(|c| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        });
+        let argument: String = match end {
+            Some(end) => arguments[..end].split_whitespace().collect(),
+            None => return true,
+        };
+        argument != "vec2<i32>(fragCoord)"
+    })
+}
+
+// The code block of the compute variant. The "update" entry point of a mainImage pass is
+// generated, along with the loaders it uses, as a loader of a buffer the pass does not read
+// would not validate once the access of the buffer is narrowed. Buffer passes that read
+// their own buffer at other pixels are rejected, see uses_main_image.
+pub fn compute_code_block(
+    buffer_type: &str,
+    common: &str,
+    code_block: &str,
+) -> Result<String, String> {
+    if !uses_main_image(code_block) {
+        return Ok(code_block.to_string());
+    }
+
+    if buffer_type != "image"
+        && (reads_own_buffer_elsewhere(buffer_type, common)
+            || reads_own_buffer_elsewhere(buffer_type, code_block))
+    {
+        return Err(format!(
+            "{} reads its own buffer at other pixels than fragCoord, which the compute \
+             backend cannot do, use the fragment backend",
+            buffer_type
+        ));
+    }
+
+    let mut code_block = code_block.to_string();
+
+    for name in BUFFER_NAMES {
+        let loader = format!("load_{}", name);
+        if common.contains(&loader) || code_block.contains(&loader) {
+            code_block.push_str(&format!(
+                "\n\nfn {}(location: vec2<i32>) -> vec4<f32> {{\n    return textureLoad({}, location);\n}}",
+                loader, name
+            ));
+        }
+    }
+
+    let output = match buffer_type {
+        "image" => "texture",
+        buffer => buffer,
+    };
+    code_block.push_str(&format!(
+        "\n\n@compute @workgroup_size(8, 8, 1)\nfn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {{\n    let location = vec2<i32>(invocation_id.xy);\n    textureStore({}, location, mainImage(vec2<f32>(location) + 0.5));\n}}\n",
+        output
+    ));
+
+    Ok(code_block)
+}
+
+// Assembles the fragment variant of a pass, and whether it can be run. Passes that are not
+// written as a mainImage function get a stub, which is fine as long as they are inactive.
+pub fn assemble_fragment_shader(
+    buffer_type: &str,
+    common: &str,
+    code_block: &str,
+    include_debugger: bool,
+) -> (String, bool) {
+    let template = match buffer_type {
+        "image" => include_str!("./templates/fragment_image_template.wgsl"),
+        _ => include_str!("./templates/fragment_buffer_template.wgsl"),
+    };
+
+    let mut shader_content = template.replace(
+        "{{FRAGMENT_PRELUDE}}",
+        include_str!("./templates/fragment_prelude.wgsl"),
+    );
+
+    if include_debugger {
+        let debbuger_str = include_str!("./templates/debugger.wgsl");
+        shader_content = shader_content.replace("{{DEBUGGER}}", debbuger_str);
+    } else {
+        shader_content = shader_content.replace("{{DEBUGGER}}", "");
+    }
+
+    if !uses_main_image(code_block) {
+        let stub = "fn mainImage(fragCoord: vec2<f32>) -> vec4<f32> {\n    return vec4<f32>(0.0);\n}";
+        let shader_content = shader_content
+            .replace("{{COMMON}}", "")
+            .replace("{{CODE_BLOCK}}", stub);
+        return (shader_content, false);
+    }
+
+    let shader_content = shader_content
+        .replace("{{COMMON}}", common)
+        .replace("{{CODE_BLOCK}}", code_block);
+
+    (shader_content, true)
+}
+
+// A project can run on the fragment backend if all of its passes are mainImage functions,
+// save for the buffers that are not active
+pub fn fragment_runnable(fragment_passes: [bool; 5], active_passes: &ActivePasses) -> bool {
+    let active_buffers = active_passes.as_array();
+    (0..5).all(|pass| fragment_passes[pass] || (pass < 4 && !active_buffers[pass]))
+}
+
+// Loads the fragment variants of the passes, indexed as in PassUsages::get, if the project
+// can run on the fragment backend: the image pass and every active buffer pass must be
+// written as a mainImage function.
+pub fn load_fragment_shaders(
+    asset_server: &AssetServer,
+    folder: &str,
+    fragment_passes: [bool; 5],
+    active_passes: &ActivePasses,
+) -> Option<[Handle<Shader>; 5]> {
+    if !fragment_runnable(fragment_passes, active_passes) {
+        info!(
+            "the project is not written as mainImage functions or declares storage buffers, \
+             it needs the compute backend"
+        );
+        return None;
+    }
+
+    Some([0, 1, 2, 3, 4].map(|pass| {
+        asset_server.load(&format!("{}/{}_fragment.wgsl", folder, pass_name(pass)))
+    }))
+}
+
+#[derive(Resource)]
+pub struct FragmentPipelines {
+    // uniform at binding 0, then the four buffers at bindings 1 to 4
+    pub buffer_layout: BindGroupLayout,
+    // the same, followed by the font, the rgba noise and the blue noise at bindings 6 to 11
+    pub image_layout: BindGroupLayout,
+}
+
+impl FromWorld for FragmentPipelines {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let mut entries = vec![BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(std::mem::size_of::<f32>() as u64 * 25),
+            },
+            count: None,
+        }];
+
+        // rgba32float cannot be filtered on every adapter
+        for binding in 1..5 {
+            entries.push(BindGroupLayoutEntry {
+                binding,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+        }
+
+        let buffer_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("fragment_buffer_layout"),
+            entries: &entries,
+        });
+
+        for texture in 0..3 {
+            entries.push(BindGroupLayoutEntry {
+                binding: 6 + 2 * texture,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+            entries.push(BindGroupLayoutEntry {
+                binding: 7 + 2 * texture,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+
+        let image_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("fragment_image_layout"),
+            entries: &entries,
+        });
+
+        FragmentPipelines {
+            buffer_layout,
+            image_layout,
+        }
+    }
+}
+
+// the image pass renders in the format of the main image, the buffers in rgba32float
+fn target_format(pass: usize) -> TextureFormat {
+    match pass {
+        4 => MAIN_IMAGE_FORMAT,
+        _ => TextureFormat::Rgba32Float,
+    }
+}
+
+fn fragment_pipeline_descriptor(
+    layout: &BindGroupLayout,
+    shader: &Handle<Shader>,
+    format: TextureFormat,
+    init: bool,
+) -> RenderPipelineDescriptor {
+    let shader_defs = match init {
+        true => vec!["INIT".to_string()],
+        false => vec![],
+    };
+
+    RenderPipelineDescriptor {
+        label: Some(Cow::from("fragment_pass_pipeline")),
+        layout: Some(vec![layout.clone()]),
+        vertex: fullscreen_shader_vertex_state(),
+        fragment: Some(FragmentState {
+            shader: shader.clone(),
+            shader_defs,
+            entry_point: Cow::from("fragment"),
+            targets: vec![Some(ColorTargetState {
+                format,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+    }
+}
+
+pub struct FragmentPass {
+    shader: Handle<Shader>,
+    init_pipeline: CachedRenderPipelineId,
+    update_pipeline: CachedRenderPipelineId,
+    bind_group: BindGroup,
+    bind_group_key: BindGroupKey,
+    // The pass renders into this and it is then copied into the buffer of the pass, since
+    // the buffer is bound to the pass as well. It has the size of the buffer. Ping-ponging
+    // two textures per buffer would save the copy, but would swap the texture behind the
+    // image of the buffer every frame, while the materials, captures, uploads, camera
+    // inputs and the resample node all hold that one image. The copy moves the texels
+    // without any shading, which costs little next to the pass that rendered them.
+    target: Texture,
+    target_view: TextureView,
+    size: Vec2,
+}
+
+#[derive(Resource, Default)]
+pub struct FragmentPasses {
+    // indexed as in PassUsages::get, None for the inactive passes
+    passes: [Option<FragmentPass>; 5],
+    // the active passes, in the order of pass_order as on the compute backend
+    order: Vec<usize>,
+}
+
+fn create_target(
+    render_device: &RenderDevice,
+    size: Vec2,
+    format: TextureFormat,
+) -> (Texture, TextureView) {
+    let target = render_device.create_texture(&TextureDescriptor {
+        label: Some("fragment_pass_target"),
+        size: Extent3d {
+            width: size.x as u32,
+            height: size.y as u32,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+    });
+    let target_view = target.create_view(&TextureViewDescriptor::default());
+
+    (target, target_view)
+}
+
+fn make_fragment_bind_group_entries<'a>(
+    common_uniform_buffer: &'a Buffer,
+    buffers: &[&'a GpuImage],
+    textures: Option<[&'a GpuImage; 3]>,
+) -> Vec<BindGroupEntry<'a>> {
+    let mut entries = vec![BindGroupEntry {
+        binding: 0,
+        resource: common_uniform_buffer.as_entire_binding(),
+    }];
+
+    for (buffer, image) in buffers.iter().enumerate() {
+        entries.push(BindGroupEntry {
+            binding: buffer as u32 + 1,
+            resource: BindingResource::TextureView(&image.texture_view),
+        });
+    }
+
+    for (index, texture) in textures.into_iter().flatten().enumerate() {
+        entries.push(BindGroupEntry {
+            binding: 6 + 2 * index as u32,
+            resource: BindingResource::TextureView(&texture.texture_view),
+        });
+        entries.push(BindGroupEntry {
+            binding: 7 + 2 * index as u32,
+            resource: BindingResource::Sampler(&texture.sampler),
+        });
+    }
+
+    entries
+}
+
+// Queues the pipelines of the fragment variants and keeps the bind groups and the render
+// targets in sync with the buffers
+pub fn queue_fragment_passes(
+    mut passes: ResMut<FragmentPasses>,
+    pipelines: Res<FragmentPipelines>,
+    all_shader_handles: Res<ShaderHandles>,
+    common_uniform_meta: Res<CommonUniformMeta>,
+    gpu_images: Res<RenderAssets<Image>>,
+    shadertoy_textures: Res<ShadertoyTextures>,
+    main_image: Res<MainImage>,
+    texture_a_image: Res<TextureA>,
+    texture_b_image: Res<TextureB>,
+    texture_c_image: Res<TextureC>,
+    texture_d_image: Res<TextureD>,
+    render_device: Res<RenderDevice>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    rebuild_counters: Res<ShadertoyRebuildCounters>,
+    mut reported: Local<bool>,
+) {
+    let shaders = match &all_shader_handles.fragment_shaders {
+        Some(shaders) => shaders,
+        None => {
+            if !*reported {
+                error!(
+                    "the fragment backend needs the image pass and the active buffer passes to \
+                     be written as mainImage functions, nothing will be rendered"
+                );
+                *reported = true;
+            }
+            *passes = FragmentPasses::default();
+            return;
+        }
+    };
+    *reported = false;
+
+    let outputs = [
+        &texture_a_image.0,
+        &texture_b_image.0,
+        &texture_c_image.0,
+        &texture_d_image.0,
+        &main_image.0,
+    ];
+    let images = match outputs.map(|handle| gpu_images.get(handle)) {
+        [Some(a), Some(b), Some(c), Some(d), Some(main_image)] => [a, b, c, d, main_image],
+        _ => return,
+    };
+    let textures = [
+        &shadertoy_textures.font_texture_handle,
+        &shadertoy_textures.rgba_noise_256_handle,
+        &shadertoy_textures.blue_noise_handle,
+    ];
+    let textures = match textures.map(|handle| gpu_images.get(handle)) {
+        [Some(font), Some(rgba_noise), Some(blue_noise)] => [font, rgba_noise, blue_noise],
+        _ => return,
+    };
+
+    let active_buffers = all_shader_handles.active_passes.as_array();
+    let active: Vec<usize> = (0..5)
+        .filter(|pass| *pass == 4 || active_buffers[*pass])
+        .collect();
+    passes.order = pass_order(&active, &all_shader_handles.pass_usages);
+
+    for (pass, shader) in shaders.iter().enumerate() {
+        if pass < 4 && !active_buffers[pass] {
+            passes.passes[pass] = None;
+            continue;
+        }
+
+        let (layout, pass_textures) = match pass {
+            4 => (&pipelines.image_layout, Some(textures)),
+            _ => (&pipelines.buffer_layout, None),
+        };
+
+        let mut textures_in_bind_group: Vec<&Texture> =
+            images[..4].iter().map(|image| &image.texture).collect();
+        textures_in_bind_group.extend(
+            pass_textures
+                .iter()
+                .flatten()
+                .map(|texture| &texture.texture),
+        );
+        let bind_group_key = BindGroupKey::new(layout, &textures_in_bind_group);
+
+        let cached = passes.passes[pass].take();
+
+        let (init_pipeline, update_pipeline) = match &cached {
+            Some(cached) if cached.shader == *shader => {
+                (cached.init_pipeline, cached.update_pipeline)
+            }
+            _ => {
+                rebuild_counters.count_pipeline_rebuild();
+                let format = target_format(pass);
+                (
+                    pipeline_cache.queue_render_pipeline(fragment_pipeline_descriptor(
+                        layout, shader, format, true,
+                    )),
+                    pipeline_cache.queue_render_pipeline(fragment_pipeline_descriptor(
+                        layout, shader, format, false,
+                    )),
+                )
+            }
+        };
+
+        let bind_group = match &cached {
+            Some(cached) if cached.bind_group_key == bind_group_key => cached.bind_group.clone(),
+            _ => {
+                rebuild_counters.count_bind_group_rebuild();
+
+                let entries = make_fragment_bind_group_entries(
+                    &common_uniform_meta.buffer,
+                    &images[..4],
+                    pass_textures,
+                );
+
+                render_device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("fragment_bind_group"),
+                    layout,
+                    entries: &entries,
+                })
+            }
+        };
+
+        let size = images[pass].size;
+        let (target, target_view) = match cached {
+            Some(cached) if cached.size == size => (cached.target, cached.target_view),
+            _ => create_target(&render_device, size, target_format(pass)),
+        };
+
+        passes.passes[pass] = Some(FragmentPass {
+            shader: shader.clone_weak(),
+            init_pipeline,
+            update_pipeline,
+            bind_group,
+            bind_group_key,
+            target,
+            target_view,
+            size,
+        });
+    }
+}
+
+// Runs every pass of the fragment backend in the order of pass_order, as the compute
+// backend does, so that a pass sees what the passes before it wrote in the current frame
+pub struct FragmentPassesNode {
+    states: [ShadertoyState; 5],
+    run_states: [PassRunState; 5],
+}
+
+impl Default for FragmentPassesNode {
+    fn default() -> Self {
+        Self {
+            states: [ShadertoyState::Loading; 5],
+            run_states: Default::default(),
+        }
+    }
+}
+
+impl render_graph::Node for FragmentPassesNode {
+    fn update(&mut self, world: &mut World) {
+        // the content of the buffers is carried over by the resample node, so the passes
+        // only go through INIT again when asked to
+        let reset = world.resource::<ShadertoyReset>().0
+            || (world.resource::<ChangedWindowSize>().0
+                && world.resource::<ShadertoyResizePolicies>().reinit_on_resize);
+
+        for pass in 0..5 {
+            let pipelines = world.resource::<FragmentPasses>().passes[pass]
+                .as_ref()
+                .map(|fragment_pass| (fragment_pass.init_pipeline, fragment_pass.update_pipeline));
+
+            let (init_pipeline, update_pipeline) = match pipelines {
+                Some(pipelines) => pipelines,
+                None => {
+                    self.states[pass] = ShadertoyState::Loading;
+                    self.run_states[pass].dispatch = false;
+                    // inactive buffer passes are not waited for
+                    if pass == 4 {
+                        world.resource_mut::<ShadertoyLoading>().0 = true;
+                    }
+                    continue;
+                }
+            };
+
+            if reset {
+                self.states[pass] = ShadertoyState::Loading;
+            }
+
+            let pipeline_cache = world.resource::<PipelineCache>();
+            let pipeline = match self.states[pass] {
+                ShadertoyState::Loading => Some(init_pipeline),
+                ShadertoyState::Init => Some(update_pipeline),
+                ShadertoyState::Update => None,
+            };
+            let pipeline_state =
+                pipeline.map(|pipeline| pipeline_cache.get_render_pipeline_state(pipeline));
+
+            if let Some(CachedPipelineState::Ok(_)) = pipeline_state {
+                self.states[pass] = match self.states[pass] {
+                    ShadertoyState::Loading => ShadertoyState::Init,
+                    _ => ShadertoyState::Update,
+                };
+            }
+
+            self.run_states[pass].update(pass_name(pass), &self.states[pass], world);
+        }
+    }
+
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        if !world.resource::<ShadertoyCanvas>().active {
+            return Ok(());
+        }
+
+        let passes = world.resource::<FragmentPasses>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+
+        let outputs = [
+            &world.resource::<TextureA>().0,
+            &world.resource::<TextureB>().0,
+            &world.resource::<TextureC>().0,
+            &world.resource::<TextureD>().0,
+            &world.resource::<MainImage>().0,
+        ];
+
+        // the pipeline and the buffer of a pass, if they are ready for the pipeline of its state
+        let prepared = |index: usize| {
+            let pass = passes.passes[index].as_ref()?;
+            let pipeline = match self.states[index] {
+                ShadertoyState::Loading => return None,
+                ShadertoyState::Init => pass.init_pipeline,
+                ShadertoyState::Update => pass.update_pipeline,
+            };
+
+            match (
+                pipeline_cache.get_render_pipeline(pipeline),
+                gpu_images.get(outputs[index]),
+            ) {
+                // a resized buffer gets its render target in the next frame
+                (Some(pipeline), Some(output)) if output.size == pass.size => {
+                    Some((pass, pipeline, output))
+                }
+                _ => None,
+            }
+        };
+
+        // the steps of a fast-forward before the last one run every buffer pass in turn, each
+        // step with its own uniform, see FastForwardNode
+        let uniforms = world.resource::<FastForwardUniforms>();
+        if uniforms.earlier_steps > 0 {
+            let common_uniform_meta = world.resource::<CommonUniformMeta>();
+            for step in 0..uniforms.earlier_steps {
+                uniforms.copy_step(
+                    step,
+                    common_uniform_meta,
+                    &mut render_context.command_encoder,
+                );
+                for index in passes.order.iter().copied().filter(|index| *index < 4) {
+                    let dispatched = self.run_states[index]
+                        .earlier_steps
+                        .get(step as usize)
+                        .copied()
+                        .unwrap_or(false);
+                    if let (true, Some((pass, pipeline, output))) = (dispatched, prepared(index)) {
+                        draw_fragment_pass(
+                            pass,
+                            pipeline,
+                            output,
+                            &mut render_context.command_encoder,
+                        );
+                    }
+                }
+            }
+            uniforms.copy_step(
+                uniforms.earlier_steps,
+                common_uniform_meta,
+                &mut render_context.command_encoder,
+            );
+        }
+
+        for index in passes.order.iter().copied() {
+            if !self.run_states[index].dispatch {
+                continue;
+            }
+            if let Some((pass, pipeline, output)) = prepared(index) {
+                draw_fragment_pass(pass, pipeline, output, &mut render_context.command_encoder);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Renders a pass into its render target and copies it into the buffer of the pass
+fn draw_fragment_pass(
+    pass: &FragmentPass,
+    pipeline: &RenderPipeline,
+    output: &GpuImage,
+    command_encoder: &mut CommandEncoder,
+) {
+    let pass_descriptor = RenderPassDescriptor {
+        label: Some("fragment_pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: &pass.target_view,
+            resolve_target: None,
+            ops: Operations::default(),
+        })],
+        depth_stencil_attachment: None,
+    };
+
+    {
+        let mut render_pass =
+            TrackedRenderPass::new(command_encoder.begin_render_pass(&pass_descriptor));
+
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &pass.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    command_encoder.copy_texture_to_texture(
+        ImageCopyTexture {
+            texture: &pass.target,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        ImageCopyTexture {
+            texture: &output.texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        Extent3d {
+            width: pass.size.x as u32,
+            height: pass.size.y as u32,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN_IMAGE: &str = "fn mainImage(fragCoord: vec2<f32>) -> vec4<f32> {
+    return load_buffer_b(vec2<i32>(fragCoord) + vec2<i32>(1, 0));
+}";
+
+    #[test]
+    fn keeps_update_passes() {
+        let code_block = "@compute @workgroup_size(8, 8, 1)\nfn update() {}\n";
+        assert_eq!(
+            compute_code_block("buffer_a", "", code_block),
+            Ok(code_block.to_string())
+        );
+    }
+
+    #[test]
+    fn generates_the_entry_point_and_the_loaders_used() {
+        let code_block = compute_code_block("buffer_a", "", MAIN_IMAGE).unwrap();
+
+        assert!(code_block.contains(
+            "fn load_buffer_b(location: vec2<i32>) -> vec4<f32> {\n    return textureLoad(buffer_b, location);\n}"
+        ));
+        assert!(!code_block.contains("fn load_buffer_a("));
+        assert!(code_block.ends_with(
+            "@compute @workgroup_size(8, 8, 1)\nfn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {\n    let location = vec2<i32>(invocation_id.xy);\n    textureStore(buffer_a, location, mainImage(vec2<f32>(location) + 0.5));\n}\n"
+        ));
+    }
+
+    #[test]
+    fn writes_the_image_pass_into_the_main_image() {
+        let code_block = compute_code_block("image", "", MAIN_IMAGE).unwrap();
+        assert!(code_block.contains("textureStore(texture, location,"));
+    }
+
+    #[test]
+    fn adds_the_loaders_used_by_the_common_code() {
+        let common = "fn previous(p: vec2<i32>) -> vec4<f32> { return load_buffer_d(p); }";
+        let code_block = compute_code_block("buffer_a", common, MAIN_IMAGE).unwrap();
+        assert!(code_block.contains("fn load_buffer_d("));
+    }
+
+    #[test]
+    fn accepts_reading_the_own_pixel() {
+        let code_block = "fn mainImage(fragCoord: vec2<f32>) -> vec4<f32> {
+    return load_buffer_a(vec2<i32>( fragCoord )) * 0.98;
+}";
+        assert!(compute_code_block("buffer_a", "", code_block).is_ok());
+    }
+
+    #[test]
+    fn rejects_reading_the_own_buffer_elsewhere() {
+        assert!(compute_code_block("buffer_b", "", MAIN_IMAGE).is_err());
+
+        let common = "fn previous(p: vec2<i32>) -> vec4<f32> { return load_buffer_a(p); }";
+        assert!(compute_code_block("buffer_a", common, MAIN_IMAGE).is_err());
+    }
+}
@@ -10,7 +10,7 @@ use bevy::{
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
-        render_asset::RenderAssets,
+        render_asset::{PrepareAssetLabel, RenderAssets},
         render_graph::{self, NodeLabel, RenderGraph},
         // render_resource::ShaderSize,
         // render_resource::*,
@@ -18,12 +18,13 @@ use bevy::{
             encase::private::WriteInto, BindGroup, BindGroupDescriptor, BindGroupEntry,
             BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource,
             BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages,
-            CachedComputePipelineId, CachedPipelineState, ComputePassDescriptor,
-            ComputePipelineDescriptor, Extent3d, PipelineCache, SamplerBindingType, ShaderStages,
-            ShaderType, StorageTextureAccess, TextureDimension, TextureFormat, TextureSampleType,
-            TextureUsages, TextureViewDimension,
+            CachedComputePipelineId, CachedPipelineState, ComputePassDescriptor, Extent3d,
+            PipelineCache, SamplerBindingType, ShaderStages, ShaderType, StorageTextureAccess,
+            TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+            TextureViewDimension,
         },
-        renderer::{RenderContext, RenderDevice, RenderQueue},
+        renderer::{RenderAdapter, RenderContext, RenderDevice, RenderQueue},
+        texture::GpuImage,
         MainWorld,
         RenderApp,
         RenderStage,
@@ -33,27 +34,119 @@ use bevy::{
 
 use crevice::std140::AsStd140;
 
-use bevy::{app::ScheduleRunnerSettings, utils::Duration};
+use bevy::asset::load_internal_asset;
 
 use std::borrow::Cow;
+#[cfg(feature = "native")]
 use std::fs; // not compatible with WASM -->
 
 mod texture_a;
+pub use texture_a::TextureA;
 use texture_a::*;
 
 mod texture_b;
+pub use texture_b::TextureB;
 use texture_b::*;
 
 mod texture_c;
+pub use texture_c::TextureC;
 use texture_c::*;
 
 mod texture_d;
+pub use texture_d::TextureD;
 use texture_d::*;
 
+mod schedule;
+pub use schedule::*;
+
+mod reflect;
+pub use reflect::*;
+
+mod workgroup;
+pub use workgroup::*;
+
+mod resize;
+pub use resize::*;
+
+mod cache;
+pub use cache::*;
+
+mod command;
+pub use command::*;
+
+mod clock;
+pub use clock::*;
+
+mod deterministic;
+pub use deterministic::*;
+
+mod capture;
+pub use capture::*;
+
+#[cfg(feature = "native")]
+mod offline;
+#[cfg(feature = "native")]
+pub use offline::*;
+
+#[cfg(feature = "native")]
+mod recorder;
+#[cfg(feature = "native")]
+pub use recorder::*;
+
+#[cfg(feature = "native")]
+mod screenshot;
+#[cfg(feature = "native")]
+pub use screenshot::*;
+
+mod readback;
+pub use readback::*;
+
+mod upload;
+pub use upload::*;
+
+#[cfg(feature = "native")]
+mod snapshot;
+#[cfg(feature = "native")]
+pub use snapshot::*;
+
+mod material;
+pub use material::*;
+
+mod output;
+pub use output::*;
+
+mod camera_input;
+pub use camera_input::*;
+
+mod post_process;
+pub use post_process::*;
+
+mod fragment;
+pub use fragment::*;
+
+mod project;
+pub use project::*;
+
+mod storage;
+pub use storage::*;
+
+mod draw;
+pub use draw::*;
+
+mod fast_forward;
+pub use fast_forward::*;
+
+// default workgroup size of the passes, see PassWorkgroupSizes
 pub const WORKGROUP_SIZE: u32 = 8;
-pub const NUM_PARTICLES: u32 = 256;
+
+// WebGPU cannot filter rgba32float textures, and the main image is shown through a sprite
+#[cfg(feature = "wasm")]
+pub const MAIN_IMAGE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+#[cfg(not(feature = "wasm"))]
+pub const MAIN_IMAGE_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
 // pub const BORDERS: f32 = 1.0;
 
+// the one canvas of the app, which the buffers, the passes and the main image follow
 #[derive(Clone, Resource, ExtractResource, Debug)]
 pub struct ShadertoyCanvas {
     pub width: u32,
@@ -81,12 +174,10 @@ fn setup(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     canvas: Res<ShadertoyCanvas>,
+    output: Res<ShadertoyOutput>,
 
     asset_server: Res<AssetServer>,
-    windows: Res<Windows>,
 ) {
-    // let window = windows.primary();
-
     let mut image = Image::new_fill(
         Extent3d {
             width: canvas.width,
@@ -95,32 +186,39 @@ fn setup(
         },
         TextureDimension::D2,
         &[0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0],
-        TextureFormat::Rgba32Float,
+        MAIN_IMAGE_FORMAT,
     );
-    image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-
-    let image = images.add(image);
+    // COPY_SRC lets the main image be read back, e.g. by the offline renderer
+    image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
+
+    let image = match &*output {
+        ShadertoyOutput::Image(target) => match images.get_mut(target) {
+            Some(target_image) => {
+                *target_image = image;
+                target.clone()
+            }
+            None => {
+                error!("the output image is not in Assets<Image>, rendering into a new one");
+                images.add(image)
+            }
+        },
+        _ => images.add(image),
+    };
 
     commands.insert_resource(ChangedWindowSize(false));
 
     commands.insert_resource(MainImage(image.clone()));
 
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(canvas.width as f32, canvas.height as f32)),
-            ..default()
-        },
-        texture: image.clone(),
-        // // the y axis of a bevy window is flipped compared to shadertoy. We fix it
-        // // by rotating the sprite 180 degrees, but this comes at the cost of a mirrored
-        // // image in the x axis.
-        // transform: Transform::from_rotation(bevy::math::Quat::from_rotation_z(
-        //     core::f32::consts::PI,
-        // )),
-        transform: Transform::from_translation(canvas.position),
-        ..default()
-    });
+    spawn_output(
+        &mut commands,
+        &output,
+        &image,
+        Vec2::new(canvas.width as f32, canvas.height as f32),
+        canvas.position,
+    );
 
     let font_texture_handle: Handle<Image> = asset_server.load("textures/font.png");
     let rgba_noise_256_handle: Handle<Image> = asset_server.load("textures/rgba_noise_256.png");
@@ -132,11 +230,10 @@ fn setup(
         blue_noise_handle,
     });
 
-    let window = windows.primary();
     let mut common_uniform = CommonUniform::new();
 
-    common_uniform.i_resolution.x = window.width();
-    common_uniform.i_resolution.y = window.height();
+    common_uniform.i_resolution.x = canvas.width as f32;
+    common_uniform.i_resolution.y = canvas.height as f32;
     commands.insert_resource(common_uniform);
 
     //
@@ -154,8 +251,10 @@ fn setup(
         &[0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0],
         TextureFormat::Rgba32Float,
     );
-    texture_a.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    texture_a.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
 
     let texture_a = images.add(texture_a);
 
@@ -176,8 +275,10 @@ fn setup(
         &[0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0],
         TextureFormat::Rgba32Float,
     );
-    texture_b.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    texture_b.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
 
     let texture_b = images.add(texture_b);
 
@@ -198,8 +299,10 @@ fn setup(
         &[0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0],
         TextureFormat::Rgba32Float,
     );
-    texture_c.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    texture_c.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
 
     let texture_c = images.add(texture_c);
 
@@ -220,8 +323,10 @@ fn setup(
         &[0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0],
         TextureFormat::Rgba32Float,
     );
-    texture_d.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    texture_d.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
 
     let texture_d = images.add(texture_d);
 
@@ -267,23 +372,85 @@ pub fn make_and_load_shaders(example: &str, asset_server: &Res<AssetServer>) ->
         texture_b_shader,
         texture_c_shader,
         texture_d_shader,
+        active_passes: ActivePasses::default(),
+        pass_usages: PassUsages::default(),
+        workgroup_sizes: PassWorkgroupSizes::default(),
+        fragment_shaders: None,
+        storage: ProjectStorage::default(),
+        dispatches: Default::default(),
+        draw_shader: None,
+        vertex_art_shader: None,
     }
 }
 
+// reads and writes the project on the filesystem, see ShadertoyActiveProject for wasm
+#[cfg(feature = "native")]
 pub fn make_and_load_shaders2(
     example: &str,
     asset_server: &Res<AssetServer>,
     include_debugger: bool,
+) -> ShaderHandles {
+    make_and_load_shaders2_with_workgroup_sizes(
+        example,
+        asset_server,
+        include_debugger,
+        PassWorkgroupSizes::default(),
+    )
+}
+
+#[cfg(feature = "native")]
+pub fn make_and_load_shaders2_with_workgroup_sizes(
+    example: &str,
+    asset_server: &Res<AssetServer>,
+    include_debugger: bool,
+    workgroup_sizes: PassWorkgroupSizes,
 ) -> ShaderHandles {
     // let image_shader_handle = asset_server.load(&format!("shaders/{}/image.wgsl", example));
     // let example_string = example.to_string();
     //
 
-    format_and_save_shader(example, "image", include_debugger);
-    format_and_save_shader(example, "buffer_a", false);
-    format_and_save_shader(example, "buffer_b", false);
-    format_and_save_shader(example, "buffer_c", false);
-    format_and_save_shader(example, "buffer_d", false);
+    let workgroup_sizes = workgroup_sizes.validated();
+    let storage = read_project_storage(&format!("./examples/{}/storage.wgsl", example));
+    let image = format_and_save_shader(
+        example,
+        "image",
+        include_debugger,
+        workgroup_sizes.image,
+        &storage,
+    );
+    let buffers = BUFFER_NAMES.map(|buffer_type| {
+        format_and_save_shader(
+            example,
+            buffer_type,
+            false,
+            workgroup_sizes.get(buffer_type),
+            &storage,
+        )
+    });
+    let pass_usages = PassUsages {
+        image: image.usage,
+        buffers: [0, 1, 2, 3].map(|pass| buffers[pass].usage),
+    };
+    let active_passes = find_active_passes(&pass_usages);
+
+    let [a, b, c, d] = [0, 1, 2, 3].map(|pass| buffers[pass].fragment_runnable);
+    let fragment_shaders = load_fragment_shaders(
+        asset_server,
+        &format!("./shaders/{}", example),
+        [a, b, c, d, image.fragment_runnable],
+        &active_passes,
+    );
+    let [a, b, c, d] = [0, 1, 2, 3].map(|pass| buffers[pass].dispatch);
+    let dispatches = [a, b, c, d, image.dispatch];
+
+    let project_folder = format!("./examples/{}", example);
+    let build_folder = format!("./assets/shaders/{}", example);
+    let load_draw_shader = |file: &str, assemble: AssembleDrawShader| {
+        format_and_save_draw_shader(&project_folder, &build_folder, file, &storage, assemble)
+            .then(|| asset_server.load(&format!("./shaders/{}/{}", example, file)))
+    };
+    let draw_shader = load_draw_shader("draw.wgsl", assemble_draw_shader);
+    let vertex_art_shader = load_draw_shader("vertex.wgsl", assemble_vertex_art_shader);
 
     let image_shader_handle = asset_server.load(&format!("./shaders/{}/image.wgsl", example));
     let texture_a_shader = asset_server.load(&format!("./shaders/{}/buffer_a.wgsl", example));
@@ -297,19 +464,81 @@ pub fn make_and_load_shaders2(
         texture_b_shader,
         texture_c_shader,
         texture_d_shader,
+        active_passes,
+        pass_usages,
+        workgroup_sizes,
+        fragment_shaders,
+        storage,
+        dispatches,
+        draw_shader,
+        vertex_art_shader,
     }
 }
 
+#[cfg(feature = "native")]
 pub fn make_and_load_shaders3(
     shadertoy_name: &str,
     asset_server: &Res<AssetServer>,
     include_debugger: bool,
 ) -> ShaderHandles {
-    format_and_save_shader2(shadertoy_name, "image", include_debugger);
-    format_and_save_shader2(shadertoy_name, "buffer_a", false);
-    format_and_save_shader2(shadertoy_name, "buffer_b", false);
-    format_and_save_shader2(shadertoy_name, "buffer_c", false);
-    format_and_save_shader2(shadertoy_name, "buffer_d", false);
+    make_and_load_shaders3_with_workgroup_sizes(
+        shadertoy_name,
+        asset_server,
+        include_debugger,
+        PassWorkgroupSizes::default(),
+    )
+}
+
+#[cfg(feature = "native")]
+pub fn make_and_load_shaders3_with_workgroup_sizes(
+    shadertoy_name: &str,
+    asset_server: &Res<AssetServer>,
+    include_debugger: bool,
+    workgroup_sizes: PassWorkgroupSizes,
+) -> ShaderHandles {
+    let workgroup_sizes = workgroup_sizes.validated();
+    let storage =
+        read_project_storage(&format!("assets/shadertoy/{}/storage.wgsl", shadertoy_name));
+    let image = format_and_save_shader2(
+        shadertoy_name,
+        "image",
+        include_debugger,
+        workgroup_sizes.image,
+        &storage,
+    );
+    let buffers = BUFFER_NAMES.map(|buffer_type| {
+        format_and_save_shader2(
+            shadertoy_name,
+            buffer_type,
+            false,
+            workgroup_sizes.get(buffer_type),
+            &storage,
+        )
+    });
+    let pass_usages = PassUsages {
+        image: image.usage,
+        buffers: [0, 1, 2, 3].map(|pass| buffers[pass].usage),
+    };
+    let active_passes = find_active_passes(&pass_usages);
+
+    let [a, b, c, d] = [0, 1, 2, 3].map(|pass| buffers[pass].fragment_runnable);
+    let fragment_shaders = load_fragment_shaders(
+        asset_server,
+        &format!("shadertoy/{}/build", shadertoy_name),
+        [a, b, c, d, image.fragment_runnable],
+        &active_passes,
+    );
+    let [a, b, c, d] = [0, 1, 2, 3].map(|pass| buffers[pass].dispatch);
+    let dispatches = [a, b, c, d, image.dispatch];
+
+    let project_folder = format!("assets/shadertoy/{}", shadertoy_name);
+    let build_folder = format!("./assets/shadertoy/{}/build", shadertoy_name);
+    let load_draw_shader = |file: &str, assemble: AssembleDrawShader| {
+        format_and_save_draw_shader(&project_folder, &build_folder, file, &storage, assemble)
+            .then(|| asset_server.load(&format!("shadertoy/{}/build/{}", shadertoy_name, file)))
+    };
+    let draw_shader = load_draw_shader("draw.wgsl", assemble_draw_shader);
+    let vertex_art_shader = load_draw_shader("vertex.wgsl", assemble_vertex_art_shader);
 
     let image_shader_handle = asset_server.load(&format!("shadertoy/{}/build/image.wgsl", shadertoy_name));
     let texture_a_shader = asset_server.load(&format!("shadertoy/{}/build/buffer_a.wgsl", shadertoy_name));
@@ -323,31 +552,36 @@ pub fn make_and_load_shaders3(
         texture_b_shader,
         texture_c_shader,
         texture_d_shader,
+        active_passes,
+        pass_usages,
+        workgroup_sizes,
+        fragment_shaders,
+        storage,
+        dispatches,
+        draw_shader,
+        vertex_art_shader,
     }
 }
 
-// This function uses the std library and isn't compatible with wasm
-fn format_and_save_shader(example: &str, buffer_type: &str, include_debugger: bool) {
-    let common_prelude = include_str!("./templates/common_prelude.wgsl");
-
-    let template = match buffer_type {
-        "image" => include_str!("./templates/image_template.wgsl"),
-        "buffer_a" => include_str!("./templates/buffer_a_template.wgsl"),
-        "buffer_b" => include_str!("./templates/buffer_b_template.wgsl"),
-        "buffer_c" => include_str!("./templates/buffer_c_template.wgsl"),
-        "buffer_d" => include_str!("./templates/buffer_d_template.wgsl"),
-        _ => include_str!("./templates/buffer_d_template.wgsl"),
-    };
-
-    let mut shader_content = template.replace("{{COMMON_PRELUDE}}", common_prelude);
-
-    if include_debugger {
-        let debbuger_str = include_str!("./templates/debugger.wgsl");
-        shader_content = shader_content.replace("{{DEBUGGER}}", debbuger_str);
-    } else {
-        shader_content = shader_content.replace("{{DEBUGGER}}", "");
+// storage.wgsl is optional, a project without it has no storage buffers
+#[cfg(feature = "native")]
+fn read_project_storage(path: &str) -> ProjectStorage {
+    match fs::read_to_string(path) {
+        Ok(source) => reflect_project_storage(&source),
+        Err(_) => ProjectStorage::default(),
     }
+}
 
+// This function uses the std library and isn't compatible with wasm
+// Returns what was reflected from the pass, the fragment variant is saved next to it
+#[cfg(feature = "native")]
+fn format_and_save_shader(
+    example: &str,
+    buffer_type: &str,
+    include_debugger: bool,
+    workgroup_size: WorkgroupSize,
+    storage: &ProjectStorage,
+) -> AssembledPass {
     let path_to_code_block = format!("./examples/{}/{}.wgsl", example, buffer_type);
     let path_to_common = format!("./examples/{}/common.wgsl", example);
     println!("common: {}", path_to_common);
@@ -355,49 +589,88 @@ fn format_and_save_shader(example: &str, buffer_type: &str, include_debugger: bo
     let common = fs::read_to_string(path_to_common).expect("could not read file.");
     let image_main = fs::read_to_string(path_to_code_block).expect("could not read file.");
 
-    let mut shader_content = shader_content.replace("{{COMMON}}", &common);
-    shader_content = shader_content.replace("{{CODE_BLOCK}}", &image_main);
+    let pass = assemble_pass(
+        buffer_type,
+        &common,
+        &image_main,
+        include_debugger,
+        workgroup_size,
+        storage,
+    );
     let folder = format!("./assets/shaders/{}", example);
     let path = format!("{}/{}.wgsl", folder, buffer_type);
     println!("{}", path);
-    let _ = fs::create_dir(folder);
-    fs::write(path, shader_content).expect("Unable to write file");
-}
+    let _ = fs::create_dir(&folder);
+    fs::write(path, &pass.compute).expect("Unable to write file");
 
-// This function uses the std library and isn't compatible with wasm
-fn format_and_save_shader2(shadertoy_name: &str, buffer_type: &str, include_debugger: bool) {
-    let common_prelude = include_str!("./templates/common_prelude.wgsl");
-
-    let template = match buffer_type {
-        "image" => include_str!("./templates/image_template.wgsl"),
-        "buffer_a" => include_str!("./templates/buffer_a_template.wgsl"),
-        "buffer_b" => include_str!("./templates/buffer_b_template.wgsl"),
-        "buffer_c" => include_str!("./templates/buffer_c_template.wgsl"),
-        "buffer_d" => include_str!("./templates/buffer_d_template.wgsl"),
-        _ => include_str!("./templates/buffer_d_template.wgsl"),
-    };
-
-    let mut shader_content = template.replace("{{COMMON_PRELUDE}}", common_prelude);
+    let fragment_path = format!("{}/{}_fragment.wgsl", folder, buffer_type);
+    fs::write(fragment_path, &pass.fragment).expect("Unable to write file");
 
-    if include_debugger {
-        let debbuger_str = include_str!("./templates/debugger.wgsl");
-        shader_content = shader_content.replace("{{DEBUGGER}}", debbuger_str);
-    } else {
-        shader_content = shader_content.replace("{{DEBUGGER}}", "");
-    }
+    pass
+}
 
+// This function uses the std library and isn't compatible with wasm
+// Returns what was reflected from the pass, the fragment variant is saved next to it
+#[cfg(feature = "native")]
+fn format_and_save_shader2(
+    shadertoy_name: &str,
+    buffer_type: &str,
+    include_debugger: bool,
+    workgroup_size: WorkgroupSize,
+    storage: &ProjectStorage,
+) -> AssembledPass {
     let path_to_code_block = format!("assets/shadertoy/{}/{}.wgsl", shadertoy_name, buffer_type);
     let path_to_common = format!("assets/shadertoy/{}/common.wgsl", shadertoy_name);
 
     let common = fs::read_to_string(path_to_common).expect("could not read file.");
     let image_main = fs::read_to_string(path_to_code_block).expect("could not read file.");
 
-    let mut shader_content = shader_content.replace("{{COMMON}}", &common);
-    shader_content = shader_content.replace("{{CODE_BLOCK}}", &image_main);
+    let pass = assemble_pass(
+        buffer_type,
+        &common,
+        &image_main,
+        include_debugger,
+        workgroup_size,
+        storage,
+    );
     let folder = format!("./assets/shadertoy/{}/build", shadertoy_name);
     let path = format!("{}/{}.wgsl", folder, buffer_type);
-    let _ = fs::create_dir(folder);
-    fs::write(path, shader_content).expect("Unable to write file");
+    let _ = fs::create_dir(&folder);
+    fs::write(path, &pass.compute).expect("Unable to write file");
+
+    let fragment_path = format!("{}/{}_fragment.wgsl", folder, buffer_type);
+    fs::write(fragment_path, &pass.fragment).expect("Unable to write file");
+
+    pass
+}
+
+#[cfg(feature = "native")]
+type AssembleDrawShader = fn(&str, &str, &ProjectStorage) -> Option<String>;
+
+// draw.wgsl and vertex.wgsl are optional, returns whether the project has the file, whose
+// assembled shader is then saved next to the passes
+#[cfg(feature = "native")]
+fn format_and_save_draw_shader(
+    project_folder: &str,
+    build_folder: &str,
+    file: &str,
+    storage: &ProjectStorage,
+    assemble: AssembleDrawShader,
+) -> bool {
+    let code_block =
+        fs::read_to_string(format!("{}/{}", project_folder, file)).unwrap_or_default();
+    let common = fs::read_to_string(format!("{}/common.wgsl", project_folder))
+        .expect("could not read file.");
+
+    match assemble(&common, &code_block, storage) {
+        Some(shader) => {
+            let path = format!("{}/{}", build_folder, file);
+            let _ = fs::create_dir(build_folder);
+            fs::write(path, shader).expect("Unable to write file");
+            true
+        }
+        None => false,
+    }
 }
 
 // fn import_shader(
@@ -532,6 +805,9 @@ pub struct CommonUniformCrevice {
     pub i_date: crevice::std140::Vec4,
 }
 
+// size of the common uniform buffer
+pub const COMMON_UNIFORM_SIZE: u64 = std::mem::size_of::<f32>() as u64 * 25;
+
 #[derive(Deref, Resource)]
 pub struct ExtractedUniform(pub CommonUniformCrevice);
 
@@ -586,9 +862,8 @@ fn make_new_texture(
     images: &mut ResMut<Assets<Image>>,
 ) {
     if let Some(image) = images.get_mut(image_handle) {
-        // There is no easy way to get the data from the gpu to the cpu, so when we
-        // resize the image, we lose all the data. There might be a way to get the
-        // data soon though.
+        // The resized image gets a new texture on the gpu. The content of the old
+        // texture is copied over by the resample node according to the resize policy.
 
         image.resize(Extent3d {
             width: canvas_size.x as u32,
@@ -603,7 +878,11 @@ fn make_new_texture(
 fn update_common_uniform(
     mut common_uniform: ResMut<CommonUniform>,
     mut window_resize_event: EventReader<WindowResized>,
-    mut query: Query<(&mut Sprite, &Transform, &Handle<Image>)>,
+    mut query: Query<
+        (Option<&mut Sprite>, Option<&mut Style>, &GlobalTransform),
+        With<MainImageOutput>,
+    >,
+    main_image: Res<MainImage>,
     mut images: ResMut<Assets<Image>>,
     windows: Res<Windows>,
     time: Res<Time>,
@@ -615,76 +894,129 @@ fn update_common_uniform(
     texture_d: Res<TextureD>,
     mut frames_accum: ResMut<ShadertoyResources>,
     mut changed_window_size: ResMut<ChangedWindowSize>,
+    clock: Res<ShadertoyClock>,
+    deterministic: Option<Res<ShadertoyDeterministicMode>>,
 ) {
     // update resolution
     changed_window_size.0 = false;
     for window_resize in window_resize_event.iter() {
-        common_uniform.i_resolution.x = (window_resize.width * (1. - canvas.borders)).ceil() + 5.0;
-        common_uniform.i_resolution.y = (window_resize.height * (1. - canvas.borders)).ceil() + 7.0;
+        canvas.width = (window_resize.width * (1. - canvas.borders)).ceil() as u32;
+        canvas.height = (window_resize.height * (1. - canvas.borders)).ceil() as u32;
 
-        canvas.width = common_uniform.i_resolution.x as u32 + 1;
-        canvas.height = common_uniform.i_resolution.y as u32 + 1;
+        // the textures have exactly the size of the canvas, the dispatch is rounded up and
+        // the shaders skip the invocations outside of it
+        common_uniform.i_resolution.x = canvas.width as f32;
+        common_uniform.i_resolution.y = canvas.height as f32;
 
         // common_uniform.i_resolution.x = (canvas.width as f32 * (1. - canvas.borders)).floor();
         // common_uniform.i_resolution.y = (canvas.height as f32 * (1. - canvas.borders)).floor();
         changed_window_size.0 = true;
 
-        for (mut sprite, _, image_handle) in query.iter_mut() {
-            sprite.custom_size = Some(common_uniform.i_resolution);
+        resize_output(&mut query, common_uniform.i_resolution);
 
-            make_new_texture(&common_uniform.i_resolution, image_handle, &mut images);
-            make_new_texture(&common_uniform.i_resolution, &texture_a.0, &mut images);
-            make_new_texture(&common_uniform.i_resolution, &texture_b.0, &mut images);
-            make_new_texture(&common_uniform.i_resolution, &texture_c.0, &mut images);
-            make_new_texture(&common_uniform.i_resolution, &texture_d.0, &mut images);
-        }
+        make_new_texture(&common_uniform.i_resolution, &main_image.0, &mut images);
+        make_new_texture(&common_uniform.i_resolution, &texture_a.0, &mut images);
+        make_new_texture(&common_uniform.i_resolution, &texture_b.0, &mut images);
+        make_new_texture(&common_uniform.i_resolution, &texture_c.0, &mut images);
+        make_new_texture(&common_uniform.i_resolution, &texture_d.0, &mut images);
     }
 
     // update mouse position
-    let window = windows.primary();
-    if let Some(mouse_pos) = window.cursor_position() {
-        let mp = mouse_pos;
-        // println!("{:?}", mp);
-
-        for (_, transform, _) in query.iter() {
-            let pos = transform.translation.truncate();
+    if let Some(deterministic) = &deterministic {
+        // the mouse follows the script, the window is ignored
+        let frame = clock.frame();
+        let was_pressed = frame > 0
+            && deterministic
+                .mouse_at(frame - 1)
+                .map_or(false, |mouse| mouse.pressed);
+
+        if let Some(mouse) = deterministic.mouse_at(frame) {
+            update_mouse(
+                &mut common_uniform,
+                mouse.position,
+                mouse.pressed && !was_pressed,
+                mouse.pressed,
+            );
+        }
+    } else {
+        let window = windows.primary();
+        if let Some(mouse_pos) = window.cursor_position() {
+            let mp = mouse_pos;
+            // println!("{:?}", mp);
+
+            // The bottom left corner of the canvas, in the coordinates of the cursor, which
+            // start at the bottom left of the window. Without a sprite or a ui node, the
+            // canvas is taken to be at canvas.position.
             let window_size = Vec2::new(window.width(), window.height());
-            let top_left = pos + (window_size - common_uniform.i_resolution) / 2.0;
-
-            common_uniform.i_mouse.x = mp.x - top_left.x;
-
-            common_uniform.i_mouse.y = mp.y - top_left.y;
-
-            if mouse_button_input.just_pressed(MouseButton::Left) {
-                common_uniform.i_mouse.z = common_uniform.i_mouse.x;
-                common_uniform.i_mouse.w = common_uniform.i_mouse.y;
+            let resolution = common_uniform.i_resolution;
+            let mut bottom_left = canvas.position.truncate() + (window_size - resolution) / 2.0;
+
+            for (_, style, transform) in query.iter() {
+                let pos = transform.translation().truncate();
+                bottom_left = if style.is_some() {
+                    // ui nodes are placed by their centre, with y going down from the top of
+                    // the window
+                    Vec2::new(
+                        pos.x - resolution.x / 2.0,
+                        window_size.y - (pos.y + resolution.y / 2.0),
+                    )
+                } else {
+                    pos + (window_size - resolution) / 2.0
+                };
             }
 
-            if mouse_button_input.pressed(MouseButton::Left) {
-                common_uniform.i_mouse.z = common_uniform.i_mouse.z.abs();
-                common_uniform.i_mouse.w = common_uniform.i_mouse.w.abs();
-            } else {
-                common_uniform.i_mouse.z = -common_uniform.i_mouse.z.abs();
-                common_uniform.i_mouse.w = -common_uniform.i_mouse.w.abs();
-            }
+            update_mouse(
+                &mut common_uniform,
+                mp - bottom_left,
+                mouse_button_input.just_pressed(MouseButton::Left),
+                mouse_button_input.pressed(MouseButton::Left),
+            );
         }
     }
 
-    // update time
-    common_uniform.i_time = time.elapsed_seconds() as f32;
-    common_uniform.i_time_delta = time.delta_seconds() as f32;
+    // update time, i_sample_rate is measured in real time
+    common_uniform.i_time = clock.time();
+    common_uniform.i_time_delta = clock.delta();
     frames_accum.time_since_reset += time.delta_seconds();
     frames_accum.number_of_frames += 1;
     let fps_refresh_time = 0.5; // seconds
 
-    if frames_accum.time_since_reset > fps_refresh_time {
+    if let Some(deterministic) = &deterministic {
+        common_uniform.i_sample_rate = 1.0 / deterministic.dt;
+        common_uniform.i_date = deterministic.date;
+    } else if frames_accum.time_since_reset > fps_refresh_time {
         common_uniform.i_sample_rate =
             frames_accum.number_of_frames as f32 / frames_accum.time_since_reset;
         frames_accum.time_since_reset = 0.0;
         frames_accum.number_of_frames = 0;
     }
 
-    common_uniform.i_frame += 1.0;
+    common_uniform.i_frame = clock.frame() as f32;
+}
+
+// i_mouse.xy is the position, i_mouse.zw the position of the last click, negated
+// while the button is up
+fn update_mouse(
+    common_uniform: &mut CommonUniform,
+    position: Vec2,
+    just_pressed: bool,
+    pressed: bool,
+) {
+    common_uniform.i_mouse.x = position.x;
+    common_uniform.i_mouse.y = position.y;
+
+    if just_pressed {
+        common_uniform.i_mouse.z = common_uniform.i_mouse.x;
+        common_uniform.i_mouse.w = common_uniform.i_mouse.y;
+    }
+
+    if pressed {
+        common_uniform.i_mouse.z = common_uniform.i_mouse.z.abs();
+        common_uniform.i_mouse.w = common_uniform.i_mouse.w.abs();
+    } else {
+        common_uniform.i_mouse.z = -common_uniform.i_mouse.z.abs();
+        common_uniform.i_mouse.w = -common_uniform.i_mouse.w.abs();
+    }
 }
 
 pub struct ShadertoyPlugin;
@@ -696,10 +1028,54 @@ pub struct ShaderHandles {
     pub texture_b_shader: Handle<Shader>,
     pub texture_c_shader: Handle<Shader>,
     pub texture_d_shader: Handle<Shader>,
+    pub active_passes: ActivePasses,
+    pub pass_usages: PassUsages,
+    pub workgroup_sizes: PassWorkgroupSizes,
+    // the passes assembled for ShadertoyBackend::Fragment, indexed as in PassUsages::get.
+    // None if the project cannot run on that backend.
+    pub fragment_shaders: Option<[Handle<Shader>; 5]>,
+    pub storage: ProjectStorage,
+    // indexed as in PassUsages::get
+    pub dispatches: [PassDispatch; 5],
+    // None if the project has no draw.wgsl, see ShadertoyDraw
+    pub draw_shader: Option<Handle<Shader>>,
+    // None if the project has no vertex.wgsl, see ShadertoyVertexArt
+    pub vertex_art_shader: Option<Handle<Shader>>,
+}
+
+impl ShaderHandles {
+    // number of workgroups of a pass, indexed as in PassUsages::get
+    pub fn dispatch_count(&self, pass: usize, width: u32, height: u32) -> (u32, u32) {
+        let workgroup_size = self.workgroup_sizes.get(pass_name(pass));
+        self.dispatches[pass].dispatch_count(workgroup_size, width, height)
+    }
 }
 
 impl Plugin for ShadertoyPlugin {
     fn build(&self, app: &mut App) {
+        // inserted by the app before the plugin, decides how the passes are run
+        let backend = app
+            .world
+            .get_resource::<ShadertoyBackend>()
+            .copied()
+            .unwrap_or_else(|| {
+                ShadertoyBackend::detect(
+                    app.world.resource::<RenderAdapter>(),
+                    app.world.resource::<RenderDevice>(),
+                )
+            });
+        info!("shadertoy backend: {:?}", backend);
+        app.insert_resource(backend);
+
+        // inserted by the app before the plugin, decides how the image pass is run
+        let post_process = match app.world.get_resource::<ShadertoyPostProcess>().cloned() {
+            Some(_) if backend == ShadertoyBackend::Fragment => {
+                error!("the post-process mode needs the compute backend, it is ignored");
+                None
+            }
+            post_process => post_process,
+        };
+
         app.add_plugin(ExtractResourcePlugin::<ExtractedUniform>::default())
             .add_plugin(ExtractResourcePlugin::<TextureA>::default())
             .add_plugin(ExtractResourcePlugin::<TextureB>::default())
@@ -711,96 +1087,453 @@ impl Plugin for ShadertoyPlugin {
             .add_plugin(ExtractResourcePlugin::<ShadertoyTextures>::default())
             .add_plugin(ExtractResourcePlugin::<ShaderHandles>::default())
             .add_plugin(ExtractResourcePlugin::<ShadertoyCanvas>::default())
+            .add_plugin(ExtractResourcePlugin::<ShadertoyPassSchedules>::default())
+            .add_plugin(ExtractResourcePlugin::<ShadertoyPassReruns>::default())
+            .add_plugin(ExtractResourcePlugin::<ShadertoyResizePolicies>::default())
+            .add_plugin(ExtractResourcePlugin::<ShadertoyReset>::default())
+            .add_plugin(ExtractResourcePlugin::<ShadertoyClock>::default())
+            .add_plugin(ExtractResourcePlugin::<ShadertoyDraw>::default())
+            .add_plugin(ExtractResourcePlugin::<ShadertoyVertexArt>::default())
+            .add_event::<ShadertoyRerunPass>()
+            .add_event::<ShadertoyCommand>()
+            .add_event::<ShadertoyReadbackResult>()
+            .add_asset::<ShadertoyProject>()
+            .init_asset_loader::<ShadertoyProjectLoader>()
+            .init_resource::<ShadertoyPassSchedules>()
+            .init_resource::<ShadertoyPassReruns>()
+            .init_resource::<ShadertoyResizePolicies>()
+            .init_resource::<ShadertoyKeyBindings>()
+            .init_resource::<ShadertoyOutput>()
+            .init_resource::<ShadertoyReset>()
+            .init_resource::<ShadertoyClock>()
+            .init_resource::<ShadertoyDraw>()
+            .init_resource::<ShadertoyVertexArt>()
+            .init_resource::<CaptureRequests>()
+            .init_resource::<CapturedFrames>()
+            .init_resource::<ShadertoyReadback>()
+            .init_resource::<ShadertoyUploads>()
+            .init_resource::<ShadertoyCameraInputs>()
+            .add_system_to_stage(CoreStage::First, receive_captured_frames)
+            .add_system_to_stage(CoreStage::First, send_readback_results)
+            .add_system_to_stage(CoreStage::PreUpdate, assemble_active_project)
             .add_startup_system(setup)
+            .add_system(send_key_commands.before(tick_clock))
+            .add_system(tick_clock.before(apply_commands))
+            .add_system(apply_commands.before(update_common_uniform))
             .add_system(update_common_uniform)
+            .add_system(collect_pass_reruns)
+            .add_system(resize_camera_input)
+            .add_system(reject_camera_inputs.after(assemble_active_project))
             .insert_resource(ShadertoyResources {
                 number_of_frames: 0,
                 time_since_reset: 0.0,
                 include_debugger: false,
             });
 
+        // screenshots and snapshots write and read files
+        #[cfg(feature = "native")]
+        app.init_resource::<ShadertoyScreenshots>()
+            .init_resource::<ShadertoySnapshots>()
+            .init_resource::<ShadertoyParameters>()
+            .add_system(load_snapshots.before(tick_clock))
+            .add_system(take_screenshots)
+            .add_system(save_snapshots.after(update_common_uniform));
+
+        load_internal_asset!(
+            app,
+            RESAMPLE_SHADER_HANDLE,
+            "templates/resample.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            CAMERA_INPUT_SHADER_HANDLE,
+            "templates/camera_input.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            DRAW_COMPOSITE_SHADER_HANDLE,
+            "templates/draw_composite.wgsl",
+            Shader::from_wgsl
+        );
+
+        // shared by both worlds so that the rebuilds can be reported as diagnostics
+        let rebuild_counters = ShadertoyRebuildCounters::default();
+        app.insert_resource(rebuild_counters.clone());
+
+        // the clock waits for the passes of the render world
+        let passes_ready = ShadertoyPassesReady::default();
+        app.insert_resource(passes_ready.clone());
+
+        // frames read back in the render world are handed to the main world through this
+        let capture_queue = CaptureQueue::default();
+        app.insert_resource(capture_queue.clone());
+        let readback_queue = ReadbackQueue::default();
+        app.insert_resource(readback_queue.clone());
+
         let render_app = app.sub_app_mut(RenderApp);
 
         let render_device = render_app.world.resource::<RenderDevice>();
 
         let buffer = render_device.create_buffer(&BufferDescriptor {
             label: Some("common uniform buffer"),
-            size: std::mem::size_of::<f32>() as u64 * 25,
+            size: COMMON_UNIFORM_SIZE,
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
         render_app
-            .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
-                1.0 / 120.0,
-            )))
             .insert_resource(CommonUniformMeta {
                 buffer: buffer.clone(),
             })
+            .insert_resource(rebuild_counters)
+            .insert_resource(capture_queue)
+            .init_resource::<ExtractedCaptureRequests>()
+            .init_resource::<CaptureBuffers>()
+            .add_system_to_stage(RenderStage::Extract, extract_capture_requests)
+            .add_system_to_stage(RenderStage::Prepare, prepare_capture_buffers)
+            .add_system_to_stage(RenderStage::Cleanup, read_captured_frames)
+            .insert_resource(readback_queue)
+            .init_resource::<ExtractedReadbacks>()
+            .init_resource::<PreparedReadbacks>()
+            .init_resource::<PendingReadbacks>()
+            .add_system_to_stage(RenderStage::Extract, extract_readback_requests)
+            .add_system_to_stage(RenderStage::Prepare, prepare_readbacks)
+            .add_system_to_stage(RenderStage::Cleanup, read_readbacks)
+            .init_resource::<ExtractedUploads>()
+            .init_resource::<PreparedUploads>()
+            .add_system_to_stage(RenderStage::Extract, extract_uploads)
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_uploads.after(PrepareAssetLabel::AssetPrepare),
+            )
+            .init_resource::<CameraInputPipeline>()
+            .init_resource::<ExtractedCameraInput>()
+            .init_resource::<CameraInputJobs>()
+            .add_system_to_stage(RenderStage::Extract, extract_camera_input)
+            .add_system_to_stage(RenderStage::Queue, queue_camera_input)
+            .init_resource::<ShadertoyLoading>()
+            .add_system_to_stage(RenderStage::Prepare, clear_shadertoy_loading)
+            .insert_resource(passes_ready)
+            .add_system_to_stage(RenderStage::Cleanup, report_passes_ready)
             .add_system_to_stage(RenderStage::Prepare, prepare_common_uniform)
-            .init_resource::<ShadertoyPipelines>()
-            // .add_system_to_stage(RenderStage::Extract, extract_stuff_here)
-            .add_system_to_stage(RenderStage::Queue, queue_bind_group)
-            // .init_resource::<TextureAPipeline>()
-            // .add_system_to_stage(RenderStage::Extract, extract_texture_a)
-            .add_system_to_stage(RenderStage::Queue, queue_bind_group_a)
-            // .init_resource::<TextureBPipeline>()
-            // .add_system_to_stage(RenderStage::Extract, extract_texture_b)
-            .add_system_to_stage(RenderStage::Queue, queue_bind_group_b)
-            // .init_resource::<TextureCPipeline>()
-            // .add_system_to_stage(RenderStage::Extract, extract_texture_c)
-            .add_system_to_stage(RenderStage::Queue, queue_bind_group_c)
-            // .init_resource::<TextureDPipeline>()
-            // .add_system_to_stage(RenderStage::Extract, extract_texture_d)
-            .add_system_to_stage(RenderStage::Queue, queue_bind_group_d);
+            .init_resource::<FastForwardUniforms>()
+            .init_resource::<FastForwardPlan>()
+            .init_resource::<FastForwardOrder>()
+            .add_system_to_stage(RenderStage::Prepare, clear_fast_forward_plan)
+            .add_system_to_stage(RenderStage::Prepare, prepare_fast_forward_uniforms)
+            .init_resource::<ResamplePipelines>()
+            .init_resource::<PendingResample>()
+            .init_resource::<ResampleJobs>()
+            .add_system_to_stage(RenderStage::Extract, extract_resized_buffers)
+            .add_system_to_stage(RenderStage::Queue, queue_resample)
+            .insert_resource(backend);
+
+        match backend {
+            ShadertoyBackend::Compute => {
+                render_app
+                    .add_system_to_stage(RenderStage::Prepare, prepare_pipelines)
+                    .init_resource::<ShadertoyPipelines>()
+                    .add_system_to_stage(RenderStage::Prepare, prepare_storage_buffers)
+                    .init_resource::<ShadertoyStorageBuffers>()
+                    .init_resource::<DrawPipelines>()
+                    .init_resource::<PreparedDraws>()
+                    .add_system_to_stage(RenderStage::Queue, queue_draw_passes)
+                    // .add_system_to_stage(RenderStage::Extract, extract_stuff_here)
+                    .add_system_to_stage(RenderStage::Queue, queue_bind_group)
+                    // .init_resource::<TextureAPipeline>()
+                    // .add_system_to_stage(RenderStage::Extract, extract_texture_a)
+                    .add_system_to_stage(RenderStage::Queue, queue_bind_group_a)
+                    // .init_resource::<TextureBPipeline>()
+                    // .add_system_to_stage(RenderStage::Extract, extract_texture_b)
+                    .add_system_to_stage(RenderStage::Queue, queue_bind_group_b)
+                    // .init_resource::<TextureCPipeline>()
+                    // .add_system_to_stage(RenderStage::Extract, extract_texture_c)
+                    .add_system_to_stage(RenderStage::Queue, queue_bind_group_c)
+                    // .init_resource::<TextureDPipeline>()
+                    // .add_system_to_stage(RenderStage::Extract, extract_texture_d)
+                    .add_system_to_stage(RenderStage::Queue, queue_bind_group_d)
+                    .add_system_to_stage(RenderStage::Queue, update_render_graph);
+            }
+            // the layouts of ShadertoyPipelines have read_write storage textures, which the
+            // adapter may not support, so they are never created
+            ShadertoyBackend::Fragment => {
+                render_app
+                    .init_resource::<FragmentPipelines>()
+                    .init_resource::<FragmentPasses>()
+                    .add_system_to_stage(RenderStage::Queue, queue_fragment_passes)
+                    .add_system_to_stage(RenderStage::Queue, reject_draw_passes);
+            }
+        }
 
         let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
 
-        render_graph.add_node("main_image", MainNode::default());
-        render_graph.add_node("texture_a", TextureANode::default());
-        render_graph.add_node("texture_b", TextureBNode::default());
-        render_graph.add_node("texture_c", TextureCNode::default());
-        render_graph.add_node("texture_d", TextureDNode::default());
-
+        match backend {
+            ShadertoyBackend::Compute => {
+                // in post-process mode, the image pass runs in the graphs of the cameras instead
+                if post_process.is_none() {
+                    render_graph.add_node("main_image", MainNode::default());
+                    render_graph.add_node(DRAW_NODE, DrawNode::new(DrawKind::Instances));
+                    render_graph.add_node_edge("main_image", DRAW_NODE).unwrap();
+                    render_graph
+                        .add_node_edge(DRAW_NODE, bevy::render::main_graph::node::CAMERA_DRIVER)
+                        .unwrap();
+                    // placed around the image pass by update_render_graph
+                    render_graph.add_node(VERTEX_ART_NODE, DrawNode::new(DrawKind::VertexArt));
+                    render_graph.add_node_edge(VERTEX_ART_NODE, DRAW_NODE).unwrap();
+                }
+                render_graph.add_node("texture_a", TextureANode::default());
+                render_graph.add_node("texture_b", TextureBNode::default());
+                render_graph.add_node("texture_c", TextureCNode::default());
+                render_graph.add_node("texture_d", TextureDNode::default());
+                // placed before the passes and the vertex art pass below
+                render_graph.add_node(FAST_FORWARD_NODE, FastForwardNode);
+            }
+            ShadertoyBackend::Fragment => {
+                render_graph.add_node(FRAGMENT_NODE, FragmentPassesNode::default());
+            }
+        }
+        render_graph.add_node(RESAMPLE_NODE, ResampleNode::default());
+
+        // captures and readbacks run once the main image is complete and every pass below is
+        // done, since update_render_graph only orders the passes by the buffers they share
+        let main_image_done = match (backend, &post_process) {
+            (ShadertoyBackend::Fragment, _) => FRAGMENT_NODE,
+            (_, Some(_)) => bevy::render::main_graph::node::CAMERA_DRIVER,
+            (_, None) => DRAW_NODE,
+        };
+        render_graph.add_node(CAPTURE_NODE, CaptureNode);
         render_graph
-            .add_node_edge("texture_a", "texture_b")
+            .add_node_edge(main_image_done, CAPTURE_NODE)
             .unwrap();
-
+        render_graph.add_node(READBACK_NODE, ReadbackNode);
         render_graph
-            .add_node_edge("texture_b", "texture_c")
+            .add_node_edge(main_image_done, READBACK_NODE)
             .unwrap();
 
-        render_graph
-            .add_node_edge("texture_c", "texture_d")
-            .unwrap();
+        render_graph.add_node(UPLOAD_NODE, UploadNode);
+        render_graph.add_node_edge(RESAMPLE_NODE, UPLOAD_NODE).unwrap();
+
+        render_graph.add_node(CAMERA_INPUT_NODE, CameraInputNode);
+        render_graph.add_node_edge(UPLOAD_NODE, CAMERA_INPUT_NODE).unwrap();
+
+        let pass_nodes: Vec<&str> = PASS_NODES
+            .into_iter()
+            .chain([FRAGMENT_NODE])
+            .filter(|pass_node| render_graph.get_node_id(*pass_node).is_ok())
+            .collect();
+
+        for pass_node in pass_nodes.iter() {
+            render_graph.add_node_edge(RESAMPLE_NODE, *pass_node).unwrap();
+            render_graph.add_node_edge(UPLOAD_NODE, *pass_node).unwrap();
+            render_graph.add_node_edge(CAMERA_INPUT_NODE, *pass_node).unwrap();
+            if *pass_node != main_image_done {
+                render_graph.add_node_edge(*pass_node, CAPTURE_NODE).unwrap();
+                render_graph.add_node_edge(*pass_node, READBACK_NODE).unwrap();
+            }
+        }
 
+        // drawn into a buffer, the vertex art pass may run before any of the passes
+        if render_graph.get_node_id(VERTEX_ART_NODE).is_ok() {
+            for node in [RESAMPLE_NODE, UPLOAD_NODE, CAMERA_INPUT_NODE] {
+                render_graph.add_node_edge(node, VERTEX_ART_NODE).unwrap();
+            }
+            for node in [CAPTURE_NODE, READBACK_NODE] {
+                render_graph.add_node_edge(VERTEX_ART_NODE, node).unwrap();
+            }
+        }
+
+        // the earlier steps of a fast-forward run before the last one
+        if render_graph.get_node_id(FAST_FORWARD_NODE).is_ok() {
+            render_graph
+                .add_node_edge(CAMERA_INPUT_NODE, FAST_FORWARD_NODE)
+                .unwrap();
+            for pass_node in pass_nodes.iter() {
+                render_graph
+                    .add_node_edge(FAST_FORWARD_NODE, *pass_node)
+                    .unwrap();
+            }
+            if render_graph.get_node_id(VERTEX_ART_NODE).is_ok() {
+                render_graph
+                    .add_node_edge(FAST_FORWARD_NODE, VERTEX_ART_NODE)
+                    .unwrap();
+            }
+        }
+
+        // the passes run in the order of Shadertoy until update_render_graph orders them
+        for pair in pass_nodes.windows(2) {
+            render_graph.add_node_edge(pair[0], pair[1]).unwrap();
+        }
+
+        // the main image is done before the cameras show it, and in post-process mode the
+        // buffers are done before the cameras read them
+        let last_pass = pass_nodes[pass_nodes.len() - 1];
         render_graph
-            .add_node_edge("texture_d", "main_image")
+            .add_node_edge(last_pass, bevy::render::main_graph::node::CAMERA_DRIVER)
             .unwrap();
 
+        if let Some(post_process) = post_process {
+            build_post_process(app, post_process);
+        }
+    }
+}
+
+// copies the content of the buffers into their resized textures before any pass runs
+const RESAMPLE_NODE: &str = "resample_buffers";
+
+// copy the images out once every pass is done
+const CAPTURE_NODE: &str = "capture";
+const READBACK_NODE: &str = "readback";
+
+// writes the ShadertoyUploads into the buffers after the resample and before any pass runs
+const UPLOAD_NODE: &str = "upload_buffers";
+
+// copies the ShadertoyCameraInputs into their buffers after the uploads and before any pass
+// runs
+const CAMERA_INPUT_NODE: &str = "camera_input";
+
+// runs every pass with ShadertoyBackend::Fragment
+const FRAGMENT_NODE: &str = "fragment_passes";
+
+// node names indexed as in PassUsages::get
+const PASS_NODES: [&str; 5] = ["texture_a", "texture_b", "texture_c", "texture_d", "main_image"];
+
+// Removes the nodes of the inactive passes from the render graph and orders the remaining
+// ones according to the buffers they share, so that independent passes are not chained.
+// The vertex art pass runs after the buffer passes when it draws into a buffer, so the
+// image pass sees it, and after the image pass otherwise.
+fn update_render_graph(
+    all_shader_handles: Res<ShaderHandles>,
+    vertex_art: Res<ShadertoyVertexArt>,
+    mut render_graph: ResMut<RenderGraph>,
+    mut fast_forward_order: ResMut<FastForwardOrder>,
+    mut applied: Local<Option<(ActivePasses, PassUsages, CaptureTarget)>>,
+) {
+    let active_passes = all_shader_handles.active_passes;
+    let pass_usages = all_shader_handles.pass_usages;
+    if *applied == Some((active_passes, pass_usages, vertex_art.target)) {
+        return;
+    }
+    *applied = Some((active_passes, pass_usages, vertex_art.target));
+
+    for output_node in PASS_NODES {
+        for input_node in PASS_NODES {
+            let _ = render_graph.remove_node_edge(output_node, input_node);
+        }
+        let _ = render_graph.remove_node_edge(output_node, VERTEX_ART_NODE);
+        let _ = render_graph.remove_node_edge(VERTEX_ART_NODE, output_node);
+    }
+
+    for (index, is_active) in active_passes.as_array().iter().enumerate() {
+        let name = PASS_NODES[index];
+        let in_graph = render_graph.get_node_id(name).is_ok();
+
+        if !is_active && in_graph {
+            render_graph.remove_node(name).unwrap();
+        } else if *is_active && !in_graph {
+            match index {
+                0 => render_graph.add_node(name, TextureANode::default()),
+                1 => render_graph.add_node(name, TextureBNode::default()),
+                2 => render_graph.add_node(name, TextureCNode::default()),
+                _ => render_graph.add_node(name, TextureDNode::default()),
+            };
+        }
+    }
+
+    let passes_in_graph: Vec<usize> = (0..5)
+        .filter(|pass| render_graph.get_node_id(PASS_NODES[*pass]).is_ok())
+        .collect();
+    // the same order as the fragment backend, see queue_fragment_passes
+    let order = pass_order(&passes_in_graph, &pass_usages);
+    info!(
+        "shadertoy pass order: {:?}",
+        order
+            .iter()
+            .map(|pass| pass_name(*pass))
+            .collect::<Vec<_>>()
+    );
+    let edges: Vec<(usize, usize)> = pass_dependencies(&pass_usages)
+        .into_iter()
+        .filter(|(before, after)| {
+            passes_in_graph.contains(before) && passes_in_graph.contains(after)
+        })
+        .collect();
+
+    for (before, after) in edges {
         render_graph
-            .add_node_edge("main_image", bevy::render::main_graph::node::CAMERA_DRIVER)
+            .add_node_edge(PASS_NODES[before], PASS_NODES[after])
             .unwrap();
     }
+
+    // a re-added image pass has also lost its edge to the draw pass
+    let _ = render_graph.add_node_edge(PASS_NODES[4], DRAW_NODE);
+
+    // the buffer passes, then the vertex art pass if it draws into a buffer, as they run
+    // in the graph
+    fast_forward_order.0 = order
+        .into_iter()
+        .filter(|pass| *pass < 4)
+        .map(|pass| PASS_NODES[pass])
+        .collect();
+
+    // the vertex art pass is not in the graph in post-process mode
+    if render_graph.get_node_id(VERTEX_ART_NODE).is_ok() {
+        if vertex_art.target != CaptureTarget::MainImage {
+            fast_forward_order.0.push(VERTEX_ART_NODE);
+        }
+
+        match vertex_art.target {
+            CaptureTarget::MainImage => {
+                let _ = render_graph.add_node_edge(PASS_NODES[4], VERTEX_ART_NODE);
+            }
+            _ => {
+                for pass in passes_in_graph.iter().filter(|pass| **pass < 4) {
+                    let _ = render_graph.add_node_edge(PASS_NODES[*pass], VERTEX_ART_NODE);
+                }
+                let _ = render_graph.add_node_edge(VERTEX_ART_NODE, PASS_NODES[4]);
+            }
+        }
+    }
+
+    // re-added nodes have lost their edges to the resample, upload, camera input and
+    // fast-forward nodes, and every pass has to be done before the captures, the readbacks
+    // and the cameras run
+    for pass in passes_in_graph {
+        let _ = render_graph.add_node_edge(RESAMPLE_NODE, PASS_NODES[pass]);
+        let _ = render_graph.add_node_edge(UPLOAD_NODE, PASS_NODES[pass]);
+        let _ = render_graph.add_node_edge(CAMERA_INPUT_NODE, PASS_NODES[pass]);
+        let _ = render_graph.add_node_edge(FAST_FORWARD_NODE, PASS_NODES[pass]);
+        let _ = render_graph.add_node_edge(PASS_NODES[pass], CAPTURE_NODE);
+        let _ = render_graph.add_node_edge(PASS_NODES[pass], READBACK_NODE);
+        let _ = render_graph
+            .add_node_edge(PASS_NODES[pass], bevy::render::main_graph::node::CAMERA_DRIVER);
+    }
 }
 
 // pub struct ShadertoyPipelines {
 //     main_image_group_layout: BindGroupLayout,
 // }
 
+// The layouts only contain the buffers a pass actually uses, with the narrowest access
+// reflected from its shader
 #[derive(Resource)] 
 pub struct ShadertoyPipelines {
     pub main_image_group_layout: BindGroupLayout,
-    pub abcd_group_layout: BindGroupLayout,
+    pub buffer_group_layouts: [BindGroupLayout; 4],
+    pub pass_usages: PassUsages,
 }
 
 impl ShadertoyPipelines {
-    pub fn make_texture_layout(binding: u32) -> BindGroupLayoutEntry {
+    pub fn make_texture_layout(
+        binding: u32,
+        access: StorageTextureAccess,
+    ) -> BindGroupLayoutEntry {
         BindGroupLayoutEntry {
             binding,
             visibility: ShaderStages::COMPUTE,
             ty: BindingType::StorageTexture {
-                access: StorageTextureAccess::ReadWrite,
+                access,
                 format: TextureFormat::Rgba32Float,
                 view_dimension: TextureViewDimension::D2,
             },
@@ -808,8 +1541,23 @@ impl ShadertoyPipelines {
         }
     }
 
-    pub fn new(render_device: &RenderDevice) -> Self {
-        let uniform_descriptor = BindGroupLayoutEntry {
+    // a read only buffer bound as a sampled texture, rgba32float is not filterable
+    pub fn make_sampled_texture_layout(binding: u32) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }
+
+    // uniform at binding 0, then the used buffers at bindings 1 to 4
+    pub fn make_buffer_layouts(usage: &BufferUsage) -> Vec<BindGroupLayoutEntry> {
+        let mut entries = vec![BindGroupLayoutEntry {
             binding: 0,
             visibility: ShaderStages::COMPUTE,
             ty: BindingType::Buffer {
@@ -818,96 +1566,107 @@ impl ShadertoyPipelines {
                 min_binding_size: BufferSize::new(std::mem::size_of::<f32>() as u64 * 25),
             },
             count: None,
-        };
+        }];
 
-        let abcd_group_layout =
+        for buffer in 0..4 {
+            let binding = buffer as u32 + 1;
+            match usage.access(buffer) {
+                Some(_) if usage.sampled[buffer] => {
+                    entries.push(ShadertoyPipelines::make_sampled_texture_layout(binding))
+                }
+                Some(access) => {
+                    entries.push(ShadertoyPipelines::make_texture_layout(binding, access))
+                }
+                None => {}
+            }
+        }
+
+        entries
+    }
+
+    pub fn new(render_device: &RenderDevice, pass_usages: &PassUsages) -> Self {
+        let buffer_group_layouts = [0, 1, 2, 3].map(|pass| {
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("abcd_layout"),
-                entries: &[
-                    uniform_descriptor,
-                    ShadertoyPipelines::make_texture_layout(1),
-                    ShadertoyPipelines::make_texture_layout(2),
-                    ShadertoyPipelines::make_texture_layout(3),
-                    ShadertoyPipelines::make_texture_layout(4),
-                ],
-            });
+                label: Some(BUFFER_NAMES[pass]),
+                entries: &ShadertoyPipelines::make_buffer_layouts(&pass_usages.buffers[pass]),
+            })
+        });
+
+        let mut main_image_entries = ShadertoyPipelines::make_buffer_layouts(&pass_usages.image);
+        main_image_entries.extend_from_slice(&[
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // font texture
+            BindGroupLayoutEntry {
+                binding: 6,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 7,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+            // noise texture
+            BindGroupLayoutEntry {
+                binding: 8,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 9,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+            // blue noise texture
+            BindGroupLayoutEntry {
+                binding: 10,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 11,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]);
 
         let main_image_group_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("main_layout"),
-                entries: &[
-                    uniform_descriptor,
-                    ShadertoyPipelines::make_texture_layout(1),
-                    ShadertoyPipelines::make_texture_layout(2),
-                    ShadertoyPipelines::make_texture_layout(3),
-                    ShadertoyPipelines::make_texture_layout(4),
-                    BindGroupLayoutEntry {
-                        binding: 5,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba32Float,
-                            view_dimension: TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    // font texture
-                    BindGroupLayoutEntry {
-                        binding: 6,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Texture {
-                            sample_type: TextureSampleType::Float { filterable: true },
-                            view_dimension: TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 7,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    // noise texture
-                    BindGroupLayoutEntry {
-                        binding: 8,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Texture {
-                            sample_type: TextureSampleType::Float { filterable: true },
-                            view_dimension: TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 9,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    // blue noise texture
-                    BindGroupLayoutEntry {
-                        binding: 10,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Texture {
-                            sample_type: TextureSampleType::Float { filterable: true },
-                            view_dimension: TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 11,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
+                entries: &main_image_entries,
             });
 
         ShadertoyPipelines {
             main_image_group_layout,
-            abcd_group_layout,
+            buffer_group_layouts,
+            pass_usages: *pass_usages,
         }
     }
 }
@@ -915,10 +1674,67 @@ impl ShadertoyPipelines {
 impl FromWorld for ShadertoyPipelines {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
-        ShadertoyPipelines::new(render_device)
+        ShadertoyPipelines::new(render_device, &PassUsages::default())
     }
 }
 
+// Builds the bind group entries matching ShadertoyPipelines::make_buffer_layouts
+pub fn make_buffer_bind_group_entries<'a>(
+    usage: &BufferUsage,
+    common_uniform_buffer: &'a Buffer,
+    buffer_views: [&'a TextureView; 4],
+) -> Vec<BindGroupEntry<'a>> {
+    let mut entries = vec![BindGroupEntry {
+        binding: 0,
+        resource: common_uniform_buffer.as_entire_binding(),
+    }];
+
+    for (buffer, view) in buffer_views.into_iter().enumerate() {
+        if usage.access(buffer).is_some() {
+            entries.push(BindGroupEntry {
+                binding: buffer as u32 + 1,
+                resource: BindingResource::TextureView(view),
+            });
+        }
+    }
+
+    entries
+}
+
+// Builds the bind group entries matching the main_image_group_layout, the textures are the
+// font, the rgba noise and the blue noise
+pub fn make_main_image_bind_group_entries<'a>(
+    usage: &BufferUsage,
+    common_uniform_buffer: &'a Buffer,
+    buffers: [&'a GpuImage; 4],
+    main_image: &'a GpuImage,
+    textures: [&'a GpuImage; 3],
+) -> Vec<BindGroupEntry<'a>> {
+    let mut entries = make_buffer_bind_group_entries(
+        usage,
+        common_uniform_buffer,
+        buffers.map(|buffer| &buffer.texture_view),
+    );
+
+    entries.push(BindGroupEntry {
+        binding: 5,
+        resource: BindingResource::TextureView(&main_image.texture_view),
+    });
+
+    for (index, texture) in textures.into_iter().enumerate() {
+        entries.push(BindGroupEntry {
+            binding: 6 + 2 * index as u32,
+            resource: BindingResource::TextureView(&texture.texture_view),
+        });
+        entries.push(BindGroupEntry {
+            binding: 7 + 2 * index as u32,
+            resource: BindingResource::Sampler(&texture.sampler),
+        });
+    }
+
+    entries
+}
+
 // impl FromWorld for ShadertoyPipelines {
 //     fn from_world(world: &mut World) -> Self {
 //         let main_image_group_layout =
@@ -1040,8 +1856,9 @@ impl FromWorld for ShadertoyPipelines {
 //     }
 // }
 
+// the image pass writes into this, it is shown through the sprite spawned by setup
 #[derive(Deref, Clone, Resource, ExtractResource)]
-struct MainImage(Handle<Image>);
+pub struct MainImage(pub Handle<Image>);
 
 // use bevy::core::cast_slice;
 
@@ -1050,16 +1867,16 @@ struct MainImageBindGroup {
     main_image_bind_group: BindGroup,
     init_pipeline: CachedComputePipelineId,
     update_pipeline: CachedComputePipelineId,
+    pipeline_key: PipelineKey,
+    bind_group_key: BindGroupKey,
 }
 
 // write the extracted common uniform into the corresponding uniform buffer
 pub fn prepare_common_uniform(
     common_uniform_meta: ResMut<CommonUniformMeta>,
     render_queue: Res<RenderQueue>,
-    mut extrated_common_uniform_crevice: ResMut<ExtractedUniform>,
+    extrated_common_uniform_crevice: Res<ExtractedUniform>,
     // mut extracted_uniform: ResMut<ExtractedUniform>,
-    render_device: Res<RenderDevice>,
-    mut pipelines: ResMut<ShadertoyPipelines>,
 ) {
     // // use bevy::render::render_resource::std140::Std140;
     let std140_common_uniform = extrated_common_uniform_crevice.0.as_std140();
@@ -1080,11 +1897,16 @@ pub fn prepare_common_uniform(
         // bytemuck::cast_slice(as_bytes),
         // bevy::core::cast_slice(&bytes),
     );
+}
 
-    // TODO: DO THIS IN THE EXTRACT PHASE?
-    // modify the pipelines according to the new window size if applicable
-    if extrated_common_uniform_crevice.changed_window_size > 0.5 {
-        *pipelines = ShadertoyPipelines::new(&render_device);
+// rebuild the bind group layouts when a project with a different buffer usage is loaded
+pub fn prepare_pipelines(
+    all_shader_handles: Res<ShaderHandles>,
+    render_device: Res<RenderDevice>,
+    mut pipelines: ResMut<ShadertoyPipelines>,
+) {
+    if pipelines.pass_usages != all_shader_handles.pass_usages {
+        *pipelines = ShadertoyPipelines::new(&render_device, &all_shader_handles.pass_usages);
     }
 }
 
@@ -1145,6 +1967,7 @@ fn extract_stuff_here(
 fn queue_bind_group(
     mut commands: Commands,
     pipeline: Res<ShadertoyPipelines>,
+    cached: Option<Res<MainImageBindGroup>>,
 
     gpu_images: Res<RenderAssets<Image>>,
     shadertoy_textures: Res<ShadertoyTextures>,
@@ -1159,51 +1982,52 @@ fn queue_bind_group(
     all_shader_handles: Res<ShaderHandles>,
     common_uniform_meta: ResMut<CommonUniformMeta>,
     mut changed_size_res: ResMut<ChangedWindowSize>,
+    resize_policies: Res<ShadertoyResizePolicies>,
+    rebuild_counters: Res<ShadertoyRebuildCounters>,
+    reset: Res<ShadertoyReset>,
+    storage_buffers: Res<ShadertoyStorageBuffers>,
     mut render_graph: ResMut<RenderGraph>,
 ) {
-    if changed_size_res.0 {
-        let main_node: &mut MainNode = render_graph
-            .get_node_mut(NodeLabel::Name(Cow::from("main_image")))
-            .unwrap();
-        main_node.state = ShadertoyState::Loading;
+    // the content of the buffers is carried over by the resample node, so the passes only
+    // go through INIT again when asked to
+    if (changed_size_res.0 && resize_policies.reinit_on_resize) || reset.0 {
+        // the node is not in the graph in post-process mode
+        if let Ok(main_node) =
+            render_graph.get_node_mut::<MainNode>(NodeLabel::Name(Cow::from("main_image")))
+        {
+            main_node.state = ShadertoyState::Loading;
+        }
+        reset_post_process_nodes(&mut render_graph);
 
-        let texture_a_node: &mut TextureANode = render_graph
-            .get_node_mut(NodeLabel::Name(Cow::from("texture_a")))
-            .unwrap();
-        texture_a_node.state = ShadertoyState::Loading;
+        // the node is not in the graph if the pass is inactive
+        if let Ok(texture_a_node) =
+            render_graph.get_node_mut::<TextureANode>(NodeLabel::Name(Cow::from("texture_a")))
+        {
+            texture_a_node.state = ShadertoyState::Loading;
+        }
 
-        let texture_b_node: &mut TextureBNode = render_graph
-            .get_node_mut(NodeLabel::Name(Cow::from("texture_b")))
-            .unwrap();
-        texture_b_node.state = ShadertoyState::Loading;
+        // the node is not in the graph if the pass is inactive
+        if let Ok(texture_b_node) =
+            render_graph.get_node_mut::<TextureBNode>(NodeLabel::Name(Cow::from("texture_b")))
+        {
+            texture_b_node.state = ShadertoyState::Loading;
+        }
 
-        let texture_c_node: &mut TextureCNode = render_graph
-            .get_node_mut(NodeLabel::Name(Cow::from("texture_c")))
-            .unwrap();
-        texture_c_node.state = ShadertoyState::Loading;
+        // the node is not in the graph if the pass is inactive
+        if let Ok(texture_c_node) =
+            render_graph.get_node_mut::<TextureCNode>(NodeLabel::Name(Cow::from("texture_c")))
+        {
+            texture_c_node.state = ShadertoyState::Loading;
+        }
 
-        let texture_d_node: &mut TextureDNode = render_graph
-            .get_node_mut(NodeLabel::Name(Cow::from("texture_d")))
-            .unwrap();
-        texture_d_node.state = ShadertoyState::Loading;
+        // the node is not in the graph if the pass is inactive
+        if let Ok(texture_d_node) =
+            render_graph.get_node_mut::<TextureDNode>(NodeLabel::Name(Cow::from("texture_d")))
+        {
+            texture_d_node.state = ShadertoyState::Loading;
+        }
     }
 
-    let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-        label: None,
-        layout: Some(vec![pipeline.main_image_group_layout.clone()]),
-        shader: all_shader_handles.image_shader.clone(),
-        shader_defs: vec!["INIT".to_string()],
-        entry_point: Cow::from("update"),
-    });
-
-    let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-        label: None,
-        layout: Some(vec![pipeline.main_image_group_layout.clone()]),
-        shader: all_shader_handles.image_shader.clone(),
-        shader_defs: vec![],
-        entry_point: Cow::from("update"),
-    });
-
     let main_view = &gpu_images[&main_image.0];
     let font_view = &gpu_images[&shadertoy_textures.font_texture_handle];
     let rgba_noise_256_view = &gpu_images[&shadertoy_textures.rgba_noise_256_handle];
@@ -1214,73 +2038,79 @@ fn queue_bind_group(
     let texture_c_view = &gpu_images[&texture_c_image.0];
     let texture_d_view = &gpu_images[&texture_d_image.0];
 
-    // let texture_a_view = &gpu_images[&textures.a.0];
-    // let texture_b_view = &gpu_images[&textures.b.0];
-    // let texture_c_view = &gpu_images[&textures.c.0];
-    // let texture_d_view = &gpu_images[&textures.d.0];
-
-    let main_image_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-        label: Some("main_bind_group"),
-        layout: &pipeline.main_image_group_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: common_uniform_meta.buffer.as_entire_binding(),
-            },
-            BindGroupEntry {
-                binding: 1,
-                resource: BindingResource::TextureView(&texture_a_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 2,
-                resource: BindingResource::TextureView(&texture_b_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 3,
-                resource: BindingResource::TextureView(&texture_c_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 4,
-                resource: BindingResource::TextureView(&texture_d_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 5,
-                resource: BindingResource::TextureView(&main_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 6,
-                resource: BindingResource::TextureView(&font_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 7,
-                resource: BindingResource::Sampler(&font_view.sampler),
-            },
-            BindGroupEntry {
-                binding: 8,
-                resource: BindingResource::TextureView(&rgba_noise_256_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 9,
-                resource: BindingResource::Sampler(&rgba_noise_256_view.sampler),
-            },
-            BindGroupEntry {
-                binding: 10,
-                resource: BindingResource::TextureView(&blue_noise_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 11,
-                resource: BindingResource::Sampler(&blue_noise_view.sampler),
-            },
+    let layout = &pipeline.main_image_group_layout;
+    let pipeline_key = PipelineKey::new(&all_shader_handles.image_shader, layout)
+        .with_storage(&storage_buffers.layout);
+    let bind_group_key = BindGroupKey::new(
+        layout,
+        &[
+            &texture_a_view.texture,
+            &texture_b_view.texture,
+            &texture_c_view.texture,
+            &texture_d_view.texture,
+            &main_view.texture,
+            &font_view.texture,
+            &rgba_noise_256_view.texture,
+            &blue_noise_view.texture,
         ],
-    });
+    );
+
+    // nothing to do until the shader, the layout or one of the textures changes
+    let cached = cached.as_deref();
+    if let Some(cached) = cached {
+        if cached.pipeline_key == pipeline_key && cached.bind_group_key == bind_group_key {
+            return;
+        }
+    }
+
+    let (init_pipeline, update_pipeline) = match cached {
+        Some(cached) if cached.pipeline_key == pipeline_key => {
+            (cached.init_pipeline, cached.update_pipeline)
+        }
+        _ => {
+            rebuild_counters.count_pipeline_rebuild();
+            queue_pass_pipelines(
+                &mut pipeline_cache,
+                layout,
+                Some(&storage_buffers.layout),
+                &all_shader_handles.image_shader,
+            )
+        }
+    };
+
+    let main_image_bind_group = match cached {
+        Some(cached) if cached.bind_group_key == bind_group_key => {
+            cached.main_image_bind_group.clone()
+        }
+        _ => {
+            rebuild_counters.count_bind_group_rebuild();
+
+            let main_image_entries = make_main_image_bind_group_entries(
+                &pipeline.pass_usages.image,
+                &common_uniform_meta.buffer,
+                [texture_a_view, texture_b_view, texture_c_view, texture_d_view],
+                main_view,
+                [font_view, rgba_noise_256_view, blue_noise_view],
+            );
+
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: Some("main_bind_group"),
+                layout,
+                entries: &main_image_entries,
+            })
+        }
+    };
 
     commands.insert_resource(MainImageBindGroup {
         main_image_bind_group,
-        init_pipeline: init_pipeline.clone(),
-        update_pipeline: update_pipeline.clone(),
+        init_pipeline,
+        update_pipeline,
+        pipeline_key,
+        bind_group_key,
     });
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ShadertoyState {
     Loading,
     Init,
@@ -1289,12 +2119,14 @@ pub enum ShadertoyState {
 
 pub struct MainNode {
     pub state: ShadertoyState,
+    pub run_state: PassRunState,
 }
 
 impl Default for MainNode {
     fn default() -> Self {
         Self {
             state: ShadertoyState::Loading,
+            run_state: PassRunState::default(),
         }
     }
 }
@@ -1325,6 +2157,8 @@ impl render_graph::Node for MainNode {
             }
             ShadertoyState::Update => {}
         }
+
+        self.run_state.update("image", &self.state, world);
     }
 
     fn run(
@@ -1335,8 +2169,11 @@ impl render_graph::Node for MainNode {
     ) -> Result<(), render_graph::NodeRunError> {
         let bind_group = world.resource::<MainImageBindGroup>();
         let canvas = world.resource::<ShadertoyCanvas>();
+        let (workgroups_x, workgroups_y) = world
+            .resource::<ShaderHandles>()
+            .dispatch_count(4, canvas.width, canvas.height);
         
-        if !canvas.active {
+        if !canvas.active || !self.run_state.dispatch {
             return Ok(());
         }
 
@@ -1352,6 +2189,7 @@ impl render_graph::Node for MainNode {
             });
 
         pass.set_bind_group(0, &bind_group.main_image_bind_group, &[]);
+        pass.set_bind_group(1, &world.resource::<ShadertoyStorageBuffers>().bind_group, &[]);
 
         // select the pipeline based on the current state
         match self.state {
@@ -1362,11 +2200,7 @@ impl render_graph::Node for MainNode {
                     .get_compute_pipeline(init_pipeline_cache)
                     .unwrap();
                 pass.set_pipeline(init_pipeline);
-                pass.dispatch_workgroups(
-                    canvas.width / WORKGROUP_SIZE,
-                    canvas.height / WORKGROUP_SIZE,
-                    1,
-                );
+                pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
             }
 
             ShadertoyState::Update => {
@@ -1374,11 +2208,7 @@ impl render_graph::Node for MainNode {
                     .get_compute_pipeline(update_pipeline_cache)
                     .unwrap();
                 pass.set_pipeline(update_pipeline);
-                pass.dispatch_workgroups(
-                    canvas.width / WORKGROUP_SIZE,
-                    canvas.height / WORKGROUP_SIZE,
-                    1,
-                );
+                pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
             }
         }
 
@@ -0,0 +1,262 @@
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{mesh::MeshVertexBufferLayout, render_asset::RenderAssets, render_resource::*},
+    sprite::{Material2d, Material2dPlugin},
+    utils::HashSet,
+};
+
+use bevy::asset::load_internal_asset;
+
+pub const SHADERTOY_MATERIAL_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x2d91_c4a7_53e8_0f16);
+
+pub const SHADERTOY_MATERIAL_2D_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x8a5e_27f3_c019_d64b);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShadertoyShading {
+    // the canvas is shown as it is, unaffected by the lights of the scene
+    Emissive,
+    // the canvas is the base color of a pbr surface
+    Lit,
+}
+
+impl Default for ShadertoyShading {
+    fn default() -> Self {
+        ShadertoyShading::Emissive
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+pub struct ShadertoyMaterialUniform {
+    pub uv_scale: Vec2,
+    pub uv_offset: Vec2,
+    pub emissive_intensity: f32,
+    pub perceptual_roughness: f32,
+}
+
+// Shows the canvas on a 3D mesh. The texture is the MainImage or one of the buffers, e.g.
+// TextureA. The uv of the mesh is mapped to the canvas as uv * uv_scale + uv_offset, with
+// (0, 0) at the top left corner of the canvas, so one canvas can be spread over several
+// meshes or tiled. Lit surfaces need a mesh with normals.
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "0f0c7d3e-5b8a-4e21-9c6f-3a1d2b7e8f40"]
+#[bind_group_data(ShadertoyMaterialKey)]
+#[uniform(0, ShadertoyMaterialUniform)]
+pub struct ShadertoyMaterial {
+    #[texture(1)]
+    #[sampler(2)]
+    pub texture: Handle<Image>,
+    pub uv_scale: Vec2,
+    pub uv_offset: Vec2,
+    pub shading: ShadertoyShading,
+    // multiplies the color of emissive surfaces, above 1 for bloom
+    pub emissive_intensity: f32,
+    // only used by lit surfaces
+    pub perceptual_roughness: f32,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+}
+
+impl ShadertoyMaterial {
+    pub fn new(texture: Handle<Image>) -> Self {
+        Self {
+            texture,
+            uv_scale: Vec2::ONE,
+            uv_offset: Vec2::ZERO,
+            shading: ShadertoyShading::Emissive,
+            emissive_intensity: 1.0,
+            perceptual_roughness: 0.5,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+        }
+    }
+
+    pub fn lit(texture: Handle<Image>) -> Self {
+        Self {
+            shading: ShadertoyShading::Lit,
+            ..Self::new(texture)
+        }
+    }
+
+    // maps the whole uv range of the mesh to the given rectangle of the canvas, in uv
+    pub fn with_canvas_rect(mut self, min: Vec2, max: Vec2) -> Self {
+        self.uv_offset = min;
+        self.uv_scale = max - min;
+        self
+    }
+}
+
+impl AsBindGroupShaderType<ShadertoyMaterialUniform> for ShadertoyMaterial {
+    fn as_bind_group_shader_type(
+        &self,
+        _images: &RenderAssets<Image>,
+    ) -> ShadertoyMaterialUniform {
+        ShadertoyMaterialUniform {
+            uv_scale: self.uv_scale,
+            uv_offset: self.uv_offset,
+            emissive_intensity: self.emissive_intensity,
+            perceptual_roughness: self.perceptual_roughness,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ShadertoyMaterialKey {
+    shading: ShadertoyShading,
+    double_sided: bool,
+}
+
+impl From<&ShadertoyMaterial> for ShadertoyMaterialKey {
+    fn from(material: &ShadertoyMaterial) -> Self {
+        Self {
+            shading: material.shading,
+            double_sided: material.double_sided,
+        }
+    }
+}
+
+impl Material for ShadertoyMaterial {
+    fn fragment_shader() -> ShaderRef {
+        SHADERTOY_MATERIAL_SHADER_HANDLE.typed().into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        if key.bind_group_data.shading == ShadertoyShading::Lit {
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("SHADERTOY_LIT".to_string());
+            }
+        }
+        if key.bind_group_data.double_sided {
+            descriptor.primitive.cull_mode = None;
+        }
+        Ok(())
+    }
+}
+
+// The 2D counterpart of ShadertoyMaterial, for a Mesh2d. There are no lights in 2D, so
+// the canvas is always shown as it is.
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "6b2e9a14-d7c3-4f58-a0b1-8e4c5d6f7a92"]
+#[uniform(0, ShadertoyMaterialUniform)]
+pub struct ShadertoyMaterial2d {
+    #[texture(1)]
+    #[sampler(2)]
+    pub texture: Handle<Image>,
+    pub uv_scale: Vec2,
+    pub uv_offset: Vec2,
+    pub emissive_intensity: f32,
+}
+
+impl ShadertoyMaterial2d {
+    pub fn new(texture: Handle<Image>) -> Self {
+        Self {
+            texture,
+            uv_scale: Vec2::ONE,
+            uv_offset: Vec2::ZERO,
+            emissive_intensity: 1.0,
+        }
+    }
+
+    pub fn with_canvas_rect(mut self, min: Vec2, max: Vec2) -> Self {
+        self.uv_offset = min;
+        self.uv_scale = max - min;
+        self
+    }
+}
+
+impl AsBindGroupShaderType<ShadertoyMaterialUniform> for ShadertoyMaterial2d {
+    fn as_bind_group_shader_type(
+        &self,
+        _images: &RenderAssets<Image>,
+    ) -> ShadertoyMaterialUniform {
+        ShadertoyMaterialUniform {
+            uv_scale: self.uv_scale,
+            uv_offset: self.uv_offset,
+            emissive_intensity: self.emissive_intensity,
+            perceptual_roughness: 1.0,
+        }
+    }
+}
+
+impl Material2d for ShadertoyMaterial2d {
+    fn fragment_shader() -> ShaderRef {
+        SHADERTOY_MATERIAL_2D_SHADER_HANDLE.typed().into()
+    }
+}
+
+// The bind group of a material keeps the texture it was created with. A resized canvas
+// gets new textures, so the materials showing it are marked as changed to rebuild theirs.
+pub fn refresh_shadertoy_materials(
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut materials: ResMut<Assets<ShadertoyMaterial>>,
+    mut materials_2d: ResMut<Assets<ShadertoyMaterial2d>>,
+) {
+    let modified: HashSet<Handle<Image>> = image_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.clone_weak()),
+            _ => None,
+        })
+        .collect();
+
+    if modified.is_empty() {
+        return;
+    }
+
+    let stale: Vec<_> = materials
+        .iter()
+        .filter(|(_, material)| modified.contains(&material.texture))
+        .map(|(id, _)| id)
+        .collect();
+    for id in stale {
+        let handle = materials.get_handle(id);
+        materials.get_mut(&handle);
+    }
+
+    let stale: Vec<_> = materials_2d
+        .iter()
+        .filter(|(_, material)| modified.contains(&material.texture))
+        .map(|(id, _)| id)
+        .collect();
+    for id in stale {
+        let handle = materials_2d.get_handle(id);
+        materials_2d.get_mut(&handle);
+    }
+}
+
+// Adds ShadertoyMaterial and ShadertoyMaterial2d. Not part of ShadertoyPlugin, as it
+// needs the pbr and sprite plugins of bevy.
+pub struct ShadertoyMaterialPlugin;
+
+impl Plugin for ShadertoyMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            SHADERTOY_MATERIAL_SHADER_HANDLE,
+            "templates/material.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            SHADERTOY_MATERIAL_2D_SHADER_HANDLE,
+            "templates/material_2d.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.add_plugin(MaterialPlugin::<ShadertoyMaterial>::default())
+            .add_plugin(Material2dPlugin::<ShadertoyMaterial2d>::default())
+            .add_system_to_stage(CoreStage::PostUpdate, refresh_shadertoy_materials);
+    }
+}
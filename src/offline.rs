@@ -0,0 +1,129 @@
+use bevy::{app::AppExit, prelude::*};
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    apply_commands, CaptureRequests, CaptureTarget, CapturedFrame, CapturedFrames,
+    ShadertoyDeterministicMode, ShadertoyPassesReady,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OfflineImageFormat {
+    // 8 bit sRGB, as shown in the window
+    Png8,
+    // 16 bit sRGB
+    Png16,
+    // linear 32 bit float, the raw content of the main image
+    Exr,
+}
+
+impl OfflineImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OfflineImageFormat::Png8 | OfflineImageFormat::Png16 => "png",
+            OfflineImageFormat::Exr => "exr",
+        }
+    }
+}
+
+// Frames of the main image are written to output_dir as frame_00000.png, frame_00001.png...
+// The size of the images is the size of the ShadertoyCanvas.
+#[derive(Clone, Resource, Debug)]
+pub struct ShadertoyOfflineRender {
+    pub output_dir: PathBuf,
+    pub format: OfflineImageFormat,
+    pub frame_count: u32,
+}
+
+#[derive(Default)]
+struct OfflineRenderState {
+    started: bool,
+    frames_requested: u32,
+    frames_written: u32,
+}
+
+// Renders a project without a window, to use with the winit plugin disabled and the
+// ScheduleRunnerPlugin added, see the offline example. Frames advance by the fixed dt of
+// ShadertoyDeterministicMode and the app exits once every frame has been written.
+pub struct ShadertoyOfflineRenderPlugin;
+
+impl Plugin for ShadertoyOfflineRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ShadertoyDeterministicMode>()
+            .add_system(drive_offline_render.before(apply_commands));
+    }
+}
+
+fn drive_offline_render(
+    offline_render: Res<ShadertoyOfflineRender>,
+    passes_ready: Res<ShadertoyPassesReady>,
+    captured: Res<CapturedFrames>,
+    mut capture: ResMut<CaptureRequests>,
+    mut state: Local<OfflineRenderState>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for frame in captured.of(CaptureTarget::MainImage) {
+        let path = offline_render.output_dir.join(format!(
+            "frame_{:05}.{}",
+            state.frames_written,
+            offline_render.format.extension()
+        ));
+
+        if let Err(e) = save_frame(&path, offline_render.format, frame) {
+            error!("could not write {}: {}", path.display(), e);
+        }
+        state.frames_written += 1;
+    }
+
+    if state.frames_written >= offline_render.frame_count {
+        exit.send(AppExit);
+        return;
+    }
+
+    // the clock is held at frame 0 until every pass is running, so the time the shaders
+    // took to compile does not change the output
+    if passes_ready.get() {
+        state.started = true;
+    }
+
+    if state.started && state.frames_requested < offline_render.frame_count {
+        capture.request(CaptureTarget::MainImage);
+        state.frames_requested += 1;
+    }
+}
+
+pub(crate) fn save_frame(
+    path: &Path,
+    format: OfflineImageFormat,
+    frame: &CapturedFrame,
+) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let wrong_size = || anyhow::anyhow!("wrong buffer size");
+
+    match format {
+        OfflineImageFormat::Png8 => {
+            image::RgbaImage::from_raw(frame.width, frame.height, frame.to_srgb8())
+                .ok_or_else(wrong_size)?
+                .save(path)?;
+        }
+        OfflineImageFormat::Png16 => {
+            image::ImageBuffer::<image::Rgba<u16>, Vec<u16>>::from_raw(
+                frame.width,
+                frame.height,
+                frame.to_srgb16(),
+            )
+            .ok_or_else(wrong_size)?
+            .save(path)?;
+        }
+        OfflineImageFormat::Exr => {
+            image::Rgba32FImage::from_raw(frame.width, frame.height, frame.pixels.clone())
+                .ok_or_else(wrong_size)?
+                .save(path)?;
+        }
+    }
+
+    Ok(())
+}
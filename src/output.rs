@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+
+// Where the main image ends up. Insert it before adding the ShadertoyPlugin, it is only
+// read at startup. Whatever the output, the main image is resized with the canvas and its
+// handle is in the MainImage resource.
+#[derive(Clone, Debug, Resource)]
+pub enum ShadertoyOutput {
+    // a sprite at canvas.position, as before
+    Sprite,
+    // an ImageBundle, laid out by the ui like any other node
+    UiImage,
+    // nothing is spawned, e.g. to show the image through a ShadertoyMaterial
+    None,
+    // The image pass renders into this image instead of a new one. Its content, size and
+    // format are replaced, as the passes need a rgba32float storage texture.
+    Image(Handle<Image>),
+}
+
+impl Default for ShadertoyOutput {
+    fn default() -> Self {
+        ShadertoyOutput::Sprite
+    }
+}
+
+// marks the sprite or the ui node spawned for the output
+#[derive(Component)]
+pub struct MainImageOutput;
+
+pub(crate) fn spawn_output(
+    commands: &mut Commands,
+    output: &ShadertoyOutput,
+    image: &Handle<Image>,
+    size: Vec2,
+    position: Vec3,
+) {
+    match output {
+        ShadertoyOutput::Sprite => {
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        custom_size: Some(size),
+                        ..default()
+                    },
+                    texture: image.clone(),
+                    // // the y axis of a bevy window is flipped compared to shadertoy. We fix
+                    // // it by rotating the sprite 180 degrees, but this comes at the cost of a
+                    // // mirrored image in the x axis.
+                    // transform: Transform::from_rotation(bevy::math::Quat::from_rotation_z(
+                    //     core::f32::consts::PI,
+                    // )),
+                    transform: Transform::from_translation(position),
+                    ..default()
+                },
+                MainImageOutput,
+            ));
+        }
+        ShadertoyOutput::UiImage => {
+            commands.spawn((
+                ImageBundle {
+                    image: UiImage(image.clone()),
+                    style: Style {
+                        size: Size::new(Val::Px(size.x), Val::Px(size.y)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                MainImageOutput,
+            ));
+        }
+        ShadertoyOutput::None | ShadertoyOutput::Image(_) => {}
+    }
+}
+
+// keeps the spawned sprite or ui node at the size of the canvas
+pub(crate) fn resize_output(
+    query: &mut Query<
+        (Option<&mut Sprite>, Option<&mut Style>, &GlobalTransform),
+        With<MainImageOutput>,
+    >,
+    size: Vec2,
+) {
+    for (sprite, style, _) in query.iter_mut() {
+        if let Some(mut sprite) = sprite {
+            sprite.custom_size = Some(size);
+        }
+        if let Some(mut style) = style {
+            style.size = Size::new(Val::Px(size.x), Val::Px(size.y));
+        }
+    }
+}
@@ -0,0 +1,511 @@
+use bevy::{
+    core_pipeline::{core_2d, core_3d, fullscreen_vertex_shader::fullscreen_shader_vertex_state},
+    ecs::query::QueryState,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        camera::ExtractedCamera,
+        render_asset::RenderAssets,
+        render_graph::{self, NodeLabel, RenderGraph, SlotInfo, SlotType},
+        render_phase::{RenderPhase, TrackedRenderPass},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        texture::{BevyDefault, TextureCache},
+        view::{ExtractedView, Msaa, ViewDepthTexture, ViewTarget},
+        RenderApp, RenderStage,
+    },
+    utils::HashMap,
+};
+
+use bevy::asset::load_internal_asset;
+
+use std::borrow::Cow;
+
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::{
+    CameraInputPipeline, CaptureTarget, MainImage, MainNode, ShadertoyState, WorkgroupSize,
+};
+
+pub const POST_PROCESS_DEPTH_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x93f0_6a1b_e42d_c758);
+
+pub const POST_PROCESS_BLIT_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x1e7b_d5c2_8f09_a346);
+
+// name of the node in the core_2d and core_3d graphs
+pub const POST_PROCESS_NODE: &str = "shadertoy_post_process";
+
+// Runs the image pass as a post-processing node of the cameras, between the tonemapping
+// and the end of the main pass post processing of the core_2d and core_3d graphs. Insert
+// it before adding the ShadertoyPlugin, it is only read when the plugin is built.
+//
+// For every view, the view is copied into the scene buffer and its depth, for 3D views,
+// into the red channel of the depth buffer. Then the image pass runs and the main image is
+// written to the view target, stretched to its size. The buffer passes keep running before
+// the cameras, so they see the scene of the previous frame and can be used as feedback
+// buffers, e.g. for trails. The passes of the scene and depth buffers should be inactive,
+// as they would overwrite them. Use ShadertoyOutput::None, as the main image is shown by
+// the cameras, and a canvas without borders, so that the buffers have the size of the
+// window. Depth is reversed, 1 at the near plane and 0 at infinity.
+//
+// The main image is only complete after the cameras have rendered, so captures and
+// readbacks run after them in this mode. With several cameras, the image pass runs once
+// per view, on the same buffers.
+#[derive(Clone, Debug, Resource)]
+pub struct ShadertoyPostProcess {
+    pub scene_buffer: CaptureTarget,
+    pub depth_buffer: Option<CaptureTarget>,
+}
+
+impl Default for ShadertoyPostProcess {
+    fn default() -> Self {
+        Self {
+            scene_buffer: CaptureTarget::BufferA,
+            depth_buffer: None,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct PostProcessPipeline {
+    depth_layouts: [BindGroupLayout; 2],
+    depth_pipelines: [CachedComputePipelineId; 2],
+    blit_layout: BindGroupLayout,
+}
+
+impl PostProcessPipeline {
+    // indexed by whether the depth texture is multisampled
+    fn depth_index(multisampled: bool) -> usize {
+        multisampled as usize
+    }
+}
+
+impl FromWorld for PostProcessPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let depth_layouts = [false, true].map(|multisampled| {
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("post_process_depth_layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Depth,
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: TextureFormat::Rgba32Float,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            })
+        });
+
+        let blit_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("post_process_blit_layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let depth_pipelines = [false, true].map(|multisampled| {
+            let shader_defs = match multisampled {
+                true => vec!["MULTISAMPLED".to_string()],
+                false => vec![],
+            };
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from("post_process_depth_pipeline")),
+                layout: Some(vec![
+                    depth_layouts[PostProcessPipeline::depth_index(multisampled)].clone(),
+                ]),
+                shader: POST_PROCESS_DEPTH_SHADER_HANDLE.typed(),
+                shader_defs,
+                entry_point: Cow::from("copy_depth"),
+            })
+        });
+
+        PostProcessPipeline {
+            depth_layouts,
+            depth_pipelines,
+            blit_layout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PostProcessPipelineKey {
+    hdr: bool,
+}
+
+impl SpecializedRenderPipeline for PostProcessPipeline {
+    type Key = PostProcessPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let format = match key.hdr {
+            true => ViewTarget::TEXTURE_FORMAT_HDR,
+            false => TextureFormat::bevy_default(),
+        };
+
+        RenderPipelineDescriptor {
+            label: Some(Cow::from("post_process_blit_pipeline")),
+            layout: Some(vec![self.blit_layout.clone()]),
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: POST_PROCESS_BLIT_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: Cow::from("blit"),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+        }
+    }
+}
+
+// The depth textures of core_3d are only render attachments, so they are requested again
+// from the texture cache with the same descriptor and sampled usage, when the depth is
+// copied. Views that render to the same target keep sharing one depth texture.
+pub fn prepare_post_process_depth_textures(
+    mut commands: Commands,
+    mut texture_cache: ResMut<TextureCache>,
+    msaa: Res<Msaa>,
+    render_device: Res<RenderDevice>,
+    settings: Res<ShadertoyPostProcess>,
+    views: Query<(Entity, &ExtractedCamera), With<RenderPhase<core_3d::Opaque3d>>>,
+) {
+    if settings.depth_buffer.is_none() {
+        return;
+    }
+
+    let mut textures = HashMap::default();
+    for (entity, camera) in &views {
+        let physical_target_size = match camera.physical_target_size {
+            Some(size) => size,
+            None => continue,
+        };
+        let cached_texture = textures
+            .entry(camera.target.clone())
+            .or_insert_with(|| {
+                texture_cache.get(
+                    &render_device,
+                    TextureDescriptor {
+                        label: Some("view_depth_texture"),
+                        size: Extent3d {
+                            depth_or_array_layers: 1,
+                            width: physical_target_size.x,
+                            height: physical_target_size.y,
+                        },
+                        mip_level_count: 1,
+                        sample_count: msaa.samples,
+                        dimension: TextureDimension::D2,
+                        format: TextureFormat::Depth32Float,
+                        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                    },
+                )
+            })
+            .clone();
+        commands.entity(entity).insert(ViewDepthTexture {
+            texture: cached_texture.texture,
+            view: cached_texture.default_view,
+        });
+    }
+}
+
+#[derive(Component)]
+pub struct ViewPostProcessPipeline(CachedRenderPipelineId);
+
+pub fn queue_post_process_pipelines(
+    mut commands: Commands,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<PostProcessPipeline>>,
+    post_process_pipeline: Res<PostProcessPipeline>,
+    views: Query<(Entity, &ExtractedView)>,
+) {
+    for (entity, view) in views.iter() {
+        let pipeline = pipelines.specialize(
+            &mut pipeline_cache,
+            &post_process_pipeline,
+            PostProcessPipelineKey { hdr: view.hdr },
+        );
+        commands
+            .entity(entity)
+            .insert(ViewPostProcessPipeline(pipeline));
+    }
+}
+
+pub struct ShadertoyPostProcessNode {
+    query: QueryState<
+        (
+            &'static ViewTarget,
+            Option<&'static ViewDepthTexture>,
+            &'static ViewPostProcessPipeline,
+        ),
+        With<ExtractedView>,
+    >,
+    // the image pass, run for every view
+    pub main: MainNode,
+}
+
+impl ShadertoyPostProcessNode {
+    pub const IN_VIEW: &'static str = "view";
+
+    pub fn new(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+            main: MainNode::default(),
+        }
+    }
+}
+
+impl render_graph::Node for ShadertoyPostProcessNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(Self::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+        self.main.update(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let (target, depth, blit_pipeline) = match self.query.get_manual(world, view_entity) {
+            Ok(view) => view,
+            Err(_) => return Ok(()),
+        };
+
+        let settings = world.resource::<ShadertoyPostProcess>();
+        let post_process_pipeline = world.resource::<PostProcessPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+
+        let main_image = &world.resource::<MainImage>().0;
+        let buffers = [
+            &world.resource::<TextureA>().0,
+            &world.resource::<TextureB>().0,
+            &world.resource::<TextureC>().0,
+            &world.resource::<TextureD>().0,
+        ];
+
+        if let Some(scene_buffer) = gpu_images.get(settings.scene_buffer.image(main_image, buffers))
+        {
+            world.resource::<CameraInputPipeline>().copy(
+                render_context,
+                pipeline_cache,
+                target.main_texture(),
+                scene_buffer,
+            );
+        }
+
+        // 2D views have no depth texture
+        let depth_buffer = settings
+            .depth_buffer
+            .and_then(|buffer| gpu_images.get(buffer.image(main_image, buffers)));
+        if let (Some(depth), Some(depth_buffer)) = (depth, depth_buffer) {
+            let multisampled = world.resource::<Msaa>().samples > 1;
+            let index = PostProcessPipeline::depth_index(multisampled);
+            if let Some(pipeline) =
+                pipeline_cache.get_compute_pipeline(post_process_pipeline.depth_pipelines[index])
+            {
+                let bind_group =
+                    render_context
+                        .render_device
+                        .create_bind_group(&BindGroupDescriptor {
+                            label: Some("post_process_depth_bind_group"),
+                            layout: &post_process_pipeline.depth_layouts[index],
+                            entries: &[
+                                BindGroupEntry {
+                                    binding: 0,
+                                    resource: BindingResource::TextureView(&depth.view),
+                                },
+                                BindGroupEntry {
+                                    binding: 1,
+                                    resource: BindingResource::TextureView(
+                                        &depth_buffer.texture_view,
+                                    ),
+                                },
+                            ],
+                        });
+
+                let mut pass =
+                    render_context
+                        .command_encoder
+                        .begin_compute_pass(&ComputePassDescriptor {
+                            label: Some("post_process_depth_compute_pass"),
+                        });
+
+                let (workgroups_x, workgroups_y) = WorkgroupSize::SIZE_8X8.dispatch_count(
+                    depth_buffer.size.x as u32,
+                    depth_buffer.size.y as u32,
+                );
+
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+            }
+        }
+
+        self.main.run(graph, render_context, world)?;
+
+        let (pipeline, main_image) = match (
+            pipeline_cache.get_render_pipeline(blit_pipeline.0),
+            gpu_images.get(main_image),
+        ) {
+            (Some(pipeline), Some(main_image)) => (pipeline, main_image),
+            // the view keeps the scene until the blit pipeline is ready
+            _ => return Ok(()),
+        };
+
+        // swaps the main textures of the view, so it must be followed by the blit
+        let post_process = target.post_process_write();
+
+        let bind_group = render_context
+            .render_device
+            .create_bind_group(&BindGroupDescriptor {
+                label: Some("post_process_blit_bind_group"),
+                layout: &post_process_pipeline.blit_layout,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&main_image.texture_view),
+                }],
+            });
+
+        let pass_descriptor = RenderPassDescriptor {
+            label: Some("post_process_blit_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: post_process.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+        };
+
+        let mut render_pass = TrackedRenderPass::new(
+            render_context
+                .command_encoder
+                .begin_render_pass(&pass_descriptor),
+        );
+
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+// Adds the post-process node to the core_2d and core_3d graphs
+pub(crate) fn add_post_process_nodes(render_app: &mut App) {
+    let graphs = [
+        (
+            core_2d::graph::NAME,
+            core_2d::graph::input::VIEW_ENTITY,
+            core_2d::graph::node::TONEMAPPING,
+            core_2d::graph::node::END_MAIN_PASS_POST_PROCESSING,
+        ),
+        (
+            core_3d::graph::NAME,
+            core_3d::graph::input::VIEW_ENTITY,
+            core_3d::graph::node::TONEMAPPING,
+            core_3d::graph::node::END_MAIN_PASS_POST_PROCESSING,
+        ),
+    ];
+
+    for (graph_name, view_entity, before, after) in graphs {
+        let node = ShadertoyPostProcessNode::new(&mut render_app.world);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        let graph = match render_graph.get_sub_graph_mut(graph_name) {
+            Some(graph) => graph,
+            None => {
+                warn!("no {} render graph, no post processing there", graph_name);
+                continue;
+            }
+        };
+
+        let input_node_id = graph.input_node().unwrap().id;
+        graph.add_node(POST_PROCESS_NODE, node);
+        graph
+            .add_slot_edge(
+                input_node_id,
+                view_entity,
+                POST_PROCESS_NODE,
+                ShadertoyPostProcessNode::IN_VIEW,
+            )
+            .unwrap();
+        graph.add_node_edge(before, POST_PROCESS_NODE).unwrap();
+        graph.add_node_edge(POST_PROCESS_NODE, after).unwrap();
+    }
+}
+
+// sends the image pass of the post-process nodes back through INIT
+pub(crate) fn reset_post_process_nodes(render_graph: &mut RenderGraph) {
+    for graph_name in [core_2d::graph::NAME, core_3d::graph::NAME] {
+        if let Some(graph) = render_graph.get_sub_graph_mut(graph_name) {
+            if let Ok(node) = graph.get_node_mut::<ShadertoyPostProcessNode>(NodeLabel::Name(
+                Cow::from(POST_PROCESS_NODE),
+            )) {
+                node.main.state = ShadertoyState::Loading;
+            }
+        }
+    }
+}
+
+pub(crate) fn build_post_process(app: &mut App, post_process: ShadertoyPostProcess) {
+    load_internal_asset!(
+        app,
+        POST_PROCESS_DEPTH_SHADER_HANDLE,
+        "templates/post_process_depth.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        POST_PROCESS_BLIT_SHADER_HANDLE,
+        "templates/post_process_blit.wgsl",
+        Shader::from_wgsl
+    );
+
+    let render_app = app.sub_app_mut(RenderApp);
+    render_app
+        .insert_resource(post_process)
+        .init_resource::<PostProcessPipeline>()
+        .init_resource::<SpecializedRenderPipelines<PostProcessPipeline>>()
+        .add_system_to_stage(
+            RenderStage::Prepare,
+            prepare_post_process_depth_textures.after(core_3d::prepare_core_3d_depth_textures),
+        )
+        .add_system_to_stage(RenderStage::Queue, queue_post_process_pipelines);
+
+    add_post_process_nodes(render_app);
+}
@@ -0,0 +1,402 @@
+use bevy::{
+    asset::{AssetIoError, AssetLoader, HandleId, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use std::path::PathBuf;
+
+use crate::{
+    assemble_draw_shader, assemble_fragment_shader, assemble_vertex_art_shader, compute_code_block,
+    find_active_passes, fragment_runnable, prepare_entry_point, reflect_pass,
+    reflect_project_storage, restrict_buffer_access, BufferUsage, PassDispatch, PassUsages,
+    PassWorkgroupSizes, ProjectStorage, ShaderHandles, WorkgroupSize, BUFFER_NAMES,
+};
+
+// A pass assembled from its code block and the common code of its project
+pub struct AssembledPass {
+    pub compute: String,
+    // the buffers reflected from the compute variant
+    pub usage: BufferUsage,
+    pub dispatch: PassDispatch,
+    pub fragment: String,
+    // false if the pass is not written as a mainImage function, see uses_main_image
+    pub fragment_runnable: bool,
+}
+
+// Assembles both variants of a pass in memory, so it also works where there is no
+// filesystem to write the shaders to
+pub fn assemble_pass(
+    buffer_type: &str,
+    common: &str,
+    code_block: &str,
+    include_debugger: bool,
+    workgroup_size: WorkgroupSize,
+    storage: &ProjectStorage,
+) -> AssembledPass {
+    let common_prelude = include_str!("./templates/common_prelude.wgsl");
+
+    let template = match buffer_type {
+        "image" => include_str!("./templates/image_template.wgsl"),
+        "buffer_a" => include_str!("./templates/buffer_a_template.wgsl"),
+        "buffer_b" => include_str!("./templates/buffer_b_template.wgsl"),
+        "buffer_c" => include_str!("./templates/buffer_c_template.wgsl"),
+        "buffer_d" => include_str!("./templates/buffer_d_template.wgsl"),
+        _ => include_str!("./templates/buffer_d_template.wgsl"),
+    };
+
+    let mut shader_content = template.replace("{{COMMON_PRELUDE}}", common_prelude);
+
+    if include_debugger {
+        let debbuger_str = include_str!("./templates/debugger.wgsl");
+        shader_content = shader_content.replace("{{DEBUGGER}}", debbuger_str);
+    } else {
+        shader_content = shader_content.replace("{{DEBUGGER}}", "");
+    }
+
+    let shader_content = shader_content.replace(
+        "{{COMMON}}",
+        &format!("{}\n{}", storage.declarations, common),
+    );
+    // a rejected pass leaves its buffer as it is
+    let compute_code_block = compute_code_block(buffer_type, common, code_block)
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            "@compute @workgroup_size(8, 8, 1)\nfn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {}\n".to_string()
+        });
+    let shader_content = shader_content.replace("{{CODE_BLOCK}}", &compute_code_block);
+    let assemble = |dispatch| prepare_entry_point(&shader_content, workgroup_size, dispatch);
+
+    // the dispatch is only known once the usage of the storage buffers is reflected
+    let (mut compute, usage) =
+        reflect_pass(buffer_type, assemble(PassDispatch::Pixels), code_block);
+    let dispatch = match buffer_type {
+        "image" => PassDispatch::Pixels,
+        _ => PassDispatch::from_usage(&usage, storage),
+    };
+    if dispatch != PassDispatch::Pixels {
+        compute = restrict_buffer_access(&assemble(dispatch), &usage);
+    }
+
+    // the fragment backend has no storage buffers
+    let (fragment, fragment_runnable) =
+        assemble_fragment_shader(buffer_type, common, code_block, include_debugger);
+
+    AssembledPass {
+        compute,
+        usage,
+        dispatch,
+        fragment,
+        fragment_runnable: fragment_runnable && storage.buffers.is_empty(),
+    }
+}
+
+// The sources of a project, with the passes indexed as in PassUsages::get. Load it from a
+// .shadertoy file or embed it with include_shadertoy_project!, then run it by inserting a
+// ShadertoyActiveProject.
+#[derive(Clone, Debug, TypeUuid)]
+#[uuid = "c5a1e0d2-7b39-4f86-9e14-2d6b8a03f7c1"]
+pub struct ShadertoyProject {
+    pub common: String,
+    pub passes: [String; 5],
+    // the declarations of storage.wgsl, see ProjectStorage
+    pub storage: String,
+    // the code block of draw.wgsl, see ShadertoyDraw
+    pub draw: String,
+    // the code block of vertex.wgsl, see ShadertoyVertexArt
+    pub vertex_art: String,
+}
+
+impl ShadertoyProject {
+    pub fn new(common: &str, passes: [&str; 5]) -> Self {
+        Self {
+            common: common.to_string(),
+            passes: passes.map(|pass| pass.to_string()),
+            storage: String::new(),
+            draw: String::new(),
+            vertex_art: String::new(),
+        }
+    }
+
+    pub fn with_storage(mut self, storage: &str) -> Self {
+        self.storage = storage.to_string();
+        self
+    }
+
+    pub fn with_draw(mut self, draw: &str) -> Self {
+        self.draw = draw.to_string();
+        self
+    }
+
+    pub fn with_vertex_art(mut self, vertex_art: &str) -> Self {
+        self.vertex_art = vertex_art.to_string();
+        self
+    }
+}
+
+// Embeds the project in the given folder, relative to the calling file, into the binary.
+// The folder must contain common.wgsl, image.wgsl and buffer_a.wgsl to buffer_d.wgsl,
+// which may be empty. A storage.wgsl is added with
+// .with_storage(include_str!("<folder>/storage.wgsl")), a draw.wgsl with .with_draw and a
+// vertex.wgsl with .with_vertex_art.
+#[macro_export]
+macro_rules! include_shadertoy_project {
+    ($folder:literal) => {
+        $crate::ShadertoyProject::new(
+            include_str!(concat!($folder, "/common.wgsl")),
+            [
+                include_str!(concat!($folder, "/buffer_a.wgsl")),
+                include_str!(concat!($folder, "/buffer_b.wgsl")),
+                include_str!(concat!($folder, "/buffer_c.wgsl")),
+                include_str!(concat!($folder, "/buffer_d.wgsl")),
+                include_str!(concat!($folder, "/image.wgsl")),
+            ],
+        )
+    };
+}
+
+// Loads the project in the folder of a .shadertoy file, e.g.
+// asset_server.load("shadertoy/paint/project.shadertoy"). The content of the file is not
+// read, and the buffer passes, storage.wgsl, draw.wgsl and vertex.wgsl are empty if they
+// have no file. Only changes
+// to the .shadertoy file itself are hot reloaded. On the web a missing file is fetched as
+// the body of the 404 response, so all of the files must be there, even if empty.
+#[derive(Default)]
+pub struct ShadertoyProjectLoader;
+
+impl AssetLoader for ShadertoyProjectLoader {
+    fn load<'a>(
+        &'a self,
+        _bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let folder = load_context
+                .path()
+                .parent()
+                .map(|folder| folder.to_path_buf())
+                .unwrap_or_default();
+
+            let common_path = folder.join("common.wgsl");
+            let common = read_project_file(load_context, common_path, false).await?;
+            let mut passes: [String; 5] = Default::default();
+            for (index, name) in BUFFER_NAMES.iter().chain(["image"].iter()).enumerate() {
+                let path = folder.join(format!("{}.wgsl", name));
+                passes[index] = read_project_file(load_context, path, *name == "image").await?;
+            }
+
+            let storage_path = folder.join("storage.wgsl");
+            let storage = read_project_file(load_context, storage_path, false).await?;
+            let draw_path = folder.join("draw.wgsl");
+            let draw = read_project_file(load_context, draw_path, false).await?;
+            let vertex_art_path = folder.join("vertex.wgsl");
+            let vertex_art = read_project_file(load_context, vertex_art_path, false).await?;
+
+            load_context.set_default_asset(LoadedAsset::new(ShadertoyProject {
+                common,
+                passes,
+                storage,
+                draw,
+                vertex_art,
+            }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["shadertoy"]
+    }
+}
+
+// A missing file is empty unless it is required
+async fn read_project_file(
+    load_context: &LoadContext<'_>,
+    path: PathBuf,
+    required: bool,
+) -> Result<String, anyhow::Error> {
+    match load_context.read_asset_bytes(&path).await {
+        Ok(bytes) => Ok(String::from_utf8(bytes)?),
+        Err(AssetIoError::NotFound(_)) if !required => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// The project that the main canvas runs. Its passes are assembled in memory once it is
+// loaded, and again whenever it changes, so that nothing is read from or written to the
+// filesystem. Until then, the canvas waits on shaders that do not exist yet.
+#[derive(Resource)]
+pub struct ShadertoyActiveProject {
+    pub project: Handle<ShadertoyProject>,
+    pub include_debugger: bool,
+    // set with with_workgroup_sizes, which validates them
+    workgroup_sizes: PassWorkgroupSizes,
+    // the assembled shaders keep their handles, so a changed project replaces them in place
+    compute_shaders: [Handle<Shader>; 5],
+    fragment_shaders: [Handle<Shader>; 5],
+    draw_shader: Handle<Shader>,
+    vertex_art_shader: Handle<Shader>,
+}
+
+impl ShadertoyActiveProject {
+    pub fn new(project: Handle<ShadertoyProject>) -> Self {
+        Self {
+            project,
+            include_debugger: false,
+            workgroup_sizes: PassWorkgroupSizes::default(),
+            compute_shaders: [(); 5].map(|_| Handle::weak(HandleId::random::<Shader>())),
+            fragment_shaders: [(); 5].map(|_| Handle::weak(HandleId::random::<Shader>())),
+            draw_shader: Handle::weak(HandleId::random::<Shader>()),
+            vertex_art_shader: Handle::weak(HandleId::random::<Shader>()),
+        }
+    }
+
+    pub fn with_workgroup_sizes(mut self, workgroup_sizes: PassWorkgroupSizes) -> Self {
+        self.workgroup_sizes = workgroup_sizes.validated();
+        self
+    }
+
+    pub fn workgroup_sizes(&self) -> PassWorkgroupSizes {
+        self.workgroup_sizes
+    }
+
+    fn shader_handles(
+        &self,
+        pass_usages: PassUsages,
+        fragment_passes: [bool; 5],
+        storage: ProjectStorage,
+        dispatches: [PassDispatch; 5],
+        draw: bool,
+        vertex_art: bool,
+    ) -> ShaderHandles {
+        let [texture_a_shader, texture_b_shader, texture_c_shader, texture_d_shader, image_shader] =
+            self.compute_shaders.clone();
+        let active_passes = find_active_passes(&pass_usages);
+        let fragment = fragment_runnable(fragment_passes, &active_passes);
+
+        ShaderHandles {
+            image_shader,
+            texture_a_shader,
+            texture_b_shader,
+            texture_c_shader,
+            texture_d_shader,
+            active_passes,
+            pass_usages,
+            workgroup_sizes: self.workgroup_sizes,
+            fragment_shaders: match fragment {
+                true => Some(self.fragment_shaders.clone()),
+                false => None,
+            },
+            storage,
+            dispatches,
+            draw_shader: draw.then(|| self.draw_shader.clone()),
+            vertex_art_shader: vertex_art.then(|| self.vertex_art_shader.clone()),
+        }
+    }
+
+    pub fn assemble(
+        &self,
+        project: &ShadertoyProject,
+        shaders: &mut Assets<Shader>,
+    ) -> ShaderHandles {
+        let storage = reflect_project_storage(&project.storage);
+        let passes: Vec<AssembledPass> = project
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(index, code_block)| {
+                let buffer_type = BUFFER_NAMES.get(index).copied().unwrap_or("image");
+                assemble_pass(
+                    buffer_type,
+                    &project.common,
+                    code_block,
+                    self.include_debugger && buffer_type == "image",
+                    self.workgroup_sizes.get(buffer_type),
+                    &storage,
+                )
+            })
+            .collect();
+
+        for (index, pass) in passes.iter().enumerate() {
+            shaders.set_untracked(
+                &self.compute_shaders[index],
+                Shader::from_wgsl(pass.compute.clone()),
+            );
+            shaders.set_untracked(
+                &self.fragment_shaders[index],
+                Shader::from_wgsl(pass.fragment.clone()),
+            );
+        }
+
+        let pass_usages = PassUsages {
+            image: passes[4].usage,
+            buffers: [0, 1, 2, 3].map(|pass| passes[pass].usage),
+        };
+
+        let draw = assemble_draw_shader(&project.common, &project.draw, &storage);
+        if let Some(draw) = &draw {
+            shaders.set_untracked(&self.draw_shader, Shader::from_wgsl(draw.clone()));
+        }
+        let vertex_art = assemble_vertex_art_shader(&project.common, &project.vertex_art, &storage);
+        if let Some(vertex_art) = &vertex_art {
+            shaders.set_untracked(
+                &self.vertex_art_shader,
+                Shader::from_wgsl(vertex_art.clone()),
+            );
+        }
+
+        let fragment_passes = [0, 1, 2, 3, 4].map(|pass| passes[pass].fragment_runnable);
+        let dispatches = [0, 1, 2, 3, 4].map(|pass| passes[pass].dispatch);
+        self.shader_handles(
+            pass_usages,
+            fragment_passes,
+            storage,
+            dispatches,
+            draw.is_some(),
+            vertex_art.is_some(),
+        )
+    }
+}
+
+// Inserts the ShaderHandles of the ShadertoyActiveProject, assembling it when it is loaded
+// or changed. The render world needs ShaderHandles from the first frame on, so handles to
+// the shaders yet to be assembled are inserted while it loads.
+pub fn assemble_active_project(
+    mut commands: Commands,
+    active_project: Option<Res<ShadertoyActiveProject>>,
+    projects: Res<Assets<ShadertoyProject>>,
+    mut project_events: EventReader<AssetEvent<ShadertoyProject>>,
+    mut shaders: ResMut<Assets<Shader>>,
+) {
+    let active_project = match active_project {
+        Some(active_project) => active_project,
+        None => return,
+    };
+
+    let project_changed = project_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == active_project.project
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+
+    if !project_changed && !active_project.is_changed() {
+        return;
+    }
+
+    match projects.get(&active_project.project) {
+        Some(project) => {
+            commands.insert_resource(active_project.assemble(project, &mut shaders));
+        }
+        None if active_project.is_changed() => {
+            commands.insert_resource(active_project.shader_handles(
+                PassUsages::default(),
+                [true; 5],
+                ProjectStorage::default(),
+                Default::default(),
+                false,
+                false,
+            ));
+        }
+        None => {}
+    }
+}
@@ -0,0 +1,314 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        Extract,
+    },
+};
+
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+
+use crate::capture::{padded_bytes_per_row, unpad_rows, MapRequest};
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::{CaptureTarget, MainImage, ShadertoyClock, TextureRegion};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ReadbackId(u64);
+
+struct ReadbackRequest {
+    id: ReadbackId,
+    target: CaptureTarget,
+    region: Option<TextureRegion>,
+}
+
+// Reads regions of the buffers or of the main image back to the main world, e.g.
+//
+// let id = readback.request(CaptureTarget::BufferA, Some(TextureRegion::texel(position)));
+//
+// The data arrives as a ShadertoyReadbackResult event with the same id once the gpu has
+// copied it, usually one or two frames later. Requests are read after every pass has run
+// in the frame they were made in.
+#[derive(Resource, Default)]
+pub struct ShadertoyReadback {
+    next_id: u64,
+    requests: Vec<ReadbackRequest>,
+}
+
+impl ShadertoyReadback {
+    // None reads the whole texture
+    pub fn request(
+        &mut self,
+        target: CaptureTarget,
+        region: Option<TextureRegion>,
+    ) -> ReadbackId {
+        let id = ReadbackId(self.next_id);
+        self.next_id += 1;
+        self.requests.push(ReadbackRequest { id, target, region });
+        id
+    }
+}
+
+// The region is clamped to the size of the texture, the pixels are empty if it lies
+// outside of the texture or if the texture was not on the gpu yet
+pub struct ShadertoyReadbackResult {
+    pub id: ReadbackId,
+    pub target: CaptureTarget,
+    // iFrame and iTime of the frame the data was read in
+    pub frame: u32,
+    pub time: f32,
+    pub region: TextureRegion,
+    // linear rgba floats, row by row
+    pub pixels: Vec<f32>,
+}
+
+impl ShadertoyReadbackResult {
+    // relative to the origin of the region
+    pub fn texel(&self, x: u32, y: u32) -> Option<Vec4> {
+        if x >= self.region.size.x || y >= self.region.size.y {
+            return None;
+        }
+
+        let index = 4 * (y * self.region.size.x + x) as usize;
+        self.pixels.get(index..index + 4).map(Vec4::from_slice)
+    }
+}
+
+// Shared by the main and the render world
+#[derive(Clone, Resource, Default)]
+pub struct ReadbackQueue(Arc<Mutex<Vec<ShadertoyReadbackResult>>>);
+
+pub fn send_readback_results(
+    queue: Res<ReadbackQueue>,
+    mut readback: ResMut<ShadertoyReadback>,
+    mut results: EventWriter<ShadertoyReadbackResult>,
+) {
+    readback.requests.clear();
+    results.send_batch(queue.0.lock().unwrap().drain(..));
+}
+
+struct ExtractedReadback {
+    id: ReadbackId,
+    target: CaptureTarget,
+    image: Handle<Image>,
+    region: Option<TextureRegion>,
+}
+
+#[derive(Resource, Default)]
+pub struct ExtractedReadbacks(Vec<ExtractedReadback>);
+
+pub fn extract_readback_requests(
+    mut commands: Commands,
+    readback: Extract<Res<ShadertoyReadback>>,
+    main_image: Extract<Res<MainImage>>,
+    texture_a: Extract<Res<TextureA>>,
+    texture_b: Extract<Res<TextureB>>,
+    texture_c: Extract<Res<TextureC>>,
+    texture_d: Extract<Res<TextureD>>,
+) {
+    let buffers = [&texture_a.0, &texture_b.0, &texture_c.0, &texture_d.0];
+    let readbacks = readback
+        .requests
+        .iter()
+        .map(|request| ExtractedReadback {
+            id: request.id,
+            target: request.target,
+            image: request.target.image(&main_image.0, buffers).clone(),
+            region: request.region,
+        })
+        .collect();
+
+    commands.insert_resource(ExtractedReadbacks(readbacks));
+}
+
+struct PreparedReadback {
+    id: ReadbackId,
+    target: CaptureTarget,
+    image: Handle<Image>,
+    region: TextureRegion,
+    // None if there is nothing to copy
+    buffer: Option<Buffer>,
+    format: TextureFormat,
+    padded_bytes_per_row: u32,
+}
+
+#[derive(Resource, Default)]
+pub struct PreparedReadbacks(Vec<PreparedReadback>);
+
+// the readbacks of earlier frames whose buffers are being mapped
+struct PendingReadback {
+    readback: PreparedReadback,
+    map_request: Option<MapRequest>,
+    frame: u32,
+    time: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct PendingReadbacks(Vec<PendingReadback>);
+
+// the buffers are small and only live for one frame, so they are created per request
+pub fn prepare_readbacks(
+    readbacks: Res<ExtractedReadbacks>,
+    mut prepared: ResMut<PreparedReadbacks>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+) {
+    prepared.0 = readbacks
+        .0
+        .iter()
+        .map(|readback| {
+            let gpu_image = gpu_images.get(&readback.image);
+            let texture_size = gpu_image
+                .map(|gpu_image| gpu_image.size.as_uvec2())
+                .unwrap_or(UVec2::ZERO);
+            let format = gpu_image.map_or(TextureFormat::Rgba32Float, |gpu_image| {
+                gpu_image.texture_format
+            });
+            let region = readback
+                .region
+                .unwrap_or(TextureRegion {
+                    origin: UVec2::ZERO,
+                    size: texture_size,
+                })
+                .clamp(texture_size);
+
+            let padded_bytes_per_row = padded_bytes_per_row(region.size.x, format);
+            let buffer = (region.size.x > 0 && region.size.y > 0).then(|| {
+                render_device.create_buffer(&BufferDescriptor {
+                    label: Some("readback_buffer"),
+                    size: (padded_bytes_per_row * region.size.y) as u64,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                })
+            });
+
+            PreparedReadback {
+                id: readback.id,
+                target: readback.target,
+                image: readback.image.clone(),
+                region,
+                buffer,
+                format,
+                padded_bytes_per_row,
+            }
+        })
+        .collect();
+}
+
+// copies the requested regions into their buffers once every pass has run
+pub struct ReadbackNode;
+
+impl render_graph::Node for ReadbackNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+
+        for readback in world.resource::<PreparedReadbacks>().0.iter() {
+            if let (Some(gpu_image), Some(buffer)) =
+                (gpu_images.get(&readback.image), &readback.buffer)
+            {
+                render_context.command_encoder.copy_texture_to_buffer(
+                    ImageCopyTexture {
+                        texture: &gpu_image.texture,
+                        mip_level: 0,
+                        origin: Origin3d {
+                            x: readback.region.origin.x,
+                            y: readback.region.origin.y,
+                            z: 0,
+                        },
+                        aspect: TextureAspect::All,
+                    },
+                    ImageCopyBuffer {
+                        buffer,
+                        layout: ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: NonZeroU32::new(readback.padded_bytes_per_row),
+                            rows_per_image: None,
+                        },
+                    },
+                    Extent3d {
+                        width: readback.region.size.x,
+                        height: readback.region.size.y,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Runs after the frame has been submitted. It starts mapping the buffers of the frame and
+// sends the readbacks whose buffers are mapped by now, without waiting for the gpu.
+pub fn read_readbacks(
+    mut prepared: ResMut<PreparedReadbacks>,
+    mut pending: ResMut<PendingReadbacks>,
+    clock: Res<ShadertoyClock>,
+    queue: Res<ReadbackQueue>,
+    render_device: Res<RenderDevice>,
+) {
+    for readback in prepared.0.drain(..) {
+        let map_request = readback.buffer.as_ref().map(MapRequest::start);
+        pending.0.push(PendingReadback {
+            readback,
+            map_request,
+            frame: clock.frame(),
+            time: clock.time(),
+        });
+    }
+
+    if pending.0.is_empty() {
+        return;
+    }
+
+    render_device.poll(wgpu::Maintain::Poll);
+
+    let mut queue = queue.0.lock().unwrap();
+
+    pending.0.retain(|pending| {
+        let pixels = match (&pending.readback.buffer, &pending.map_request) {
+            (Some(buffer), Some(map_request)) => match map_request.result() {
+                None => return true,
+                Some(true) => {
+                    let pixels = unpad_rows(
+                        &buffer.slice(..).get_mapped_range(),
+                        pending.readback.region.size.x,
+                        pending.readback.padded_bytes_per_row,
+                        pending.readback.format,
+                    );
+                    buffer.unmap();
+                    pixels
+                }
+                Some(false) => {
+                    error!(
+                        "could not map the readback buffer of {}",
+                        pending.readback.target.name()
+                    );
+                    Vec::new()
+                }
+            },
+            _ => Vec::new(),
+        };
+
+        queue.push(ShadertoyReadbackResult {
+            id: pending.readback.id,
+            target: pending.readback.target,
+            frame: pending.frame,
+            time: pending.time,
+            region: pending.readback.region,
+            pixels,
+        });
+        false
+    });
+}
@@ -0,0 +1,309 @@
+use bevy::prelude::*;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::{
+    CaptureRequests, CaptureTarget, CapturedFrame, CapturedFrames, ShadertoyClock,
+    ShadertoyCommand, ShadertoyDeterministicMode,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingFormat {
+    // frames are decimated to the given rate and quantised to a palette of 256 colors
+    Gif { fps: u32 },
+    // uncompressed 8 bit 4:4:4 video that external encoders can take
+    Y4m,
+}
+
+// Records the canvas to a file. Start and stop it through the methods or with the record
+// key of ShadertoyKeyBindings. While recording, the clock advances by a fixed dt every
+// frame, so that slow frames are not dropped from the video. The mouse and iDate stay live.
+#[derive(Resource)]
+pub struct ShadertoyRecorder {
+    pub format: RecordingFormat,
+    pub path: PathBuf,
+    // used when neither ShadertoyDeterministicMode nor the fixed_dt of the clock is set
+    pub dt: f32,
+    wants_recording: bool,
+    recording: Option<Recording>,
+}
+
+impl Default for ShadertoyRecorder {
+    fn default() -> Self {
+        Self {
+            format: RecordingFormat::Gif { fps: 30 },
+            path: PathBuf::from("recording.gif"),
+            dt: 1.0 / 60.0,
+            wants_recording: false,
+            recording: None,
+        }
+    }
+}
+
+impl ShadertoyRecorder {
+    pub fn start(&mut self) {
+        self.wants_recording = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.wants_recording = false;
+    }
+
+    pub fn toggle(&mut self) {
+        self.wants_recording = !self.wants_recording;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+}
+
+struct Recording {
+    encoder: RecordingEncoder,
+    // captures that were requested but have not arrived yet
+    frames_in_flight: u32,
+    // the fixed_dt of the clock before the recording, restored at the end
+    previous_fixed_dt: Option<f32>,
+    frames_received: u32,
+}
+
+enum RecordingEncoder {
+    Gif {
+        encoder: GifEncoder<BufWriter<File>>,
+        fps: u32,
+        keep_every: u32,
+    },
+    Y4m {
+        writer: BufWriter<File>,
+        fps: u32,
+        header_written: bool,
+    },
+}
+
+impl RecordingEncoder {
+    fn new(format: RecordingFormat, path: &PathBuf, dt: f32) -> anyhow::Result<Self> {
+        let writer = BufWriter::new(File::create(path)?);
+        let source_fps = (1.0 / dt).round().max(1.0) as u32;
+
+        Ok(match format {
+            RecordingFormat::Gif { fps } => {
+                let mut encoder = GifEncoder::new_with_speed(writer, 10);
+                encoder.set_repeat(Repeat::Infinite)?;
+                let fps = fps.clamp(1, source_fps);
+                RecordingEncoder::Gif {
+                    encoder,
+                    fps,
+                    keep_every: (source_fps as f32 / fps as f32).round() as u32,
+                }
+            }
+            RecordingFormat::Y4m => RecordingEncoder::Y4m {
+                writer,
+                fps: source_fps,
+                header_written: false,
+            },
+        })
+    }
+
+    fn write_frame(&mut self, index: u32, frame: &CapturedFrame) -> anyhow::Result<()> {
+        let mut rgba = frame.to_srgb8();
+        // the alpha of the main image is not shown in the window either
+        for pixel in rgba.chunks_mut(4) {
+            pixel[3] = u8::MAX;
+        }
+
+        match self {
+            RecordingEncoder::Gif {
+                encoder,
+                fps,
+                keep_every,
+            } => {
+                if index % *keep_every == 0 {
+                    let image = RgbaImage::from_raw(frame.width, frame.height, rgba)
+                        .ok_or_else(|| anyhow::anyhow!("wrong buffer size"))?;
+                    let delay = Delay::from_numer_denom_ms(1000, *fps);
+                    encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+                }
+            }
+            RecordingEncoder::Y4m {
+                writer,
+                fps,
+                header_written,
+            } => {
+                if !*header_written {
+                    writeln!(
+                        writer,
+                        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                        frame.width, frame.height, fps
+                    )?;
+                    *header_written = true;
+                }
+
+                writeln!(writer, "FRAME")?;
+                writer.write_all(&rgb_to_yuv444(&rgba))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            RecordingEncoder::Gif { encoder, .. } => drop(encoder),
+            RecordingEncoder::Y4m { mut writer, .. } => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+// planar Y, U and V with BT.601 limited range coefficients
+fn rgb_to_yuv444(rgba: &[u8]) -> Vec<u8> {
+    let pixel_count = rgba.len() / 4;
+    let mut planes = vec![0u8; pixel_count * 3];
+
+    for (index, pixel) in rgba.chunks(4).enumerate() {
+        let (r, g, b) = (
+            pixel[0] as f32 / 255.0,
+            pixel[1] as f32 / 255.0,
+            pixel[2] as f32 / 255.0,
+        );
+
+        planes[index] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+        planes[pixel_count + index] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+        planes[2 * pixel_count + index] =
+            (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+    }
+
+    planes
+}
+
+pub struct ShadertoyRecorderPlugin;
+
+impl Plugin for ShadertoyRecorderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ShadertoyRecorder>()
+            .add_system(record_frames);
+    }
+}
+
+fn record_frames(
+    mut shadertoy_commands: EventReader<ShadertoyCommand>,
+    mut recorder: ResMut<ShadertoyRecorder>,
+    mut capture: ResMut<CaptureRequests>,
+    captured: Res<CapturedFrames>,
+    deterministic: Option<Res<ShadertoyDeterministicMode>>,
+    mut clock: ResMut<ShadertoyClock>,
+) {
+    for command in shadertoy_commands.iter() {
+        if *command == ShadertoyCommand::ToggleRecording {
+            recorder.toggle();
+        }
+    }
+
+    let recorder = &mut *recorder;
+
+    if let Some(recording) = &mut recorder.recording {
+        for frame in captured.of(CaptureTarget::MainImage) {
+            if let Err(e) = recording
+                .encoder
+                .write_frame(recording.frames_received, frame)
+            {
+                error!("could not record frame: {}", e);
+            }
+            recording.frames_received += 1;
+            recording.frames_in_flight = recording.frames_in_flight.saturating_sub(1);
+        }
+    }
+
+    match (recorder.wants_recording, recorder.recording.take()) {
+        (true, None) => {
+            let dt = match (&deterministic, clock.fixed_dt) {
+                (Some(deterministic), _) => deterministic.dt,
+                (None, Some(fixed_dt)) => fixed_dt,
+                (None, None) => recorder.dt,
+            };
+
+            match RecordingEncoder::new(recorder.format, &recorder.path, dt) {
+                Ok(encoder) => {
+                    let previous_fixed_dt = clock.fixed_dt.replace(dt);
+
+                    info!("recording to {}", recorder.path.display());
+                    recorder.recording = Some(Recording {
+                        encoder,
+                        frames_in_flight: 0,
+                        previous_fixed_dt,
+                        frames_received: 0,
+                    });
+                }
+                Err(e) => {
+                    error!("could not record to {}: {}", recorder.path.display(), e);
+                    recorder.wants_recording = false;
+                }
+            }
+        }
+        // wait for the requested frames before closing the file
+        (false, Some(recording)) if recording.frames_in_flight == 0 => {
+            clock.fixed_dt = recording.previous_fixed_dt;
+
+            match recording.encoder.finish() {
+                Ok(()) => info!("recording saved to {}", recorder.path.display()),
+                Err(e) => error!("could not save {}: {}", recorder.path.display(), e),
+            }
+        }
+        (_, recording) => recorder.recording = recording,
+    }
+
+    if recorder.wants_recording {
+        if let Some(recording) = &mut recorder.recording {
+            capture.request(CaptureTarget::MainImage);
+            recording.frames_in_flight += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, pixels: Vec<f32>) -> CapturedFrame {
+        CapturedFrame {
+            target: CaptureTarget::MainImage,
+            frame: 0,
+            time: 0.0,
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn converts_to_limited_range_planes() {
+        let rgba = [0, 0, 0, 255, 255, 255, 255, 255, 255, 0, 0, 255];
+        let planes = rgb_to_yuv444(&rgba);
+
+        // Y, then U, then V, one value per pixel
+        assert_eq!(planes, vec![16, 235, 81, 128, 128, 90, 128, 128, 240]);
+    }
+
+    #[test]
+    fn writes_y4m_frames() {
+        let path = std::env::temp_dir().join("bevy_shadertoy_wgsl_test.y4m");
+        let mut encoder = RecordingEncoder::new(RecordingFormat::Y4m, &path, 1.0 / 30.0).unwrap();
+
+        let black = frame(2, 1, vec![0.0; 8]);
+        encoder.write_frame(0, &black).unwrap();
+        encoder.write_frame(1, &black).unwrap();
+        encoder.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"YUV4MPEG2 W2 H1 F30:1 Ip A1:1 C444\n";
+        let frame = [b"FRAME\n".as_slice(), &[16, 16, 128, 128, 128, 128]].concat();
+        assert_eq!(bytes, [header.as_slice(), &frame, &frame].concat());
+    }
+}
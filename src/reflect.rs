@@ -0,0 +1,710 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Shader, ShaderProcessor, StorageTextureAccess},
+    utils::HashMap,
+};
+
+use naga::valid::{Capabilities, GlobalUse, ModuleInfo, ValidationFlags, Validator};
+
+use crate::MAX_STORAGE_BUFFERS;
+
+pub const BUFFER_NAMES: [&str; 4] = ["buffer_a", "buffer_b", "buffer_c", "buffer_d"];
+
+// Which of the buffers a-d are read and written by the "update" entry point of a pass,
+// indexed in the same order as BUFFER_NAMES, and likewise for the storage buffers of the
+// project, indexed by their binding
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferUsage {
+    pub reads: [bool; 4],
+    pub writes: [bool; 4],
+    // the read only buffers that are bound as sampled textures, see reflect_pass
+    pub sampled: [bool; 4],
+    pub storage_reads: [bool; MAX_STORAGE_BUFFERS],
+    pub storage_writes: [bool; MAX_STORAGE_BUFFERS],
+}
+
+impl BufferUsage {
+    // used when a shader cannot be reflected, so that the pass is kept as is
+    pub fn all() -> Self {
+        Self {
+            reads: [true; 4],
+            writes: [true; 4],
+            sampled: [false; 4],
+            storage_reads: [true; MAX_STORAGE_BUFFERS],
+            storage_writes: [true; MAX_STORAGE_BUFFERS],
+        }
+    }
+
+    // the narrowest storage access that covers the usage, None if the buffer is unused
+    pub fn access(&self, buffer: usize) -> Option<StorageTextureAccess> {
+        match (self.reads[buffer], self.writes[buffer]) {
+            (true, true) => Some(StorageTextureAccess::ReadWrite),
+            (true, false) => Some(StorageTextureAccess::ReadOnly),
+            (false, true) => Some(StorageTextureAccess::WriteOnly),
+            (false, false) => None,
+        }
+    }
+}
+
+// Buffer usage of every pass of a project
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassUsages {
+    pub image: BufferUsage,
+    pub buffers: [BufferUsage; 4],
+}
+
+impl Default for PassUsages {
+    fn default() -> Self {
+        Self {
+            image: BufferUsage::all(),
+            buffers: [BufferUsage::all(); 4],
+        }
+    }
+}
+
+impl PassUsages {
+    // passes are indexed as buffer_a..buffer_d, then image
+    pub fn get(&self, pass: usize) -> &BufferUsage {
+        if pass < 4 {
+            &self.buffers[pass]
+        } else {
+            &self.image
+        }
+    }
+}
+
+pub fn pass_name(pass: usize) -> &'static str {
+    BUFFER_NAMES.get(pass).copied().unwrap_or("image")
+}
+
+// The source with its line and nested block comments replaced by spaces, so that the
+// positions of the code are kept
+pub(crate) fn blank_comments(source: &str) -> String {
+    let mut blanked = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut block_depth = 0;
+    let mut line_comment = false;
+
+    while let Some(c) = chars.next() {
+        let in_comment = match (c, chars.peek()) {
+            ('\n', _) => {
+                line_comment = false;
+                false
+            }
+            _ if line_comment => true,
+            ('/', Some('*')) => {
+                chars.next();
+                blanked.push(' ');
+                block_depth += 1;
+                true
+            }
+            ('*', Some('/')) if block_depth > 0 => {
+                chars.next();
+                blanked.push(' ');
+                block_depth -= 1;
+                true
+            }
+            ('/', Some('/')) if block_depth == 0 => {
+                line_comment = true;
+                true
+            }
+            _ => block_depth > 0,
+        };
+
+        match in_comment {
+            true => blanked.extend(std::iter::repeat(' ').take(c.len_utf8())),
+            false => blanked.push(c),
+        }
+    }
+
+    blanked
+}
+
+// Parses both the regular and the INIT variants of an assembled shader
+pub(crate) fn parse_variants(source: &str) -> Result<Vec<naga::Module>, String> {
+    let processor = ShaderProcessor::default();
+    let shader = Shader::from_wgsl(source.to_string());
+    let mut variants = Vec::with_capacity(2);
+
+    for shader_defs in [vec![], vec!["INIT".to_string()]] {
+        let processed = processor
+            .process(
+                &shader,
+                &shader_defs,
+                &HashMap::default(),
+                &HashMap::default(),
+            )
+            .map_err(|e| e.to_string())?;
+        let wgsl = processed.get_wgsl_source().unwrap_or_default();
+
+        let module = naga::front::wgsl::parse_str(wgsl).map_err(|e| e.emit_to_string(wgsl))?;
+        variants.push(module);
+    }
+
+    Ok(variants)
+}
+
+// Parses and validates both variants of an assembled shader
+fn validate_variants(source: &str) -> Result<Vec<(naga::Module, ModuleInfo)>, String> {
+    parse_variants(source)?
+        .into_iter()
+        .map(|module| {
+            let module_info = Validator::new(ValidationFlags::all(), Capabilities::all())
+                .validate(&module)
+                .map_err(|e| e.to_string())?;
+            Ok((module, module_info))
+        })
+        .collect()
+}
+
+// Parses an assembled shader with naga and collects the global variable usage of its
+// "update" entry point. Both the INIT and the regular variants are analysed and merged.
+pub fn reflect_buffer_usage(source: &str) -> Result<BufferUsage, String> {
+    let mut usage = BufferUsage::default();
+
+    for (module, module_info) in validate_variants(source)? {
+        let entry_point_index = module
+            .entry_points
+            .iter()
+            .position(|entry_point| entry_point.name == "update")
+            .ok_or_else(|| "no \"update\" entry point".to_string())?;
+        let entry_point_info = module_info.get_entry_point(entry_point_index);
+
+        for (handle, global) in module.global_variables.iter() {
+            let name = global.name.as_deref().unwrap_or_default();
+            let global_use = entry_point_info[handle];
+            if let Some(index) = BUFFER_NAMES.iter().position(|buffer| *buffer == name) {
+                usage.reads[index] |= global_use.contains(GlobalUse::READ);
+                usage.writes[index] |= global_use.contains(GlobalUse::WRITE);
+            }
+
+            // the storage buffers are the only bindings of group 1, see ProjectStorage
+            let storage_binding = match (&global.space, &global.binding) {
+                (naga::AddressSpace::Storage { .. }, Some(binding)) if binding.group == 1 => {
+                    Some(binding.binding as usize).filter(|index| *index < MAX_STORAGE_BUFFERS)
+                }
+                _ => None,
+            };
+            if let Some(index) = storage_binding {
+                usage.storage_reads[index] |= global_use.contains(GlobalUse::READ);
+                usage.storage_writes[index] |= global_use.contains(GlobalUse::WRITE);
+            }
+        }
+    }
+
+    Ok(usage)
+}
+
+// Reflects an assembled shader and narrows its buffer declarations to the reflected usage.
+// The buffers the pass only reads are bound as sampled textures when the shader still
+// validates with them, and as read only storage textures otherwise. Empty code blocks have
+// an empty usage. Shaders that fail to reflect are kept as is and the error is left to the
+// pipeline cache.
+pub fn reflect_pass(
+    buffer_type: &str,
+    shader_content: String,
+    code_block: &str,
+) -> (String, BufferUsage) {
+    if code_block.trim().is_empty() {
+        return (shader_content, BufferUsage::default());
+    }
+
+    let mut usage = match reflect_buffer_usage(&shader_content) {
+        Ok(usage) => usage,
+        Err(e) => {
+            warn!("could not reflect {}: {}", buffer_type, e);
+            return (shader_content, BufferUsage::all());
+        }
+    };
+
+    for buffer in 0..4 {
+        if usage.access(buffer) == Some(StorageTextureAccess::ReadOnly) {
+            usage.sampled[buffer] = true;
+            let sampled = restrict_buffer_access(&shader_content, &usage);
+            usage.sampled[buffer] = validate_variants(&sampled).is_ok();
+        }
+    }
+
+    (restrict_buffer_access(&shader_content, &usage), usage)
+}
+
+// Rewrites the read_write declarations of the prelude to the access the pass actually
+// needs, so that they match the bind group layout generated for the pass. The loads of
+// sampled buffers get their mip level. Unused buffers are left untouched since they are
+// not part of the layout.
+pub fn restrict_buffer_access(source: &str, usage: &BufferUsage) -> String {
+    let mut source = source.to_string();
+
+    for (index, name) in BUFFER_NAMES.iter().enumerate() {
+        let declaration = match usage.access(index) {
+            Some(StorageTextureAccess::ReadOnly) if usage.sampled[index] => {
+                source = sampled_loads(&source, name);
+                "texture_2d<f32>"
+            }
+            Some(StorageTextureAccess::ReadOnly) => "texture_storage_2d<rgba32float, read>",
+            Some(StorageTextureAccess::WriteOnly) => "texture_storage_2d<rgba32float, write>",
+            _ => continue,
+        };
+
+        source = source.replace(
+            &format!("var {}: texture_storage_2d<rgba32float, read_write>;", name),
+            &format!("var {}: {};", name, declaration),
+        );
+    }
+
+    source
+}
+
+// Adds the mip level to the textureLoad calls on a buffer. A call the level cannot be added
+// to is left as is, and the shader then fails to validate with the buffer sampled.
+fn sampled_loads(source: &str, name: &str) -> String {
+    let call = format!("textureLoad({},", name);
+    let mut loaded = String::with_capacity(source.len());
+    let mut rest = source;
+
+    while let Some(start) = rest.find(&call) {
+        let arguments = start + call.len();
+        let mut depth = 1;
+        let end = rest[arguments..].find(|c: char| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        });
+
+        match end {
+            Some(end) => {
+                loaded.push_str(&rest[..arguments + end]);
+                loaded.push_str(", 0");
+                rest = &rest[arguments + end..];
+            }
+            None => {
+                loaded.push_str(&rest[..arguments]);
+                rest = &rest[arguments..];
+            }
+        }
+    }
+    loaded.push_str(rest);
+
+    loaded
+}
+
+// The buffer passes that are worth dispatching. The image pass always runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ActivePasses {
+    pub buffer_a: bool,
+    pub buffer_b: bool,
+    pub buffer_c: bool,
+    pub buffer_d: bool,
+}
+
+impl Default for ActivePasses {
+    fn default() -> Self {
+        Self {
+            buffer_a: true,
+            buffer_b: true,
+            buffer_c: true,
+            buffer_d: true,
+        }
+    }
+}
+
+impl ActivePasses {
+    pub fn from_array(active: [bool; 4]) -> Self {
+        Self {
+            buffer_a: active[0],
+            buffer_b: active[1],
+            buffer_c: active[2],
+            buffer_d: active[3],
+        }
+    }
+
+    pub fn as_array(&self) -> [bool; 4] {
+        [self.buffer_a, self.buffer_b, self.buffer_c, self.buffer_d]
+    }
+}
+
+// A buffer pass is active if at least one of the buffers it writes is read by the image
+// pass or by another active buffer pass, or if it writes a storage buffer. Passes with no
+// code have an empty usage, write nothing and are therefore never active.
+pub fn find_active_passes(usages: &PassUsages) -> ActivePasses {
+    let mut active = [false; 4];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for pass in 0..4 {
+            if active[pass] {
+                continue;
+            }
+
+            let is_read = |buffer: usize| {
+                usages.image.reads[buffer]
+                    || (0..4).any(|other| active[other] && usages.buffers[other].reads[buffer])
+            };
+
+            let writes_storage = usages.buffers[pass].storage_writes.iter().any(|w| *w);
+            if writes_storage
+                || (0..4).any(|buffer| usages.buffers[pass].writes[buffer] && is_read(buffer))
+            {
+                active[pass] = true;
+                changed = true;
+            }
+        }
+    }
+
+    for (index, is_active) in active.iter().enumerate() {
+        if !is_active {
+            info!(
+                "{} is empty or unused, it will not be dispatched",
+                BUFFER_NAMES[index]
+            );
+        }
+    }
+
+    ActivePasses::from_array(active)
+}
+
+// Ordering constraints (before, after) between the passes, indexed as in PassUsages::get.
+// Two passes that share a buffer keep the order of Shadertoy: a pass sees the current
+// frame of the passes before it that write the buffers it reads, and the previous frame
+// of the passes after it. The passes that share nothing can run in any order.
+pub fn pass_dependencies(usages: &PassUsages) -> Vec<(usize, usize)> {
+    let feeds = |writer: &BufferUsage, reader: &BufferUsage| {
+        (0..4).any(|buffer| writer.writes[buffer] && reader.reads[buffer])
+            || (0..MAX_STORAGE_BUFFERS)
+                .any(|buffer| writer.storage_writes[buffer] && reader.storage_reads[buffer])
+    };
+    let shares_output = |first: &BufferUsage, second: &BufferUsage| {
+        (0..4).any(|buffer| first.writes[buffer] && second.writes[buffer])
+            || (0..MAX_STORAGE_BUFFERS)
+                .any(|buffer| first.storage_writes[buffer] && second.storage_writes[buffer])
+    };
+
+    let mut dependencies = Vec::new();
+
+    for first in 0..5 {
+        for second in (first + 1)..5 {
+            let (first_usage, second_usage) = (usages.get(first), usages.get(second));
+
+            // a read of a buffer written by a later pass must happen before the write
+            if feeds(first_usage, second_usage)
+                || feeds(second_usage, first_usage)
+                || shares_output(first_usage, second_usage)
+            {
+                dependencies.push((first, second));
+            }
+        }
+    }
+
+    dependencies
+}
+
+// Kahn's algorithm over the given nodes, which keeps their order where the dependencies
+// allow it. Fails if the dependencies contain a cycle.
+pub fn topological_sort(
+    nodes: &[usize],
+    dependencies: &[(usize, usize)],
+) -> Result<Vec<usize>, String> {
+    let dependencies: Vec<(usize, usize)> = dependencies
+        .iter()
+        .copied()
+        .filter(|(before, after)| nodes.contains(before) && nodes.contains(after))
+        .collect();
+
+    let mut remaining: Vec<usize> = nodes.to_vec();
+    let mut sorted = Vec::with_capacity(nodes.len());
+
+    while !remaining.is_empty() {
+        let ready = remaining.iter().position(|node| {
+            !dependencies
+                .iter()
+                .any(|(before, after)| after == node && remaining.contains(before))
+        });
+
+        match ready {
+            Some(index) => sorted.push(remaining.remove(index)),
+            None => {
+                let names: Vec<&str> = remaining.iter().map(|pass| pass_name(*pass)).collect();
+                return Err(format!(
+                    "cycle in the pass dependencies between {:?}",
+                    names
+                ));
+            }
+        }
+    }
+
+    Ok(sorted)
+}
+
+// The order in which both backends run the given passes. pass_dependencies only puts
+// earlier passes before later ones, so the sort cannot fail, and the order of Shadertoy is
+// kept if it does.
+pub fn pass_order(passes: &[usize], usages: &PassUsages) -> Vec<usize> {
+    topological_sort(passes, &pass_dependencies(usages)).unwrap_or_else(|_| passes.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUFFER_DECLARATIONS: &str = "
+@group(0) @binding(1)
+var buffer_a: texture_storage_2d<rgba32float, read_write>;
+
+@group(0) @binding(2)
+var buffer_b: texture_storage_2d<rgba32float, read_write>;
+
+@group(0) @binding(3)
+var buffer_c: texture_storage_2d<rgba32float, read_write>;
+";
+
+    fn shader(body: &str) -> String {
+        format!(
+            "{}
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {{
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+{}
+}}
+",
+            BUFFER_DECLARATIONS, body
+        )
+    }
+
+    fn writes(buffers: &[usize]) -> BufferUsage {
+        let mut usage = BufferUsage::default();
+        for buffer in buffers {
+            usage.writes[*buffer] = true;
+        }
+        usage
+    }
+
+    #[test]
+    fn reflects_reads_and_writes() {
+        let source =
+            shader("    textureStore(buffer_b, location, textureLoad(buffer_a, location));");
+        let usage = reflect_buffer_usage(&source).unwrap();
+
+        assert_eq!(usage.reads, [true, false, false, false]);
+        assert_eq!(usage.writes, [false, true, false, false]);
+        assert_eq!(usage.access(0), Some(StorageTextureAccess::ReadOnly));
+        assert_eq!(usage.access(1), Some(StorageTextureAccess::WriteOnly));
+        assert_eq!(usage.access(2), None);
+    }
+
+    #[test]
+    fn merges_the_init_variant() {
+        let source = shader(
+            "#ifdef INIT
+    textureStore(buffer_c, location, vec4<f32>(0.0));
+#else
+    textureStore(buffer_a, location, textureLoad(buffer_a, location));
+#endif",
+        );
+        let usage = reflect_buffer_usage(&source).unwrap();
+
+        assert_eq!(usage.reads, [true, false, false, false]);
+        assert_eq!(usage.writes, [true, false, true, false]);
+        assert_eq!(usage.access(0), Some(StorageTextureAccess::ReadWrite));
+    }
+
+    #[test]
+    fn needs_an_update_entry_point() {
+        let source = format!("{}\nfn helper() {{}}\n", BUFFER_DECLARATIONS);
+        assert!(reflect_buffer_usage(&source).is_err());
+    }
+
+    #[test]
+    fn passes_nothing_reads_are_inactive() {
+        let mut usages = PassUsages {
+            image: BufferUsage::default(),
+            buffers: [
+                writes(&[0]),
+                writes(&[1]),
+                writes(&[2]),
+                BufferUsage::default(),
+            ],
+        };
+        usages.image.reads[0] = true;
+
+        let active = find_active_passes(&usages);
+        assert_eq!(active.as_array(), [true, false, false, false]);
+    }
+
+    #[test]
+    fn passes_read_by_active_passes_are_active() {
+        let mut usages = PassUsages {
+            image: BufferUsage::default(),
+            buffers: [writes(&[0]), writes(&[1]), writes(&[2]), writes(&[3])],
+        };
+        // the image reads a, a reads c and c reads itself
+        usages.image.reads[0] = true;
+        usages.buffers[0].reads[2] = true;
+        usages.buffers[2].reads[2] = true;
+
+        let active = find_active_passes(&usages);
+        assert_eq!(active.as_array(), [true, false, true, false]);
+    }
+
+    #[test]
+    fn passes_writing_storage_are_active() {
+        let mut usages = PassUsages {
+            image: BufferUsage::default(),
+            buffers: [BufferUsage::default(); 4],
+        };
+        usages.buffers[1].storage_writes[0] = true;
+
+        let active = find_active_passes(&usages);
+        assert_eq!(active.as_array(), [false, true, false, false]);
+    }
+
+    #[test]
+    fn earlier_writers_run_before_later_readers() {
+        let mut usages = PassUsages {
+            image: BufferUsage::default(),
+            buffers: [
+                writes(&[0]),
+                writes(&[1]),
+                BufferUsage::default(),
+                writes(&[3]),
+            ],
+        };
+        // b reads a, and d is independent of a and b
+        usages.buffers[1].reads[0] = true;
+        usages.image.reads[1] = true;
+        usages.image.reads[3] = true;
+
+        let dependencies = pass_dependencies(&usages);
+        assert!(dependencies.contains(&(0, 1)));
+        assert!(dependencies.contains(&(1, 4)));
+        assert!(dependencies.contains(&(3, 4)));
+        assert!(!dependencies
+            .iter()
+            .any(|(before, after)| *before == 3 && *after < 4));
+
+        assert_eq!(
+            topological_sort(&[0, 1, 3, 4], &dependencies),
+            Ok(vec![0, 1, 3, 4])
+        );
+    }
+
+    #[test]
+    fn later_writers_run_after_earlier_readers() {
+        let mut usages = PassUsages {
+            image: BufferUsage::default(),
+            buffers: [
+                writes(&[0]),
+                BufferUsage::default(),
+                BufferUsage::default(),
+                writes(&[3]),
+            ],
+        };
+        // a reads the previous frame of d
+        usages.buffers[0].reads[3] = true;
+        usages.image.reads[0] = true;
+
+        let dependencies = pass_dependencies(&usages);
+        assert_eq!(dependencies, vec![(0, 3), (0, 4)]);
+        assert_eq!(
+            topological_sort(&[0, 3, 4], &dependencies),
+            Ok(vec![0, 3, 4])
+        );
+    }
+
+    #[test]
+    fn shared_outputs_keep_the_order_of_shadertoy() {
+        let usages = PassUsages {
+            image: BufferUsage::default(),
+            buffers: [
+                writes(&[2]),
+                BufferUsage::default(),
+                writes(&[2]),
+                writes(&[]),
+            ],
+        };
+
+        assert_eq!(pass_dependencies(&usages), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn passes_feeding_each_other_keep_the_order_of_shadertoy() {
+        let mut usages = PassUsages {
+            image: BufferUsage::default(),
+            buffers: [
+                writes(&[0]),
+                writes(&[1]),
+                writes(&[2]),
+                BufferUsage::default(),
+            ],
+        };
+        usages.buffers[0].reads[1] = true;
+        usages.buffers[1].reads[2] = true;
+        usages.buffers[2].reads[0] = true;
+
+        let dependencies = pass_dependencies(&usages);
+        assert_eq!(
+            topological_sort(&[0, 1, 2, 4], &dependencies),
+            Ok(vec![0, 1, 2, 4])
+        );
+    }
+
+    #[test]
+    fn runs_the_passes_in_the_order_of_shadertoy() {
+        let mut usages = PassUsages {
+            image: BufferUsage::default(),
+            buffers: [writes(&[0]), writes(&[1]), writes(&[2]), writes(&[3])],
+        };
+        // d feeds a and c feeds b, which a sort by data flow alone would turn around
+        usages.buffers[0].reads[3] = true;
+        usages.buffers[1].reads[2] = true;
+        usages.image.reads = [true; 4];
+
+        assert_eq!(pass_order(&[0, 1, 2, 3, 4], &usages), vec![0, 1, 2, 3, 4]);
+        // the inactive passes are left out
+        assert_eq!(pass_order(&[0, 3, 4], &usages), vec![0, 3, 4]);
+    }
+
+    #[test]
+    fn cycles_cannot_be_sorted() {
+        assert!(topological_sort(&[0, 1, 4], &[(0, 1), (1, 0)]).is_err());
+        assert_eq!(topological_sort(&[0, 4], &[(0, 1), (1, 0)]), Ok(vec![0, 4]));
+    }
+
+    #[test]
+    fn blanks_comments_in_place() {
+        let source = "a /* b /* c */ d */ e // f /*\ng";
+        let blanked = blank_comments(source);
+
+        assert_eq!(blanked, "a                   e        \ng");
+        assert_eq!(blanked.len(), source.len());
+    }
+
+    #[test]
+    fn sampled_loads_get_a_mip_level() {
+        let source = "let a = textureLoad(buffer_a, vec2<i32>(f(1), 2));\n\
+                      let b = textureLoad(buffer_b, location);";
+
+        assert_eq!(
+            sampled_loads(source, "buffer_a"),
+            "let a = textureLoad(buffer_a, vec2<i32>(f(1), 2), 0);\n\
+             let b = textureLoad(buffer_b, location);"
+        );
+    }
+
+    #[test]
+    fn read_only_buffers_are_sampled() {
+        let source =
+            shader("    textureStore(buffer_b, location, textureLoad(buffer_a, location));");
+        let (restricted, usage) = reflect_pass("buffer_b", source, "code");
+
+        assert_eq!(usage.sampled, [true, false, false, false]);
+        assert!(restricted.contains("var buffer_a: texture_2d<f32>;"));
+        assert!(restricted.contains("textureLoad(buffer_a, location, 0)"));
+        assert!(restricted.contains("var buffer_b: texture_storage_2d<rgba32float, write>;"));
+        assert!(validate_variants(&restricted).is_ok());
+    }
+}
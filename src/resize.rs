@@ -0,0 +1,283 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        extract_resource::ExtractResource,
+        render_asset::RenderAssets,
+        render_graph,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        Extract,
+    },
+};
+
+use std::borrow::Cow;
+
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::{ChangedWindowSize, ShadertoyCanvas, WorkgroupSize};
+
+pub const RESAMPLE_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x6b3f_1d2c_9a47_e580);
+
+// What happens to the content of a buffer when the canvas is resized
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizePolicy {
+    // texels keep their coordinates, the buffer is cut or padded at the top and right
+    Crop,
+    // the centre of the old content stays at the centre of the buffer
+    Centre,
+    // the old content is scaled to the new size
+    Stretch,
+    // the buffer starts over empty, as before buffers were preserved
+    Clear,
+}
+
+impl Default for ResizePolicy {
+    fn default() -> Self {
+        ResizePolicy::Crop
+    }
+}
+
+#[derive(Clone, Resource, ExtractResource, Debug, Default)]
+pub struct ShadertoyResizePolicies {
+    pub buffer_a: ResizePolicy,
+    pub buffer_b: ResizePolicy,
+    pub buffer_c: ResizePolicy,
+    pub buffer_d: ResizePolicy,
+    // sends every pass back through INIT after a resize
+    pub reinit_on_resize: bool,
+}
+
+impl ShadertoyResizePolicies {
+    pub fn get(&self, buffer: usize) -> ResizePolicy {
+        match buffer {
+            0 => self.buffer_a,
+            1 => self.buffer_b,
+            2 => self.buffer_c,
+            _ => self.buffer_d,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct ResamplePipelines {
+    layout: BindGroupLayout,
+    crop_pipeline: CachedComputePipelineId,
+    centre_pipeline: CachedComputePipelineId,
+    stretch_pipeline: CachedComputePipelineId,
+}
+
+impl ResamplePipelines {
+    pub fn get(&self, policy: ResizePolicy) -> Option<CachedComputePipelineId> {
+        match policy {
+            ResizePolicy::Crop => Some(self.crop_pipeline),
+            ResizePolicy::Centre => Some(self.centre_pipeline),
+            ResizePolicy::Stretch => Some(self.stretch_pipeline),
+            ResizePolicy::Clear => None,
+        }
+    }
+}
+
+// the pipelines are queued once here so that they are compiled before the first resize
+impl FromWorld for ResamplePipelines {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("resample_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba32Float,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue_pipeline = |shader_def: &str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from("resample_pipeline")),
+                layout: Some(vec![layout.clone()]),
+                shader: RESAMPLE_SHADER_HANDLE.typed(),
+                shader_defs: vec![shader_def.to_string()],
+                entry_point: Cow::from("resample"),
+            })
+        };
+
+        let crop_pipeline = queue_pipeline("CROP");
+        let centre_pipeline = queue_pipeline("CENTRE");
+        let stretch_pipeline = queue_pipeline("STRETCH");
+
+        ResamplePipelines {
+            layout,
+            crop_pipeline,
+            centre_pipeline,
+            stretch_pipeline,
+        }
+    }
+}
+
+// The textures of the buffers as they were before the resize. They are kept alive until
+// their content has been copied into the new textures.
+#[derive(Resource, Default)]
+pub struct PendingResample {
+    old_textures: [Option<(TextureId, TextureView)>; 4],
+}
+
+pub struct ResampleJob {
+    bind_group: BindGroup,
+    pipeline: CachedComputePipelineId,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Resource, Default)]
+pub struct ResampleJobs(pub Vec<ResampleJob>);
+
+// runs before the resized images are prepared, so the render assets still hold the old textures
+pub fn extract_resized_buffers(
+    changed_window_size: Extract<Res<ChangedWindowSize>>,
+    texture_a: Extract<Res<TextureA>>,
+    texture_b: Extract<Res<TextureB>>,
+    texture_c: Extract<Res<TextureC>>,
+    texture_d: Extract<Res<TextureD>>,
+    gpu_images: Res<RenderAssets<Image>>,
+    mut pending: ResMut<PendingResample>,
+) {
+    if !changed_window_size.0 {
+        return;
+    }
+
+    let handles = [&texture_a.0, &texture_b.0, &texture_c.0, &texture_d.0];
+    for (index, handle) in handles.iter().enumerate() {
+        // during consecutive resizes, keep the texture that still holds the content
+        if pending.old_textures[index].is_none() {
+            pending.old_textures[index] = gpu_images
+                .get(*handle)
+                .map(|image| (image.texture.id(), image.texture_view.clone()));
+        }
+    }
+}
+
+pub fn queue_resample(
+    mut pending: ResMut<PendingResample>,
+    mut jobs: ResMut<ResampleJobs>,
+    canvas: Res<ShadertoyCanvas>,
+    policies: Res<ShadertoyResizePolicies>,
+    pipelines: Res<ResamplePipelines>,
+    gpu_images: Res<RenderAssets<Image>>,
+    texture_a: Res<TextureA>,
+    texture_b: Res<TextureB>,
+    texture_c: Res<TextureC>,
+    texture_d: Res<TextureD>,
+    render_device: Res<RenderDevice>,
+) {
+    jobs.0.clear();
+
+    let handles = [&texture_a.0, &texture_b.0, &texture_c.0, &texture_d.0];
+    for (index, handle) in handles.iter().enumerate() {
+        let new_image = match gpu_images.get(*handle) {
+            Some(new_image) => new_image,
+            None => continue,
+        };
+
+        // wait until the texture with the new size has been created
+        if new_image.size != Vec2::new(canvas.width as f32, canvas.height as f32) {
+            continue;
+        }
+
+        let (old_texture_id, old_view) = match pending.old_textures[index].take() {
+            Some(old_texture) => old_texture,
+            None => continue,
+        };
+
+        if old_texture_id == new_image.texture.id() {
+            continue;
+        }
+
+        let pipeline = match pipelines.get(policies.get(index)) {
+            Some(pipeline) => pipeline,
+            None => continue,
+        };
+
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("resample_bind_group"),
+            layout: &pipelines.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&old_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&new_image.texture_view),
+                },
+            ],
+        });
+
+        jobs.0.push(ResampleJob {
+            bind_group,
+            pipeline,
+            width: canvas.width,
+            height: canvas.height,
+        });
+    }
+}
+
+// Runs before every pass and copies the old content of the resized buffers
+#[derive(Default)]
+pub struct ResampleNode;
+
+impl render_graph::Node for ResampleNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let jobs = world.resource::<ResampleJobs>();
+
+        if jobs.0.is_empty() {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor {
+                label: Some("resample_compute_pass"),
+            });
+
+        for job in jobs.0.iter() {
+            if let Some(pipeline) = pipeline_cache.get_compute_pipeline(job.pipeline) {
+                let (workgroups_x, workgroups_y) =
+                    WorkgroupSize::SIZE_8X8.dispatch_count(job.width, job.height);
+
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &job.bind_group, &[]);
+                pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+            }
+        }
+
+        Ok(())
+    }
+}
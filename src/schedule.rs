@@ -0,0 +1,294 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{ExtractedUniform, FastForwardPlan, ShadertoyClock, ShadertoyState, BUFFER_NAMES};
+
+// When a pass is dispatched. Passes that are skipped keep the contents of their
+// buffer from the last time they ran.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassSchedule {
+    EveryFrame,
+    // runs on frames where i_frame is a multiple of N
+    EveryNthFrame(u32),
+    // runs the update shader a single time after the INIT dispatch
+    Once,
+    // only runs when a ShadertoyRerunPass event names this pass
+    OnDemand,
+}
+
+impl Default for PassSchedule {
+    fn default() -> Self {
+        PassSchedule::EveryFrame
+    }
+}
+
+#[derive(Clone, Resource, ExtractResource, Debug, Default)]
+pub struct ShadertoyPassSchedules {
+    pub image: PassSchedule,
+    pub buffer_a: PassSchedule,
+    pub buffer_b: PassSchedule,
+    pub buffer_c: PassSchedule,
+    pub buffer_d: PassSchedule,
+}
+
+impl ShadertoyPassSchedules {
+    pub fn get(&self, pass_name: &str) -> PassSchedule {
+        match pass_name {
+            "image" => self.image,
+            "buffer_a" => self.buffer_a,
+            "buffer_b" => self.buffer_b,
+            "buffer_c" => self.buffer_c,
+            "buffer_d" => self.buffer_d,
+            _ => PassSchedule::EveryFrame,
+        }
+    }
+}
+
+// Send with the name of the pass, e.g. ShadertoyRerunPass("buffer_c".to_string()),
+// to dispatch it on the next frame regardless of its schedule.
+pub struct ShadertoyRerunPass(pub String);
+
+// names of the passes requested through ShadertoyRerunPass during the current frame
+#[derive(Clone, Resource, ExtractResource, Default)]
+pub struct ShadertoyPassReruns(pub Vec<String>);
+
+pub fn collect_pass_reruns(
+    mut rerun_events: EventReader<ShadertoyRerunPass>,
+    mut reruns: ResMut<ShadertoyPassReruns>,
+) {
+    reruns.0.clear();
+    for rerun in rerun_events.iter() {
+        if rerun.0 != "image" && !BUFFER_NAMES.contains(&rerun.0.as_str()) {
+            warn!(
+                "cannot rerun {}, the passes are image and {}",
+                rerun.0,
+                BUFFER_NAMES.join(", ")
+            );
+            continue;
+        }
+        reruns.0.push(rerun.0.clone());
+    }
+}
+
+// Set by the render graph nodes whose pass has not reached its Update state, because
+// they are waiting for their pipelines or run INIT. It is cleared at the start of every
+// frame in the render world.
+#[derive(Resource, Default)]
+pub struct ShadertoyLoading(pub bool);
+
+pub fn clear_shadertoy_loading(mut loading: ResMut<ShadertoyLoading>) {
+    loading.0 = false;
+}
+
+// Whether every active pass was in its Update state in the last rendered frame. It is
+// shared by the main and the render world, the clock is held at frame 0 until it is set.
+#[derive(Clone, Resource, Default)]
+pub struct ShadertoyPassesReady(Arc<AtomicBool>);
+
+impl ShadertoyPassesReady {
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub fn report_passes_ready(ready: Res<ShadertoyPassesReady>, loading: Res<ShadertoyLoading>) {
+    ready.0.store(!loading.0, Ordering::Relaxed);
+}
+
+// Kept by every render graph node to decide whether its pass is dispatched
+// in the current frame.
+#[derive(Default)]
+pub struct PassRunState {
+    // whether the pass runs in the last step of the current frame
+    pub dispatch: bool,
+    // whether the pass runs in each of the steps before the last one while the clock
+    // fast-forwards, see FastForwardNode
+    pub earlier_steps: Vec<bool>,
+    pub updates_run: u32,
+}
+
+impl PassRunState {
+    // must be called at the end of Node::update, once the state of the node is known
+    pub fn update(&mut self, pass_name: &str, state: &ShadertoyState, world: &mut World) {
+        match state {
+            ShadertoyState::Loading => {
+                self.dispatch = false;
+                self.earlier_steps.clear();
+                world.resource_mut::<ShadertoyLoading>().0 = true;
+            }
+            ShadertoyState::Init => {
+                self.dispatch = true;
+                self.earlier_steps.clear();
+                self.updates_run = 0;
+                world.resource_mut::<ShadertoyLoading>().0 = true;
+            }
+            ShadertoyState::Update => {
+                let schedule = world.resource::<ShadertoyPassSchedules>().get(pass_name);
+                let rerun_requested = world
+                    .resource::<ShadertoyPassReruns>()
+                    .0
+                    .iter()
+                    .any(|name| name == pass_name);
+                let frame = world.resource::<ExtractedUniform>().i_frame as u32;
+
+                // the image pass keeps presenting the frozen buffers while the clock is paused
+                let steps = match pass_name {
+                    "image" => 1,
+                    _ => world.resource::<ShadertoyClock>().steps(),
+                };
+
+                // each step is scheduled on its own frame, the last one being the current
+                // frame, and a rerun only applies to the last step
+                self.earlier_steps.clear();
+                self.dispatch = rerun_requested;
+                for step in 0..steps {
+                    let step_frame = frame.saturating_sub(steps - 1 - step);
+                    let scheduled = match schedule {
+                        PassSchedule::EveryFrame => true,
+                        PassSchedule::EveryNthFrame(n) => n <= 1 || step_frame % n == 0,
+                        PassSchedule::Once => self.updates_run == 0,
+                        PassSchedule::OnDemand => false,
+                    };
+                    if step + 1 < steps {
+                        self.earlier_steps.push(scheduled);
+                        if scheduled {
+                            self.updates_run += 1;
+                        }
+                    } else {
+                        self.dispatch |= scheduled;
+                    }
+                }
+
+                if self.dispatch {
+                    self.updates_run += 1;
+                }
+            }
+        }
+    }
+
+    // hands the earlier steps of the pass to the FastForwardNode, which runs them under the
+    // name of the render graph node
+    pub fn plan_fast_forward(&self, node: &'static str, world: &mut World) {
+        if self.earlier_steps.contains(&true) {
+            world
+                .resource_mut::<FastForwardPlan>()
+                .0
+                .insert(node, self.earlier_steps.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::CommonUniform;
+
+    fn world(schedules: ShadertoyPassSchedules) -> World {
+        let mut world = World::new();
+        world.init_resource::<ShadertoyLoading>();
+        world.init_resource::<ShadertoyPassReruns>();
+        world.insert_resource(schedules);
+        // a single step per frame, as when the clock runs
+        let mut clock = ShadertoyClock::default();
+        clock.restart();
+        world.insert_resource(clock);
+        set_frame(&mut world, 0);
+        world
+    }
+
+    fn set_frame(world: &mut World, frame: u32) {
+        let common_uniform = CommonUniform {
+            i_frame: frame as f32,
+            ..CommonUniform::new()
+        };
+        world.insert_resource(ExtractedUniform(common_uniform.into_crevice()));
+    }
+
+    // runs the pass through the given frames of Update, and returns the frames it was
+    // dispatched on
+    fn dispatched_frames(
+        state: &mut PassRunState,
+        world: &mut World,
+        frames: std::ops::Range<u32>,
+    ) -> Vec<u32> {
+        frames
+            .filter(|frame| {
+                set_frame(world, *frame);
+                state.update("buffer_a", &ShadertoyState::Update, world);
+                state.dispatch
+            })
+            .collect()
+    }
+
+    #[test]
+    fn runs_every_nth_frame() {
+        let mut world = world(ShadertoyPassSchedules {
+            buffer_a: PassSchedule::EveryNthFrame(3),
+            ..default()
+        });
+        let mut state = PassRunState::default();
+
+        assert_eq!(
+            dispatched_frames(&mut state, &mut world, 0..7),
+            vec![0, 3, 6]
+        );
+    }
+
+    #[test]
+    fn runs_once_after_every_init() {
+        let mut world = world(ShadertoyPassSchedules {
+            buffer_a: PassSchedule::Once,
+            ..default()
+        });
+        let mut state = PassRunState::default();
+
+        state.update("buffer_a", &ShadertoyState::Init, &mut world);
+        assert!(state.dispatch);
+        assert_eq!(dispatched_frames(&mut state, &mut world, 0..3), vec![0]);
+
+        // a reset sends the pass through INIT again
+        state.update("buffer_a", &ShadertoyState::Init, &mut world);
+        assert_eq!(dispatched_frames(&mut state, &mut world, 0..3), vec![0]);
+    }
+
+    #[test]
+    fn runs_on_demand_when_rerun() {
+        let mut world = world(ShadertoyPassSchedules {
+            buffer_a: PassSchedule::OnDemand,
+            ..default()
+        });
+        let mut state = PassRunState::default();
+
+        assert!(dispatched_frames(&mut state, &mut world, 0..3).is_empty());
+
+        world.resource_mut::<ShadertoyPassReruns>().0 = vec!["buffer_a".to_string()];
+        assert_eq!(dispatched_frames(&mut state, &mut world, 3..4), vec![3]);
+
+        world.resource_mut::<ShadertoyPassReruns>().0 = vec!["buffer_b".to_string()];
+        assert!(dispatched_frames(&mut state, &mut world, 4..5).is_empty());
+    }
+
+    #[test]
+    fn ignores_reruns_of_unknown_passes() {
+        let mut app = App::new();
+        app.add_event::<ShadertoyRerunPass>()
+            .init_resource::<ShadertoyPassReruns>()
+            .add_system(collect_pass_reruns);
+
+        app.world
+            .send_event(ShadertoyRerunPass("bufer_c".to_string()));
+        app.world
+            .send_event(ShadertoyRerunPass("buffer_c".to_string()));
+        app.update();
+
+        assert_eq!(
+            app.world.resource::<ShadertoyPassReruns>().0,
+            vec!["buffer_c".to_string()]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use bevy::prelude::*;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::offline::save_frame;
+use crate::{
+    CaptureRequests, CaptureTarget, CapturedFrame, CapturedFrames, OfflineImageFormat,
+    ShaderHandles, ShadertoyCommand,
+};
+
+// magic bytes at the start of the raw buffer dumps
+pub const RAW_DUMP_MAGIC: &[u8; 8] = b"STOYF32\0";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferDumpFormat {
+    // RAW_DUMP_MAGIC, the width and the height as little endian u32, followed by
+    // width * height rgba texels of little endian f32, from the top row of the canvas down
+    Raw,
+    // linear rgba 32 bit float
+    Exr,
+}
+
+impl BufferDumpFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BufferDumpFormat::Raw => "rgba32f",
+            BufferDumpFormat::Exr => "exr",
+        }
+    }
+}
+
+// Saves the main image as 8 bit PNG and the buffers A to D in buffer_format, with the
+// project, iFrame and iTime in the file name, e.g. paint_buffer_a_000120_2.000s.rgba32f.
+// Use take or the screenshot and dump_buffers keys of ShadertoyKeyBindings.
+#[derive(Resource)]
+pub struct ShadertoyScreenshots {
+    pub directory: PathBuf,
+    pub buffer_format: BufferDumpFormat,
+    // defaults to the name of the folder of the image shader
+    pub project: Option<String>,
+    requested: Vec<CaptureTarget>,
+    in_flight: Vec<CaptureTarget>,
+}
+
+impl Default for ShadertoyScreenshots {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("screenshots"),
+            buffer_format: BufferDumpFormat::Raw,
+            project: None,
+            requested: Vec::new(),
+            in_flight: Vec::new(),
+        }
+    }
+}
+
+impl ShadertoyScreenshots {
+    pub fn take(&mut self, target: CaptureTarget) {
+        if !self.requested.contains(&target) {
+            self.requested.push(target);
+        }
+    }
+
+    pub fn dump_buffers(&mut self) {
+        for target in CaptureTarget::BUFFERS {
+            self.take(target);
+        }
+    }
+
+    fn file_name(&self, project: &str, frame: &CapturedFrame) -> String {
+        let extension = match frame.target {
+            CaptureTarget::MainImage => OfflineImageFormat::Png8.extension(),
+            _ => self.buffer_format.extension(),
+        };
+
+        format!(
+            "{}_{}_{:06}_{:.3}s.{}",
+            project,
+            frame.target.name(),
+            frame.frame,
+            frame.time,
+            extension
+        )
+    }
+}
+
+pub fn take_screenshots(
+    mut commands: EventReader<ShadertoyCommand>,
+    mut screenshots: ResMut<ShadertoyScreenshots>,
+    mut capture: ResMut<CaptureRequests>,
+    captured: Res<CapturedFrames>,
+    shader_handles: Option<Res<ShaderHandles>>,
+    asset_server: Res<AssetServer>,
+) {
+    if !screenshots.in_flight.is_empty() {
+        let project = screenshots.project.clone().unwrap_or_else(|| {
+            shader_handles
+                .and_then(|handles| project_name(&asset_server, &handles))
+                .unwrap_or_else(|| "shadertoy".to_string())
+        });
+
+        // the targets wait for their capture, which can take more than a frame
+        for frame in captured.0.iter() {
+            if !screenshots.in_flight.contains(&frame.target) {
+                continue;
+            }
+            screenshots
+                .in_flight
+                .retain(|target| *target != frame.target);
+
+            let path = screenshots
+                .directory
+                .join(screenshots.file_name(&project, frame));
+
+            match save_screenshot(&path, screenshots.buffer_format, frame) {
+                Ok(()) => info!("saved {}", path.display()),
+                Err(e) => error!("could not write {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    for command in commands.iter() {
+        match command {
+            ShadertoyCommand::Screenshot => screenshots.take(CaptureTarget::MainImage),
+            ShadertoyCommand::DumpBuffers => screenshots.dump_buffers(),
+            _ => {}
+        }
+    }
+
+    let screenshots = &mut *screenshots;
+    for target in screenshots.requested.drain(..) {
+        capture.request(target);
+        if !screenshots.in_flight.contains(&target) {
+            screenshots.in_flight.push(target);
+        }
+    }
+}
+
+// the shaders of a project are loaded from shaders/<project>/image.wgsl
+fn project_name(asset_server: &AssetServer, shader_handles: &ShaderHandles) -> Option<String> {
+    let path = asset_server.get_handle_path(&shader_handles.image_shader)?;
+    let project = path.path().parent()?.file_name()?;
+    Some(project.to_string_lossy().into_owned())
+}
+
+fn save_screenshot(
+    path: &Path,
+    buffer_format: BufferDumpFormat,
+    frame: &CapturedFrame,
+) -> anyhow::Result<()> {
+    match (frame.target, buffer_format) {
+        (CaptureTarget::MainImage, _) => save_frame(path, OfflineImageFormat::Png8, frame),
+        (_, BufferDumpFormat::Exr) => save_frame(path, OfflineImageFormat::Exr, frame),
+        (_, BufferDumpFormat::Raw) => save_raw_dump(path, frame),
+    }
+}
+
+fn save_raw_dump(path: &Path, frame: &CapturedFrame) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(RAW_DUMP_MAGIC)?;
+    writer.write_all(&frame.width.to_le_bytes())?;
+    writer.write_all(&frame.height.to_le_bytes())?;
+    for value in frame.pixels.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
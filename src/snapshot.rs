@@ -0,0 +1,406 @@
+use bevy::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    CaptureRequests, CaptureTarget, CapturedFrames, CommonUniform, ShaderHandles, ShadertoyCanvas,
+    ShadertoyClock, ShadertoyUploads,
+};
+
+// magic bytes at the start of a snapshot file
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"STOYSNAP";
+
+const SNAPSHOT_VERSION: u32 = 2;
+
+// Named values that the app tweaks at runtime and feeds into its project, e.g. the size of
+// a brush written through ShadertoyUploads. They are saved in snapshots and replaced by
+// the saved ones when a snapshot is loaded.
+#[derive(Clone, Resource, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShadertoyParameters(pub BTreeMap<String, f32>);
+
+// the part of the CommonUniform that is not derived from the clock or the canvas
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SnapshotUniform {
+    i_time: f32,
+    i_frame: f32,
+    i_mouse: [f32; 4],
+    i_date: [f32; 4],
+}
+
+// where the clock was, its settings such as the speed stay those of the running app
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SnapshotClock {
+    time: f32,
+    delta: f32,
+    frame: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+    width: u32,
+    height: u32,
+    uniform: SnapshotUniform,
+    clock: SnapshotClock,
+    parameters: ShadertoyParameters,
+}
+
+struct PendingSnapshot {
+    path: PathBuf,
+    uniform: SnapshotUniform,
+    clock: SnapshotClock,
+    parameters: ShadertoyParameters,
+}
+
+// Saves the state of the running project to a single file and resumes from it later.
+// A snapshot holds the buffers A to D, the mouse and date of the CommonUniform, the time
+// and frame of the ShadertoyClock and the ShadertoyParameters, and can only be loaded into
+// a canvas of the same size. Load snapshots once the passes are running, as the INIT of a
+// starting pass overwrites the buffers. Projects that declare storage buffers cannot be
+// saved, as those are not in the snapshot.
+//
+// The file starts with SNAPSHOT_MAGIC and the length of a RON header as little endian u32,
+// followed by the header and the content of the buffers as little endian rgba f32 texels,
+// from the top row down, for buffer A to D.
+#[derive(Resource, Default)]
+pub struct ShadertoySnapshots {
+    save_request: Option<PathBuf>,
+    load_request: Option<PathBuf>,
+    in_flight: Option<PendingSnapshot>,
+}
+
+impl ShadertoySnapshots {
+    // the file is written once the buffers have been read back, a frame or two later
+    pub fn save(&mut self, path: impl Into<PathBuf>) {
+        self.save_request = Some(path.into());
+    }
+
+    // the state is restored before the passes of this frame run
+    pub fn load(&mut self, path: impl Into<PathBuf>) {
+        self.load_request = Some(path.into());
+    }
+}
+
+pub fn save_snapshots(
+    mut snapshots: ResMut<ShadertoySnapshots>,
+    mut capture: ResMut<CaptureRequests>,
+    captured: Res<CapturedFrames>,
+    common_uniform: Res<CommonUniform>,
+    clock: Res<ShadertoyClock>,
+    parameters: Res<ShadertoyParameters>,
+    shader_handles: Option<Res<ShaderHandles>>,
+) {
+    // the buffers captured in the frame the snapshot was requested in arrive together
+    let arrived = snapshots.in_flight.as_ref().map_or(false, |pending| {
+        captured
+            .of(CaptureTarget::BufferA)
+            .any(|frame| frame.frame == pending.clock.frame)
+    });
+    if arrived {
+        let pending = snapshots.in_flight.take().unwrap();
+        match write_snapshot(&pending, &captured) {
+            Ok(()) => info!("saved snapshot {}", pending.path.display()),
+            Err(e) => error!("could not save snapshot {}: {}", pending.path.display(), e),
+        }
+    }
+
+    if let Some(path) = snapshots.save_request.take() {
+        let storage = shader_handles.map_or(false, |handles| !handles.storage.buffers.is_empty());
+        if storage {
+            error!(
+                "could not save snapshot {}: the project declares storage buffers",
+                path.display()
+            );
+            return;
+        }
+
+        for target in CaptureTarget::BUFFERS {
+            capture.request(target);
+        }
+
+        snapshots.in_flight = Some(PendingSnapshot {
+            path,
+            uniform: SnapshotUniform {
+                i_time: common_uniform.i_time,
+                i_frame: common_uniform.i_frame,
+                i_mouse: common_uniform.i_mouse.to_array(),
+                i_date: common_uniform.i_date.to_array(),
+            },
+            clock: SnapshotClock {
+                time: clock.time(),
+                delta: clock.delta(),
+                frame: clock.frame(),
+            },
+            parameters: parameters.clone(),
+        });
+    }
+}
+
+fn write_snapshot(pending: &PendingSnapshot, captured: &CapturedFrames) -> anyhow::Result<()> {
+    let buffers = CaptureTarget::BUFFERS
+        .iter()
+        .map(|target| {
+            captured
+                .of(*target)
+                .find(|frame| frame.frame == pending.clock.frame)
+                .ok_or_else(|| anyhow::anyhow!("{} was not read back", target.name()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let (width, height) = (buffers[0].width, buffers[0].height);
+    if buffers
+        .iter()
+        .any(|buffer| buffer.width != width || buffer.height != height)
+    {
+        anyhow::bail!("the buffers were resized while saving");
+    }
+
+    let header = ron::to_string(&SnapshotHeader {
+        version: SNAPSHOT_VERSION,
+        width,
+        height,
+        uniform: pending.uniform.clone(),
+        clock: pending.clock.clone(),
+        parameters: pending.parameters.clone(),
+    })?;
+
+    if let Some(parent) = pending.path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut writer = BufWriter::new(File::create(&pending.path)?);
+    writer.write_all(SNAPSHOT_MAGIC)?;
+    writer.write_all(&(header.len() as u32).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for buffer in buffers {
+        for value in buffer.pixels.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+pub fn load_snapshots(
+    mut snapshots: ResMut<ShadertoySnapshots>,
+    mut common_uniform: ResMut<CommonUniform>,
+    mut clock: ResMut<ShadertoyClock>,
+    mut parameters: ResMut<ShadertoyParameters>,
+    canvas: Res<ShadertoyCanvas>,
+    uploads: Res<ShadertoyUploads>,
+) {
+    let path = match snapshots.load_request.take() {
+        Some(path) => path,
+        None => return,
+    };
+
+    let (header, buffers) = match read_snapshot(&path) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            error!("could not load snapshot {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    if header.width != canvas.width || header.height != canvas.height {
+        error!(
+            "snapshot {} is {}x{}, the canvas is {}x{}",
+            path.display(),
+            header.width,
+            header.height,
+            canvas.width,
+            canvas.height
+        );
+        return;
+    }
+
+    for (target, texels) in CaptureTarget::BUFFERS.into_iter().zip(buffers) {
+        uploads.write_all(target, texels);
+    }
+
+    // the clock is ticked after this, so the passes continue with the frame after the
+    // one that was saved
+    clock.resume_at(header.clock.time, header.clock.delta, header.clock.frame);
+    *parameters = header.parameters;
+    common_uniform.i_time = header.uniform.i_time;
+    common_uniform.i_frame = header.uniform.i_frame;
+    common_uniform.i_mouse = Vec4::from_array(header.uniform.i_mouse);
+    common_uniform.i_date = Vec4::from_array(header.uniform.i_date);
+
+    info!("loaded snapshot {}", path.display());
+}
+
+fn read_snapshot(path: &Path) -> anyhow::Result<(SnapshotHeader, Vec<Vec<f32>>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != SNAPSHOT_MAGIC {
+        anyhow::bail!("not a snapshot");
+    }
+
+    let mut header_length = [0u8; 4];
+    reader.read_exact(&mut header_length)?;
+    let mut header = vec![0u8; u32::from_le_bytes(header_length) as usize];
+    reader.read_exact(&mut header)?;
+    let header: SnapshotHeader = ron::de::from_bytes(&header)?;
+
+    if header.version != SNAPSHOT_VERSION {
+        anyhow::bail!("unsupported version {}", header.version);
+    }
+
+    let buffer_bytes = (header.width * header.height * 16) as usize;
+    let buffers = CaptureTarget::BUFFERS
+        .iter()
+        .map(|_| {
+            let mut bytes = vec![0u8; buffer_bytes];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect())
+        })
+        .collect::<anyhow::Result<Vec<Vec<f32>>>>()?;
+
+    Ok((header, buffers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::CapturedFrame;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.init_resource::<ShadertoySnapshots>()
+            .init_resource::<CaptureRequests>()
+            .init_resource::<CapturedFrames>()
+            .init_resource::<ShadertoyUploads>()
+            .init_resource::<ShadertoyParameters>()
+            .insert_resource(CommonUniform::new())
+            .insert_resource(ShadertoyClock::default())
+            .insert_resource(ShadertoyCanvas {
+                width: 3,
+                height: 2,
+                borders: 0.0,
+                position: Vec3::ZERO,
+                active: true,
+            })
+            .add_system(load_snapshots)
+            .add_system(save_snapshots.after(load_snapshots));
+        app
+    }
+
+    // saves through the systems, with the buffers arriving in the frame after the request
+    fn save(app: &mut App, path: &Path, sizes: [(u32, u32); 4]) {
+        app.world.resource_mut::<ShadertoySnapshots>().save(path);
+        app.update();
+        assert!(app
+            .world
+            .resource::<CaptureRequests>()
+            .is_requested(CaptureTarget::BufferA));
+
+        let frame = app.world.resource::<ShadertoyClock>().frame();
+        let frames = CaptureTarget::BUFFERS
+            .into_iter()
+            .zip(sizes)
+            .enumerate()
+            .map(|(index, (target, (width, height)))| {
+                Arc::new(CapturedFrame {
+                    target,
+                    frame,
+                    time: 0.0,
+                    width,
+                    height,
+                    pixels: vec![index as f32 + 0.5; (width * height * 4) as usize],
+                })
+            })
+            .collect();
+        app.insert_resource(CapturedFrames(frames));
+        app.update();
+    }
+
+    #[test]
+    fn resumes_where_it_was_saved() {
+        let path = std::env::temp_dir().join("bevy_shadertoy_wgsl_test.snapshot");
+        let mut app = app();
+        app.world
+            .resource_mut::<ShadertoyClock>()
+            .resume_at(2.0, 0.5, 120);
+        app.world.resource_mut::<CommonUniform>().i_mouse = Vec4::new(1.0, 2.0, 3.0, 4.0);
+        app.world
+            .resource_mut::<ShadertoyParameters>()
+            .0
+            .insert("brush".to_string(), 3.0);
+        save(&mut app, &path, [(3, 2); 4]);
+
+        // the app moves on, and is paused at another speed when the snapshot is loaded
+        {
+            let mut clock = app.world.resource_mut::<ShadertoyClock>();
+            clock.resume_at(5.0, 0.1, 300);
+            clock.speed = 3.0;
+            clock.pause();
+        }
+        app.world.resource_mut::<CommonUniform>().i_mouse = Vec4::ZERO;
+        app.world.resource_mut::<ShadertoyParameters>().0.clear();
+
+        app.world.resource_mut::<ShadertoySnapshots>().load(&path);
+        app.update();
+        std::fs::remove_file(&path).unwrap();
+
+        let clock = app.world.resource::<ShadertoyClock>();
+        assert_eq!(
+            (clock.time(), clock.delta(), clock.frame()),
+            (2.0, 0.5, 120)
+        );
+        assert_eq!(clock.speed, 3.0);
+        assert!(clock.paused);
+        assert_eq!(
+            app.world.resource::<CommonUniform>().i_mouse,
+            Vec4::new(1.0, 2.0, 3.0, 4.0)
+        );
+        assert_eq!(
+            app.world.resource::<ShadertoyParameters>().0.get("brush"),
+            Some(&3.0)
+        );
+
+        let uploads = app.world.resource::<ShadertoyUploads>().queued();
+        assert_eq!(uploads.len(), 4);
+        for (index, (target, texels)) in uploads.into_iter().enumerate() {
+            assert_eq!(target, CaptureTarget::BUFFERS[index]);
+            assert_eq!(texels, vec![index as f32 + 0.5; 24]);
+        }
+    }
+
+    #[test]
+    fn does_not_save_buffers_of_different_sizes() {
+        let path = std::env::temp_dir().join("bevy_shadertoy_wgsl_resized.snapshot");
+        let mut app = app();
+        save(&mut app, &path, [(3, 2), (3, 2), (4, 2), (3, 2)]);
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn does_not_load_files_that_are_not_snapshots() {
+        let path = std::env::temp_dir().join("bevy_shadertoy_wgsl_not_a.snapshot");
+        std::fs::write(&path, b"not a snapshot at all").unwrap();
+
+        let mut app = app();
+        app.world.resource_mut::<ShadertoySnapshots>().load(&path);
+        app.update();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(app.world.resource::<ShadertoyUploads>().queued().is_empty());
+        assert_eq!(app.world.resource::<ShadertoyClock>().frame(), 0);
+    }
+}
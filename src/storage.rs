@@ -0,0 +1,512 @@
+use bevy::{
+    prelude::*,
+    render::{render_resource::*, renderer::RenderDevice},
+};
+
+use crate::{blank_comments, BufferUsage, ShaderHandles, WorkgroupSize};
+
+// wgpu's default limit of storage buffers per shader stage
+pub const MAX_STORAGE_BUFFERS: usize = 8;
+
+// A storage buffer declared by a project, as an array of a fixed number of elements
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageBufferLayout {
+    pub name: String,
+    pub element_count: u32,
+    // the array stride, padding included
+    pub element_size: u32,
+}
+
+impl StorageBufferLayout {
+    pub fn size(&self) -> u64 {
+        self.element_count as u64 * self.element_size as u64
+    }
+}
+
+// The storage buffers of a project, declared in its optional storage.wgsl:
+//
+//     struct Particle {
+//         position: vec2<f32>,
+//         velocity: vec2<f32>,
+//     };
+//
+//     var<storage, read_write> particles: array<Particle, 4096>;
+//
+// The declarations are copied into every pass with the bindings added, at group 1 in the
+// order they appear, so the buffers must be read_write and declared without bindings.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProjectStorage {
+    pub declarations: String,
+    // the same declarations with read access only, as the draw pass binds them in its
+    // vertex stage
+    pub draw_declarations: String,
+    pub buffers: Vec<StorageBufferLayout>,
+}
+
+impl ProjectStorage {
+    pub fn reflect(source: &str) -> Result<Self, String> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+
+        let mut buffers = Vec::new();
+        for (_, global) in module.global_variables.iter() {
+            if !matches!(global.space, naga::AddressSpace::Storage { .. }) {
+                continue;
+            }
+            let name = global.name.clone().unwrap_or_default();
+
+            if !matches!(
+                global.space,
+                naga::AddressSpace::Storage { access }
+                    if access.contains(naga::StorageAccess::LOAD | naga::StorageAccess::STORE)
+            ) {
+                return Err(format!(
+                    "{} must be declared var<storage, read_write>",
+                    name
+                ));
+            }
+
+            let (size, stride) = match module.types[global.ty].inner {
+                naga::TypeInner::Array { size, stride, .. } => (size, stride),
+                _ => return Err(format!("{} is not an array", name)),
+            };
+            let element_count = match size {
+                naga::ArraySize::Constant(constant) => match module.constants[constant].inner {
+                    naga::ConstantInner::Scalar {
+                        value: naga::ScalarValue::Uint(count),
+                        ..
+                    } => count as u32,
+                    naga::ConstantInner::Scalar {
+                        value: naga::ScalarValue::Sint(count),
+                        ..
+                    } => count as u32,
+                    _ => return Err(format!("the length of {} is not an integer", name)),
+                },
+                naga::ArraySize::Dynamic => {
+                    return Err(format!("{} needs a fixed number of elements", name))
+                }
+            };
+
+            if element_count == 0 {
+                return Err(format!("{} has no elements", name));
+            }
+            // dispatched along x only, in workgroups of at most MAX_INVOCATIONS
+            let max_elements = WorkgroupSize::MAX_WORKGROUPS * WorkgroupSize::MAX_INVOCATIONS;
+            if element_count > max_elements {
+                return Err(format!(
+                    "{} has {} elements, at most {} can be dispatched",
+                    name, element_count, max_elements
+                ));
+            }
+
+            buffers.push(StorageBufferLayout {
+                name,
+                element_count,
+                element_size: stride,
+            });
+        }
+
+        if buffers.len() > MAX_STORAGE_BUFFERS {
+            return Err(format!(
+                "{} storage buffers are declared, at most {} are supported",
+                buffers.len(),
+                MAX_STORAGE_BUFFERS
+            ));
+        }
+
+        // the declarations are searched for in the source without its comments
+        let source = blank_comments(source);
+        if source.contains("@group") || source.contains("@binding") {
+            return Err("the bindings of the storage buffers are added, remove them".into());
+        }
+
+        let mut declarations = String::new();
+        let mut draw_declarations = String::new();
+        let mut rest = source.as_str();
+        for binding in 0..buffers.len() {
+            let (start, end) =
+                find_storage_declaration(rest).ok_or("could not find the declarations")?;
+            let bound = format!("{}@group(1) @binding({})\n", &rest[..start], binding);
+            declarations.push_str(&bound);
+            declarations.push_str(&rest[start..end]);
+            draw_declarations.push_str(&bound);
+            draw_declarations.push_str("var<storage, read>");
+            rest = &rest[end..];
+        }
+        declarations.push_str(rest);
+        draw_declarations.push_str(rest);
+
+        Ok(Self {
+            declarations,
+            draw_declarations,
+            buffers,
+        })
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.buffers.iter().position(|buffer| buffer.name == name)
+    }
+}
+
+// The range of the first var<storage, ...> in the source, which may contain whitespace
+// anywhere between its tokens
+fn find_storage_declaration(source: &str) -> Option<(usize, usize)> {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+
+    source.match_indices("var").find_map(|(start, _)| {
+        // the end of a longer identifier
+        if source[..start]
+            .chars()
+            .next_back()
+            .map_or(false, is_identifier)
+        {
+            return None;
+        }
+        let rest = source[start + 3..].trim_start().strip_prefix('<')?;
+        let rest = rest.trim_start().strip_prefix("storage")?;
+        if rest.chars().next().map_or(true, is_identifier) {
+            return None;
+        }
+        let end = source.len() - rest.len() + rest.find('>')? + 1;
+        Some((start, end))
+    })
+}
+
+// Reflects the storage.wgsl of a project, an empty or broken one declares no buffers
+pub fn reflect_project_storage(source: &str) -> ProjectStorage {
+    match ProjectStorage::reflect(source) {
+        Ok(storage) => storage,
+        Err(e) => {
+            error!("could not reflect storage.wgsl: {}", e);
+            ProjectStorage::default()
+        }
+    }
+}
+
+// How the invocations of a pass are laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassDispatch {
+    // one invocation per pixel of the canvas
+    Pixels,
+    // one invocation per element of a storage buffer, the element index is the x of the
+    // global_invocation_id
+    Elements(u32),
+}
+
+impl Default for PassDispatch {
+    fn default() -> Self {
+        PassDispatch::Pixels
+    }
+}
+
+impl PassDispatch {
+    // A buffer pass that writes storage buffers and none of the buffers a-d is dispatched
+    // over the elements of the first storage buffer it writes. The image pass always covers
+    // the canvas.
+    pub fn from_usage(usage: &BufferUsage, storage: &ProjectStorage) -> Self {
+        if usage.writes.iter().any(|writes| *writes) {
+            return PassDispatch::Pixels;
+        }
+
+        match storage
+            .buffers
+            .iter()
+            .enumerate()
+            .find(|(index, _)| usage.storage_writes[*index])
+        {
+            Some((_, buffer)) => PassDispatch::Elements(buffer.element_count),
+            None => PassDispatch::Pixels,
+        }
+    }
+
+    // Element passes run the whole workgroup along x, widened where the elements would need
+    // more than MAX_WORKGROUPS workgroups. ProjectStorage::reflect keeps the widest one
+    // within MAX_INVOCATIONS.
+    pub fn workgroup_size(&self, workgroup_size: WorkgroupSize) -> WorkgroupSize {
+        match self {
+            PassDispatch::Pixels => workgroup_size,
+            PassDispatch::Elements(count) => WorkgroupSize {
+                x: (workgroup_size.x * workgroup_size.y).max(
+                    (count + WorkgroupSize::MAX_WORKGROUPS - 1) / WorkgroupSize::MAX_WORKGROUPS,
+                ),
+                y: 1,
+            },
+        }
+    }
+
+    pub fn dispatch_count(
+        &self,
+        workgroup_size: WorkgroupSize,
+        width: u32,
+        height: u32,
+    ) -> (u32, u32) {
+        match self {
+            PassDispatch::Pixels => workgroup_size.dispatch_count(width, height),
+            PassDispatch::Elements(count) => {
+                self.workgroup_size(workgroup_size).dispatch_count(*count, 1)
+            }
+        }
+    }
+
+    // the early return of the invocations that fall outside of the canvas or of the buffer
+    pub fn bounds_check(&self, invocation_id: &str) -> String {
+        match self {
+            PassDispatch::Pixels => format!(
+                "\n    if ({0}.x >= u32(uni.iResolution.x) || {0}.y >= u32(uni.iResolution.y)) {{\n        return;\n    }}\n",
+                invocation_id
+            ),
+            PassDispatch::Elements(count) => format!(
+                "\n    if ({}.x >= {}u) {{\n        return;\n    }}\n",
+                invocation_id, count
+            ),
+        }
+    }
+}
+
+// The storage buffers of the main canvas and their bind groups, rebuilt when a project
+// declaring different ones is loaded. Their content is zeroed when they are created, the
+// INIT dispatch of the passes is the place to fill them.
+#[derive(Resource)]
+pub struct ShadertoyStorageBuffers {
+    pub storage: ProjectStorage,
+    pub layout: BindGroupLayout,
+    pub buffers: Vec<Buffer>,
+    pub bind_group: BindGroup,
+    // the buffers bound read only to the vertex and fragment stages of the draw pass
+    pub draw_layout: BindGroupLayout,
+    pub draw_bind_group: BindGroup,
+}
+
+fn storage_layout(
+    render_device: &RenderDevice,
+    storage: &ProjectStorage,
+    visibility: ShaderStages,
+    read_only: bool,
+) -> BindGroupLayout {
+    let entries: Vec<BindGroupLayoutEntry> = storage
+        .buffers
+        .iter()
+        .enumerate()
+        .map(|(binding, buffer)| BindGroupLayoutEntry {
+            binding: binding as u32,
+            visibility,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(buffer.size()),
+            },
+            count: None,
+        })
+        .collect();
+
+    render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("storage_layout"),
+        entries: &entries,
+    })
+}
+
+impl ShadertoyStorageBuffers {
+    pub fn new(render_device: &RenderDevice, storage: &ProjectStorage) -> Self {
+        let layout = storage_layout(render_device, storage, ShaderStages::COMPUTE, false);
+        let draw_layout = storage_layout(
+            render_device,
+            storage,
+            ShaderStages::VERTEX_FRAGMENT,
+            true,
+        );
+
+        let buffers: Vec<Buffer> = storage
+            .buffers
+            .iter()
+            .map(|buffer| {
+                render_device.create_buffer(&BufferDescriptor {
+                    label: Some(&buffer.name),
+                    size: buffer.size(),
+                    usage: BufferUsages::STORAGE,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        let entries: Vec<BindGroupEntry> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("storage_bind_group"),
+            layout: &layout,
+            entries: &entries,
+        });
+        let draw_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("storage_draw_bind_group"),
+            layout: &draw_layout,
+            entries: &entries,
+        });
+
+        Self {
+            storage: storage.clone(),
+            layout,
+            buffers,
+            bind_group,
+            draw_layout,
+            draw_bind_group,
+        }
+    }
+}
+
+impl FromWorld for ShadertoyStorageBuffers {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        ShadertoyStorageBuffers::new(render_device, &ProjectStorage::default())
+    }
+}
+
+pub fn prepare_storage_buffers(
+    all_shader_handles: Res<ShaderHandles>,
+    render_device: Res<RenderDevice>,
+    mut storage_buffers: ResMut<ShadertoyStorageBuffers>,
+) {
+    if storage_buffers.storage != all_shader_handles.storage {
+        *storage_buffers =
+            ShadertoyStorageBuffers::new(&render_device, &all_shader_handles.storage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STORAGE: &str = "struct Particle {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+};
+
+// var<storage, read_write> commented: array<f32, 4>;
+var<storage, read_write> particles: array<Particle, 4096>;
+var<storage, read_write> counts: array<u32, 16>;
+";
+
+    #[test]
+    fn reflects_the_buffers_in_order() {
+        let storage = ProjectStorage::reflect(STORAGE).unwrap();
+
+        assert_eq!(
+            storage.buffers,
+            vec![
+                StorageBufferLayout {
+                    name: "particles".to_string(),
+                    element_count: 4096,
+                    element_size: 16,
+                },
+                StorageBufferLayout {
+                    name: "counts".to_string(),
+                    element_count: 16,
+                    element_size: 4,
+                },
+            ]
+        );
+        assert_eq!(storage.buffers[0].size(), 4096 * 16);
+        assert_eq!(storage.index("counts"), Some(1));
+    }
+
+    #[test]
+    fn binds_the_declarations_and_not_the_comments() {
+        let storage = ProjectStorage::reflect(STORAGE).unwrap();
+
+        assert!(!storage.declarations.contains("commented"));
+        assert!(storage.declarations.contains(
+            "@group(1) @binding(0)\nvar<storage, read_write> particles: array<Particle, 4096>;\n@group(1) @binding(1)\nvar<storage, read_write> counts"
+        ));
+        assert!(storage.draw_declarations.contains(
+            "@group(1) @binding(0)\nvar<storage, read> particles: array<Particle, 4096>;\n@group(1) @binding(1)\nvar<storage, read> counts"
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_declarations() {
+        for source in [
+            "var<storage, read> values: array<f32, 4>;",
+            "var<storage, read_write> values: array<f32>;",
+            "var<storage, read_write> values: array<f32, 0>;",
+            "var<storage, read_write> value: f32;",
+            "@group(1) @binding(0) var<storage, read_write> values: array<f32, 4>;",
+            "var<storage, read_write> values: array<f32, 16776961>;",
+        ] {
+            assert!(ProjectStorage::reflect(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn binds_declarations_spread_over_whitespace() {
+        let source = "var <storage,read_write> values: array<f32, 4>;\nvar\n<\tstorage, read_write> more: array<f32, 4>;";
+        let storage = ProjectStorage::reflect(source).unwrap();
+
+        assert_eq!(
+            storage.declarations,
+            "@group(1) @binding(0)\nvar <storage,read_write> values: array<f32, 4>;\n@group(1) @binding(1)\nvar\n<\tstorage, read_write> more: array<f32, 4>;"
+        );
+        assert_eq!(
+            storage.draw_declarations,
+            "@group(1) @binding(0)\nvar<storage, read> values: array<f32, 4>;\n@group(1) @binding(1)\nvar<storage, read> more: array<f32, 4>;"
+        );
+    }
+
+    #[test]
+    fn dispatches_over_the_first_storage_buffer_written() {
+        let storage = ProjectStorage::reflect(STORAGE).unwrap();
+
+        let mut usage = BufferUsage::default();
+        assert_eq!(
+            PassDispatch::from_usage(&usage, &storage),
+            PassDispatch::Pixels
+        );
+
+        usage.storage_writes[1] = true;
+        assert_eq!(
+            PassDispatch::from_usage(&usage, &storage),
+            PassDispatch::Elements(16)
+        );
+
+        // a pass that also writes a buffer covers the canvas
+        usage.writes[0] = true;
+        assert_eq!(
+            PassDispatch::from_usage(&usage, &storage),
+            PassDispatch::Pixels
+        );
+    }
+
+    #[test]
+    fn dispatches_elements_along_x() {
+        let dispatch = PassDispatch::Elements(100);
+
+        assert_eq!(
+            dispatch.workgroup_size(WorkgroupSize::SIZE_8X8),
+            WorkgroupSize { x: 64, y: 1 }
+        );
+        assert_eq!(
+            dispatch.dispatch_count(WorkgroupSize::SIZE_8X8, 960, 600),
+            (2, 1)
+        );
+        assert_eq!(
+            PassDispatch::Pixels.dispatch_count(WorkgroupSize::SIZE_8X8, 960, 600),
+            (120, 75)
+        );
+    }
+
+    #[test]
+    fn widens_the_workgroups_of_many_elements() {
+        let dispatch = PassDispatch::Elements(65535 * 100 + 1);
+
+        assert_eq!(
+            dispatch.workgroup_size(WorkgroupSize::SIZE_8X8),
+            WorkgroupSize { x: 101, y: 1 }
+        );
+        assert_eq!(
+            dispatch.dispatch_count(WorkgroupSize::SIZE_8X8, 960, 600),
+            (64887, 1)
+        );
+    }
+}
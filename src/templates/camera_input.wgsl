@@ -0,0 +1,26 @@
+// Copies the image rendered by a camera into a buffer, stretched to the size of the buffer.
+// Sampling the camera target converts its format, e.g. srgb bytes to linear floats.
+
+@group(0) @binding(0)
+var camera_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var camera_sampler: sampler;
+
+@group(0) @binding(2)
+var buffer: texture_storage_2d<rgba32float, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn copy_camera(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(buffer));
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+
+    if (location.x >= size.x || location.y >= size.y) {
+        return;
+    }
+
+    let uv = (vec2<f32>(location) + 0.5) / vec2<f32>(size);
+    let color = textureSampleLevel(camera_texture, camera_sampler, uv, 0.0);
+
+    textureStore(buffer, location, color);
+}
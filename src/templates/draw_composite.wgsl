@@ -0,0 +1,34 @@
+// Blends what the draw pass rendered into its image. The draw target holds colors
+// premultiplied by their alpha, or the straight color of the last primitive with the
+// STRAIGHT_ALPHA shader def, when it is not blendable. The ADDITIVE shader def adds them
+// instead.
+
+@group(0) @binding(0)
+var target_image: texture_storage_2d<rgba32float, read_write>;
+
+@group(0) @binding(1)
+var drawn: texture_2d<f32>;
+
+@compute @workgroup_size(8, 8, 1)
+fn composite(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(drawn));
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+
+    if (location.x >= size.x || location.y >= size.y) {
+        return;
+    }
+
+#ifdef STRAIGHT_ALPHA
+    let color = textureLoad(drawn, location, 0);
+    let source = vec4<f32>(color.rgb * color.a, color.a);
+#else
+    let source = textureLoad(drawn, location, 0);
+#endif
+    let destination = textureLoad(target_image, location);
+
+#ifdef ADDITIVE
+    textureStore(target_image, location, destination + source);
+#else
+    textureStore(target_image, location, source + destination * (1.0 - source.a));
+#endif
+}
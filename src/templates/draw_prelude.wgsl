@@ -0,0 +1,27 @@
+{{FRAGMENT_PRELUDE}}
+
+{{STORAGE}}
+
+// the counts of the draw call
+struct DrawCounts {
+    vertexCount: u32,
+    instanceCount: u32,
+    padding0: u32,
+    padding1: u32,
+};
+
+@group(0) @binding(5)
+var<uniform> draw_counts: DrawCounts;
+
+// what the vertex shader hands to the fragment shader. corner goes from -1 to 1 across a
+// quad of the draw pass and is 0 otherwise.
+struct DrawVertex {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) corner: vec2<f32>,
+};
+
+// the clip space position of a point in pixels, from the bottom left corner as iMouse
+fn pixelToClip(position: vec2<f32>) -> vec4<f32> {
+    return vec4<f32>(position / uni.iResolution.xy * 2.0 - 1.0, 0.0, 1.0);
+}
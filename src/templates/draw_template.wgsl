@@ -0,0 +1,32 @@
+{{DRAW_PRELUDE}}
+
+{{COMMON}}
+
+{{CODE_BLOCK}}
+
+@vertex
+fn vertex(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> DrawVertex {
+#ifdef POINTS
+    let corner = vec2<f32>(0.0);
+#else
+    // the two triangles of a quad
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+#endif
+    return drawVertex(instance_index, corner);
+}
+
+@fragment
+fn fragment(draw_vertex: DrawVertex) -> @location(0) vec4<f32> {
+    return drawFragment(draw_vertex);
+}
@@ -0,0 +1,10 @@
+{{FRAGMENT_PRELUDE}}
+
+{{COMMON}}
+
+{{CODE_BLOCK}}
+
+@fragment
+fn fragment(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return mainImage(position.xy);
+}
@@ -0,0 +1,31 @@
+{{FRAGMENT_PRELUDE}}
+
+@group(0) @binding(6)
+var font_texture: texture_2d<f32>;
+
+@group(0) @binding(7)
+var font_texture_sampler: sampler;
+
+@group(0) @binding(8)
+var rgba_noise_256_texture: texture_2d<f32>;
+
+@group(0) @binding(9)
+var rgba_noise_256_texture_sampler: sampler;
+
+@group(0) @binding(10)
+var blue_noise_texture: texture_2d<f32>;
+
+@group(0) @binding(11)
+var blue_noise_texture_sampler: sampler;
+
+
+{{DEBUGGER}}
+
+{{COMMON}}
+
+{{CODE_BLOCK}}
+
+@fragment
+fn fragment(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return mainImage(position.xy);
+}
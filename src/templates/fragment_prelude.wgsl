@@ -0,0 +1,50 @@
+struct CommonUniform {
+    iResolution: vec2<f32>,
+    changed_window_size: f32,
+    padding0: f32,
+    
+    iTime: f32,
+    iTimeDelta: f32,
+    iFrame: f32,
+    iSampleRate: f32,
+    
+    iMouse: vec4<f32>,
+    
+
+    iChannelTime: vec4<f32>,
+    iChannelResolution: vec4<f32>,
+    iDate: vec4<f32>,
+};
+
+
+@group(0) @binding(0)
+var<uniform> uni: CommonUniform;
+
+// the buffers as they were before the pass, a pass never reads what it is writing
+@group(0) @binding(1)
+var buffer_a: texture_2d<f32>;
+
+@group(0) @binding(2)
+var buffer_b: texture_2d<f32>;
+
+@group(0) @binding(3)
+var buffer_c: texture_2d<f32>;
+
+@group(0) @binding(4)
+var buffer_d: texture_2d<f32>;
+
+fn load_buffer_a(location: vec2<i32>) -> vec4<f32> {
+    return textureLoad(buffer_a, location, 0);
+}
+
+fn load_buffer_b(location: vec2<i32>) -> vec4<f32> {
+    return textureLoad(buffer_b, location, 0);
+}
+
+fn load_buffer_c(location: vec2<i32>) -> vec4<f32> {
+    return textureLoad(buffer_c, location, 0);
+}
+
+fn load_buffer_d(location: vec2<i32>) -> vec4<f32> {
+    return textureLoad(buffer_d, location, 0);
+}
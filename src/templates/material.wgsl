@@ -0,0 +1,62 @@
+// Samples a canvas on a 3D mesh. The canvas is the base color of a pbr surface when
+// SHADERTOY_LIT is set, otherwise it is shown as it is.
+
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+struct ShadertoyMaterial {
+    uv_scale: vec2<f32>,
+    uv_offset: vec2<f32>,
+    emissive_intensity: f32,
+    perceptual_roughness: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: ShadertoyMaterial;
+@group(1) @binding(1)
+var canvas_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var canvas_sampler: sampler;
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+#ifdef VERTEX_UVS
+    let uv = in.uv * material.uv_scale + material.uv_offset;
+#else
+    let uv = material.uv_offset;
+#endif
+    let color = textureSample(canvas_texture, canvas_sampler, uv);
+
+#ifdef SHADERTOY_LIT
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = color;
+    pbr_input.material.perceptual_roughness = material.perceptual_roughness;
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = prepare_world_normal(in.world_normal, false, in.is_front);
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = normalize(pbr_input.world_normal);
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+    var output_color = pbr(pbr_input);
+#else
+    var output_color = vec4<f32>(color.rgb * material.emissive_intensity, color.a);
+#endif
+
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color);
+#endif
+    return output_color;
+}
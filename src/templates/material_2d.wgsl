@@ -0,0 +1,29 @@
+// Samples a canvas on a 2D mesh
+
+#import bevy_sprite::mesh2d_types
+#import bevy_sprite::mesh2d_view_bindings
+
+struct ShadertoyMaterial {
+    uv_scale: vec2<f32>,
+    uv_offset: vec2<f32>,
+    emissive_intensity: f32,
+    perceptual_roughness: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: ShadertoyMaterial;
+@group(1) @binding(1)
+var canvas_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var canvas_sampler: sampler;
+
+struct FragmentInput {
+    #import bevy_sprite::mesh2d_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let uv = in.uv * material.uv_scale + material.uv_offset;
+    let color = textureSample(canvas_texture, canvas_sampler, uv);
+    return vec4<f32>(color.rgb * material.emissive_intensity, color.a);
+}
@@ -0,0 +1,14 @@
+// Writes the main image to the view target, stretched to the size of the view
+
+#import bevy_core_pipeline::fullscreen_vertex_shader
+
+@group(0) @binding(0)
+var main_image: texture_2d<f32>;
+
+@fragment
+fn blit(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(main_image));
+    let location = vec2<i32>(min(in.uv * size, size - 1.0));
+
+    return textureLoad(main_image, location, 0);
+}
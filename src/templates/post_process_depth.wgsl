@@ -0,0 +1,29 @@
+// Copies the depth of a view into the red channel of a buffer, stretched to the size of
+// the buffer. MULTISAMPLED is set for views rendered with msaa, which read the first sample.
+
+#ifdef MULTISAMPLED
+@group(0) @binding(0)
+var depth_texture: texture_depth_multisampled_2d;
+#else
+@group(0) @binding(0)
+var depth_texture: texture_depth_2d;
+#endif
+
+@group(0) @binding(1)
+var buffer: texture_storage_2d<rgba32float, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn copy_depth(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(buffer));
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+
+    if (location.x >= size.x || location.y >= size.y) {
+        return;
+    }
+
+    let depth_size = vec2<f32>(textureDimensions(depth_texture));
+    let uv = (vec2<f32>(location) + 0.5) / vec2<f32>(size);
+    let depth = textureLoad(depth_texture, vec2<i32>(uv * depth_size), 0);
+
+    textureStore(buffer, location, vec4<f32>(depth, 0.0, 0.0, 1.0));
+}
@@ -0,0 +1,38 @@
+// Copies the content of a buffer into its resized replacement. Exactly one of the
+// CROP, CENTRE and STRETCH shader defs is set.
+
+@group(0) @binding(0)
+var old_buffer: texture_2d<f32>;
+
+@group(0) @binding(1)
+var new_buffer: texture_storage_2d<rgba32float, write>;
+
+@compute @workgroup_size(8, 8, 1)
+fn resample(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let old_size = vec2<i32>(textureDimensions(old_buffer));
+    let new_size = vec2<i32>(textureDimensions(new_buffer));
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+
+    if (location.x >= new_size.x || location.y >= new_size.y) {
+        return;
+    }
+
+#ifdef CROP
+    let source = location;
+#endif
+#ifdef CENTRE
+    let source = location + (old_size - new_size) / 2;
+#endif
+#ifdef STRETCH
+    // nearest neighbour, since rgba32float textures are not filterable
+    let scaled = (vec2<f32>(location) + 0.5) * vec2<f32>(old_size) / vec2<f32>(new_size);
+    let source = min(vec2<i32>(scaled), old_size - 1);
+#endif
+
+    var color = vec4<f32>(0.0);
+    if (source.x >= 0 && source.y >= 0 && source.x < old_size.x && source.y < old_size.y) {
+        color = textureLoad(old_buffer, source, 0);
+    }
+
+    textureStore(new_buffer, location, color);
+}
@@ -0,0 +1,15 @@
+{{DRAW_PRELUDE}}
+
+{{COMMON}}
+
+{{CODE_BLOCK}}
+
+@vertex
+fn vertex(@builtin(vertex_index) vertex_index: u32) -> DrawVertex {
+    return mainVertex(f32(vertex_index), f32(draw_counts.vertexCount));
+}
+
+@fragment
+fn fragment(draw_vertex: DrawVertex) -> @location(0) vec4<f32> {
+    return draw_vertex.color;
+}
@@ -9,14 +9,13 @@ use bevy::{
     },
 };
 
-use std::borrow::Cow;
-
 use crate::texture_a::TextureA;
 use crate::texture_b::TextureB;
 use crate::texture_d::TextureD;
 use crate::{
-    CommonUniform, CommonUniformMeta, ShaderHandles, ShadertoyCanvas, ShadertoyPipelines,
-    ShadertoyState, WORKGROUP_SIZE,
+    make_buffer_bind_group_entries, queue_pass_pipelines, BindGroupKey, CommonUniformMeta,
+    PassRunState, PipelineKey, ShaderHandles, ShadertoyCanvas, ShadertoyPipelines,
+    ShadertoyRebuildCounters, ShadertoyState, ShadertoyStorageBuffers,
 };
 
 #[derive(Resource)] 
@@ -24,6 +23,8 @@ struct TextureCBindGroup {
     texture_c_bind_group: BindGroup,
     init_pipeline: CachedComputePipelineId,
     update_pipeline: CachedComputePipelineId,
+    pipeline_key: PipelineKey,
+    bind_group_key: BindGroupKey,
 }
 
 #[derive(Clone, Deref, Resource, ExtractResource)]
@@ -113,6 +114,7 @@ pub fn queue_bind_group_c(
     mut commands: Commands,
     pipeline: Res<ShadertoyPipelines>,
     gpu_images: Res<RenderAssets<Image>>,
+    cached: Option<Res<TextureCBindGroup>>,
     texture_a_image: Res<TextureA>,
     texture_b_image: Res<TextureB>,
     texture_c_image: Res<TextureC>,
@@ -121,70 +123,93 @@ pub fn queue_bind_group_c(
     mut pipeline_cache: ResMut<PipelineCache>,
     all_shader_handles: Res<ShaderHandles>,
     common_uniform_meta: ResMut<CommonUniformMeta>,
+    rebuild_counters: Res<ShadertoyRebuildCounters>,
+    storage_buffers: Res<ShadertoyStorageBuffers>,
 ) {
-    let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-        label: None,
-        layout: Some(vec![pipeline.abcd_group_layout.clone()]),
-        shader: all_shader_handles.texture_c_shader.clone(),
-        shader_defs: vec!["INIT".to_string()],
-        entry_point: Cow::from("update"),
-    });
-
-    let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-        label: None,
-        layout: Some(vec![pipeline.abcd_group_layout.clone()]),
-        shader: all_shader_handles.texture_c_shader.clone(),
-        shader_defs: vec![],
-        entry_point: Cow::from("update"),
-    });
+    let layout = &pipeline.buffer_group_layouts[2];
 
     let texture_a_view = &gpu_images[&texture_a_image.0];
     let texture_b_view = &gpu_images[&texture_b_image.0];
     let texture_c_view = &gpu_images[&texture_c_image.0];
     let texture_d_view = &gpu_images[&texture_d_image.0];
 
-    let texture_c_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-        label: Some("bind_group_c"),
-        layout: &pipeline.abcd_group_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: common_uniform_meta.buffer.as_entire_binding(),
-            },
-            BindGroupEntry {
-                binding: 1,
-                resource: BindingResource::TextureView(&texture_a_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 2,
-                resource: BindingResource::TextureView(&texture_b_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 3,
-                resource: BindingResource::TextureView(&texture_c_view.texture_view),
-            },
-            BindGroupEntry {
-                binding: 4,
-                resource: BindingResource::TextureView(&texture_d_view.texture_view),
-            },
+    let pipeline_key = PipelineKey::new(&all_shader_handles.texture_c_shader, layout)
+        .with_storage(&storage_buffers.layout);
+    let bind_group_key = BindGroupKey::new(
+        layout,
+        &[
+            &texture_a_view.texture,
+            &texture_b_view.texture,
+            &texture_c_view.texture,
+            &texture_d_view.texture,
         ],
-    });
+    );
+
+    // nothing to do until the shader, the layout or one of the textures changes
+    let cached = cached.as_deref();
+    if let Some(cached) = cached {
+        if cached.pipeline_key == pipeline_key && cached.bind_group_key == bind_group_key {
+            return;
+        }
+    }
+
+    let (init_pipeline, update_pipeline) = match cached {
+        Some(cached) if cached.pipeline_key == pipeline_key => {
+            (cached.init_pipeline, cached.update_pipeline)
+        }
+        _ => {
+            rebuild_counters.count_pipeline_rebuild();
+            queue_pass_pipelines(
+                &mut pipeline_cache,
+                layout,
+                Some(&storage_buffers.layout),
+                &all_shader_handles.texture_c_shader,
+            )
+        }
+    };
+
+    let texture_c_bind_group = match cached {
+        Some(cached) if cached.bind_group_key == bind_group_key => {
+            cached.texture_c_bind_group.clone()
+        }
+        _ => {
+            rebuild_counters.count_bind_group_rebuild();
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: Some("bind_group_c"),
+                layout,
+                entries: &make_buffer_bind_group_entries(
+                    &pipeline.pass_usages.buffers[2],
+                    &common_uniform_meta.buffer,
+                    [
+                        &texture_a_view.texture_view,
+                        &texture_b_view.texture_view,
+                        &texture_c_view.texture_view,
+                        &texture_d_view.texture_view,
+                    ],
+                ),
+            })
+        }
+    };
 
     commands.insert_resource(TextureCBindGroup {
         texture_c_bind_group,
         init_pipeline,
         update_pipeline,
+        pipeline_key,
+        bind_group_key,
     });
 }
 
 pub struct TextureCNode {
     pub state: ShadertoyState,
+    pub run_state: PassRunState,
 }
 
 impl Default for TextureCNode {
     fn default() -> Self {
         Self {
             state: ShadertoyState::Loading,
+            run_state: PassRunState::default(),
         }
     }
 }
@@ -215,6 +240,9 @@ impl render_graph::Node for TextureCNode {
             }
             ShadertoyState::Update => {}
         }
+
+        self.run_state.update("buffer_c", &self.state, world);
+        self.run_state.plan_fast_forward("texture_c", world);
     }
 
     fn run(
@@ -225,6 +253,9 @@ impl render_graph::Node for TextureCNode {
     ) -> Result<(), render_graph::NodeRunError> {
         let bind_group = world.resource::<TextureCBindGroup>();
         let canvas_size = world.resource::<ShadertoyCanvas>();
+        let (workgroups_x, workgroups_y) = world
+            .resource::<ShaderHandles>()
+            .dispatch_count(2, canvas_size.width, canvas_size.height);
 
         let texture_c_bind_group = &bind_group.texture_c_bind_group;
 
@@ -233,11 +264,17 @@ impl render_graph::Node for TextureCNode {
 
         let pipeline_cache = world.resource::<PipelineCache>();
 
+        // skipped passes keep the contents of their buffer
+        if !self.run_state.dispatch {
+            return Ok(());
+        }
+
         let mut pass = render_context
             .command_encoder
             .begin_compute_pass(&ComputePassDescriptor::default());
 
         pass.set_bind_group(0, texture_c_bind_group, &[]);
+        pass.set_bind_group(1, &world.resource::<ShadertoyStorageBuffers>().bind_group, &[]);
 
         // select the pipeline based on the current state
         match self.state {
@@ -248,11 +285,7 @@ impl render_graph::Node for TextureCNode {
                     .get_compute_pipeline(init_pipeline_cache)
                     .unwrap();
                 pass.set_pipeline(init_pipeline);
-                pass.dispatch_workgroups(
-                    canvas_size.width / WORKGROUP_SIZE,
-                    canvas_size.height / WORKGROUP_SIZE,
-                    1,
-                );
+                pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
             }
 
             ShadertoyState::Update => {
@@ -260,14 +293,34 @@ impl render_graph::Node for TextureCNode {
                     .get_compute_pipeline(update_pipeline_cache)
                     .unwrap();
                 pass.set_pipeline(update_pipeline);
-                pass.dispatch_workgroups(
-                    canvas_size.width / WORKGROUP_SIZE,
-                    canvas_size.height / WORKGROUP_SIZE,
-                    1,
-                );
+                pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
             }
         }
 
         Ok(())
     }
 }
+
+// Dispatches the update shader of the pass, for the steps the FastForwardNode runs before
+// the last one. The pass is in its Update state whenever it is planned.
+pub(crate) fn dispatch_texture_c_update(world: &World, command_encoder: &mut CommandEncoder) {
+    let bind_group = world.resource::<TextureCBindGroup>();
+    let canvas_size = world.resource::<ShadertoyCanvas>();
+    let (workgroups_x, workgroups_y) = world
+        .resource::<ShaderHandles>()
+        .dispatch_count(2, canvas_size.width, canvas_size.height);
+
+    let update_pipeline = match world
+        .resource::<PipelineCache>()
+        .get_compute_pipeline(bind_group.update_pipeline)
+    {
+        Some(update_pipeline) => update_pipeline,
+        None => return,
+    };
+
+    let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+    pass.set_bind_group(0, &bind_group.texture_c_bind_group, &[]);
+    pass.set_bind_group(1, &world.resource::<ShadertoyStorageBuffers>().bind_group, &[]);
+    pass.set_pipeline(update_pipeline);
+    pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+}
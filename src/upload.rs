@@ -0,0 +1,227 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        Extract,
+    },
+};
+
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+
+use crate::capture::padded_bytes_per_row;
+use crate::texture_a::TextureA;
+use crate::texture_b::TextureB;
+use crate::texture_c::TextureC;
+use crate::texture_d::TextureD;
+use crate::{CaptureTarget, MainImage, TextureRegion};
+
+struct BufferUpload {
+    target: CaptureTarget,
+    region: Option<TextureRegion>,
+    texels: Vec<f32>,
+}
+
+// Writes texels into the buffers from game code, e.g. to stamp obstacles into a fluid or to
+// load a saved state. Uploads made during a frame are applied in that frame, after the
+// content of resized buffers has been resampled and before any pass runs, in the order
+// they were made. The main image is overwritten by the image pass, so it is of little use
+// as a target. It can be cloned and written from any thread.
+#[derive(Clone, Resource, Default)]
+pub struct ShadertoyUploads(Arc<Mutex<Vec<BufferUpload>>>);
+
+impl ShadertoyUploads {
+    // the texels are linear rgba floats, row by row, 4 * region.size.x * region.size.y values
+    pub fn write(&self, target: CaptureTarget, region: TextureRegion, texels: Vec<f32>) {
+        self.push(target, Some(region), texels);
+    }
+
+    // replaces the whole content of the buffer, which must have the size of the canvas
+    pub fn write_all(&self, target: CaptureTarget, texels: Vec<f32>) {
+        self.push(target, None, texels);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn queued(&self) -> Vec<(CaptureTarget, Vec<f32>)> {
+        let uploads = self.0.lock().unwrap();
+        uploads
+            .iter()
+            .map(|upload| (upload.target, upload.texels.clone()))
+            .collect()
+    }
+
+    fn push(&self, target: CaptureTarget, region: Option<TextureRegion>, texels: Vec<f32>) {
+        self.0.lock().unwrap().push(BufferUpload {
+            target,
+            region,
+            texels,
+        });
+    }
+}
+
+struct ExtractedUpload {
+    upload: BufferUpload,
+    image: Handle<Image>,
+}
+
+#[derive(Resource, Default)]
+pub struct ExtractedUploads(Vec<ExtractedUpload>);
+
+pub fn extract_uploads(
+    mut commands: Commands,
+    uploads: Extract<Res<ShadertoyUploads>>,
+    main_image: Extract<Res<MainImage>>,
+    texture_a: Extract<Res<TextureA>>,
+    texture_b: Extract<Res<TextureB>>,
+    texture_c: Extract<Res<TextureC>>,
+    texture_d: Extract<Res<TextureD>>,
+) {
+    let buffers = [&texture_a.0, &texture_b.0, &texture_c.0, &texture_d.0];
+    let uploads = uploads
+        .0
+        .lock()
+        .unwrap()
+        .drain(..)
+        // the main image is not rgba32float on the web, see MAIN_IMAGE_FORMAT
+        .filter(|upload| !cfg!(feature = "wasm") || upload.target != CaptureTarget::MainImage)
+        .map(|upload| ExtractedUpload {
+            image: upload.target.image(&main_image.0, buffers).clone(),
+            upload,
+        })
+        .collect();
+
+    commands.insert_resource(ExtractedUploads(uploads));
+}
+
+struct PreparedUpload {
+    image: Handle<Image>,
+    region: TextureRegion,
+    buffer: Buffer,
+    padded_bytes_per_row: u32,
+}
+
+#[derive(Resource, Default)]
+pub struct PreparedUploads(Vec<PreparedUpload>);
+
+// Runs after the images have been prepared, so that uploads go to the textures that were
+// created for resized or reset buffers in this frame
+pub fn prepare_uploads(
+    mut uploads: ResMut<ExtractedUploads>,
+    mut prepared: ResMut<PreparedUploads>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+) {
+    prepared.0.clear();
+
+    for ExtractedUpload { upload, image } in uploads.0.drain(..) {
+        let texture_size = match gpu_images.get(&image) {
+            Some(gpu_image) => gpu_image.size.as_uvec2(),
+            None => {
+                warn!("{} is not on the gpu yet, upload skipped", upload.target.name());
+                continue;
+            }
+        };
+
+        let texture_region = TextureRegion {
+            origin: UVec2::ZERO,
+            size: texture_size,
+        };
+        let region = upload.region.unwrap_or(texture_region);
+
+        if !texture_region.contains(&region) {
+            error!(
+                "upload to {} is outside of the texture: {:?}",
+                upload.target.name(),
+                region
+            );
+            continue;
+        }
+
+        let row_values = 4 * region.size.x as usize;
+        if upload.texels.len() != row_values * region.size.y as usize {
+            error!(
+                "upload to {} has {} values instead of {}",
+                upload.target.name(),
+                upload.texels.len(),
+                row_values * region.size.y as usize
+            );
+            continue;
+        }
+
+        if row_values == 0 || region.size.y == 0 {
+            continue;
+        }
+
+        let padded_bytes_per_row = padded_bytes_per_row(region.size.x);
+        let mut contents = vec![0u8; (padded_bytes_per_row * region.size.y) as usize];
+        for (padded_row, row) in contents
+            .chunks_mut(padded_bytes_per_row as usize)
+            .zip(upload.texels.chunks(row_values))
+        {
+            let row: &[u8] = bytemuck::cast_slice(row);
+            padded_row[..row.len()].copy_from_slice(row);
+        }
+
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("upload_buffer"),
+            contents: &contents,
+            usage: BufferUsages::COPY_SRC,
+        });
+
+        prepared.0.push(PreparedUpload {
+            image,
+            region,
+            buffer,
+            padded_bytes_per_row,
+        });
+    }
+}
+
+// copies the uploads into the buffers after the resample node and before every pass
+pub struct UploadNode;
+
+impl render_graph::Node for UploadNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+
+        for upload in world.resource::<PreparedUploads>().0.iter() {
+            if let Some(gpu_image) = gpu_images.get(&upload.image) {
+                render_context.command_encoder.copy_buffer_to_texture(
+                    ImageCopyBuffer {
+                        buffer: &upload.buffer,
+                        layout: ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: NonZeroU32::new(upload.padded_bytes_per_row),
+                            rows_per_image: None,
+                        },
+                    },
+                    ImageCopyTexture {
+                        texture: &gpu_image.texture,
+                        mip_level: 0,
+                        origin: Origin3d {
+                            x: upload.region.origin.x,
+                            y: upload.region.origin.y,
+                            z: 0,
+                        },
+                        aspect: TextureAspect::All,
+                    },
+                    Extent3d {
+                        width: upload.region.size.x,
+                        height: upload.region.size.y,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+use bevy::prelude::*;
+
+use crate::{blank_comments, parse_variants, PassDispatch, WORKGROUP_SIZE};
+
+// Size of the compute workgroups of a pass. It is injected into the @workgroup_size
+// attribute of the "update" entry point when the shader is assembled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WorkgroupSize {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl WorkgroupSize {
+    pub const SIZE_8X8: WorkgroupSize = WorkgroupSize { x: 8, y: 8 };
+    pub const SIZE_16X16: WorkgroupSize = WorkgroupSize { x: 16, y: 16 };
+    pub const SIZE_32X1: WorkgroupSize = WorkgroupSize { x: 32, y: 1 };
+
+    // max_compute_invocations_per_workgroup of the default limits of wgpu
+    pub const MAX_INVOCATIONS: u32 = 256;
+    // max_compute_workgroups_per_dimension of the default limits of wgpu
+    pub const MAX_WORKGROUPS: u32 = 65535;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.x == 0 || self.y == 0 {
+            return Err(format!("the workgroup size {}x{} is empty", self.x, self.y));
+        }
+        if self.x.saturating_mul(self.y) > Self::MAX_INVOCATIONS {
+            return Err(format!(
+                "the workgroup size {}x{} has more than {} invocations",
+                self.x,
+                self.y,
+                Self::MAX_INVOCATIONS
+            ));
+        }
+        Ok(())
+    }
+
+    // number of workgroups needed to cover the whole canvas, rounded up
+    pub fn dispatch_count(&self, width: u32, height: u32) -> (u32, u32) {
+        (
+            (width + self.x - 1) / self.x,
+            (height + self.y - 1) / self.y,
+        )
+    }
+
+    pub fn attribute(&self) -> String {
+        format!("@workgroup_size({}, {}, 1)", self.x, self.y)
+    }
+}
+
+impl Default for WorkgroupSize {
+    fn default() -> Self {
+        WorkgroupSize {
+            x: WORKGROUP_SIZE,
+            y: WORKGROUP_SIZE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PassWorkgroupSizes {
+    pub image: WorkgroupSize,
+    pub buffer_a: WorkgroupSize,
+    pub buffer_b: WorkgroupSize,
+    pub buffer_c: WorkgroupSize,
+    pub buffer_d: WorkgroupSize,
+}
+
+impl PassWorkgroupSizes {
+    pub fn get(&self, pass_name: &str) -> WorkgroupSize {
+        match pass_name {
+            "buffer_a" => self.buffer_a,
+            "buffer_b" => self.buffer_b,
+            "buffer_c" => self.buffer_c,
+            "buffer_d" => self.buffer_d,
+            _ => self.image,
+        }
+    }
+
+    // the sizes the device cannot run are replaced with the default one
+    pub fn validated(self) -> Self {
+        let validated = |pass_name: &str, size: WorkgroupSize| match size.validate() {
+            Ok(()) => size,
+            Err(e) => {
+                error!("{} of {}, the default size is used", e, pass_name);
+                WorkgroupSize::default()
+            }
+        };
+
+        Self {
+            image: validated("image", self.image),
+            buffer_a: validated("buffer_a", self.buffer_a),
+            buffer_b: validated("buffer_b", self.buffer_b),
+            buffer_c: validated("buffer_c", self.buffer_c),
+            buffer_d: validated("buffer_d", self.buffer_d),
+        }
+    }
+}
+
+// Sets the workgroup size of the "update" entry point of an assembled shader and makes the
+// invocations that fall outside of the canvas, or of the storage buffer, return early, since
+// the dispatch is rounded up to whole workgroups. The same text in a comment is left
+// alone. Shaders that do not parse are returned as is, the error is left to the pipeline
+// cache.
+pub fn prepare_entry_point(
+    source: &str,
+    workgroup_size: WorkgroupSize,
+    dispatch: PassDispatch,
+) -> String {
+    let workgroup_size = dispatch.workgroup_size(workgroup_size);
+    let mut source = source.to_string();
+
+    // edited from the last one, so that the positions of the others still hold
+    let mut entry_points = find_entry_points(&source);
+    entry_points.reverse();
+
+    for (start, invocation_id) in entry_points {
+        if let (Some(invocation_id), Some(body_start)) = (invocation_id, source[start..].find('{'))
+        {
+            let bounds_check = dispatch.bounds_check(&invocation_id);
+            source.insert_str(start + body_start + 1, &bounds_check);
+        }
+
+        // the attribute is replaced if it is among the ones right before the function,
+        // and added otherwise
+        let attribute = "@workgroup_size(";
+        let attribute_range = source[..start]
+            .rfind(attribute)
+            .and_then(|attribute_start| {
+                let attribute_end = attribute_start + source[attribute_start..].find(')')? + 1;
+                source[attribute_end..start]
+                    .split_whitespace()
+                    .all(|other| other == "@compute")
+                    .then_some(attribute_start..attribute_end)
+            });
+        match attribute_range {
+            Some(range) => source.replace_range(range, &workgroup_size.attribute()),
+            None => source.insert_str(start, &format!("{} ", workgroup_size.attribute())),
+        }
+    }
+
+    source
+}
+
+// The positions of the declarations of the "update" entry point, with the name of their
+// global_invocation_id argument. The shader is parsed once to check that "update" is an
+// entry point, it may be declared once for INIT and once for the other frames.
+fn find_entry_points(source: &str) -> Vec<(usize, Option<String>)> {
+    let declaration = "fn update(";
+
+    let is_entry_point = parse_variants(source).map_or(false, |modules| {
+        modules.iter().any(|module| {
+            module
+                .entry_points
+                .iter()
+                .any(|entry_point| entry_point.name == "update")
+        })
+    });
+    if !is_entry_point {
+        return Vec::new();
+    }
+
+    let uncommented = blank_comments(source);
+    uncommented
+        .match_indices(declaration)
+        .map(|(start, _)| {
+            let arguments = &uncommented[start + declaration.len()..];
+            (start, invocation_id(arguments))
+        })
+        .collect()
+}
+
+// the name of the global_invocation_id argument, from the arguments of a declaration
+fn invocation_id(arguments: &str) -> Option<String> {
+    let builtin = "@builtin(global_invocation_id)";
+    let arguments: String = arguments[..arguments.find('{')?]
+        .split_whitespace()
+        .collect();
+    let name: String = arguments[arguments.find(builtin)? + builtin.len()..]
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+
+    (!name.is_empty()).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = "
+// @compute @workgroup_size(8, 8, 1)
+// fn update(@builtin(global_invocation_id) id: vec3<u32>) {
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let x = invocation_id.x;
+}
+";
+
+    #[test]
+    fn dispatch_rounds_up() {
+        assert_eq!(WorkgroupSize::SIZE_8X8.dispatch_count(960, 600), (120, 75));
+        assert_eq!(WorkgroupSize::SIZE_8X8.dispatch_count(961, 601), (121, 76));
+        assert_eq!(WorkgroupSize::SIZE_32X1.dispatch_count(33, 1), (2, 1));
+    }
+
+    #[test]
+    fn prepares_the_entry_point_only() {
+        let prepared = prepare_entry_point(SHADER, WorkgroupSize::SIZE_16X16, PassDispatch::Pixels);
+
+        assert!(prepared.starts_with(
+            "
+// @compute @workgroup_size(8, 8, 1)
+// fn update(@builtin(global_invocation_id) id: vec3<u32>) {
+@compute @workgroup_size(16, 16, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (invocation_id.x >= u32(uni.iResolution.x)"
+        ));
+    }
+
+    #[test]
+    fn adds_a_missing_attribute() {
+        let shader = "@compute\nfn update(@builtin(global_invocation_id) id: vec3<u32>) {}\n";
+        let prepared =
+            prepare_entry_point(shader, WorkgroupSize::SIZE_8X8, PassDispatch::Elements(100));
+
+        assert_eq!(
+            prepared,
+            "@compute\n@workgroup_size(64, 1, 1) fn update(@builtin(global_invocation_id) id: vec3<u32>) {\n    if (id.x >= 100u) {\n        return;\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn prepares_every_variant() {
+        let shader = "
+#ifdef INIT
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) a: vec3<u32>) {}
+#else
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) b: vec3<u32>) {}
+#endif
+";
+        let prepared = prepare_entry_point(shader, WorkgroupSize::SIZE_32X1, PassDispatch::Pixels);
+
+        assert_eq!(prepared.matches("@workgroup_size(32, 1, 1)").count(), 2);
+        assert!(prepared.contains("if (a.x >="));
+        assert!(prepared.contains("if (b.x >="));
+    }
+
+    #[test]
+    fn finds_the_invocation_id_across_lines() {
+        let shader = "@compute @workgroup_size(8, 8, 1)\nfn update(\n    @builtin( global_invocation_id )\n    id: vec3<u32>,\n) {}\n";
+        assert_eq!(
+            find_entry_points(shader),
+            vec![(34, Some("id".to_string()))]
+        );
+    }
+
+    #[test]
+    fn rejects_sizes_over_the_limits() {
+        assert!(WorkgroupSize::SIZE_16X16.validate().is_ok());
+        assert!(WorkgroupSize { x: 32, y: 16 }.validate().is_err());
+        assert!(WorkgroupSize { x: 0, y: 8 }.validate().is_err());
+
+        let sizes = PassWorkgroupSizes {
+            buffer_b: WorkgroupSize { x: 64, y: 64 },
+            image: WorkgroupSize::SIZE_32X1,
+            ..Default::default()
+        }
+        .validated();
+        assert_eq!(sizes.buffer_b, WorkgroupSize::default());
+        assert_eq!(sizes.image, WorkgroupSize::SIZE_32X1);
+    }
+
+    #[test]
+    fn leaves_shaders_that_do_not_parse() {
+        let shader = "fn update( {";
+        assert_eq!(
+            prepare_entry_point(shader, WorkgroupSize::SIZE_16X16, PassDispatch::Pixels),
+            shader
+        );
+    }
+}